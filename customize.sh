@@ -3,8 +3,6 @@
 # 重构版：云端配置同步 + 二进制归一化 + 软链化
 
 WORKSPACE="/data/adb/sing-box-workspace"
-CONFIG_URL="https://miceworld.top/sing-box-config-templates/mobile/config.template.json"
-ENV_URL="https://miceworld.top/sing-box-config-templates/mobile/.env.example"
 
 ui_print "============================================"
 ui_print "   Mice Sing-box KSU Module Installer      "
@@ -73,35 +71,49 @@ ln -sf "$WORKSPACE/bin/sing-box" "$MODPATH/system/bin/sing-box" && ui_print "
 
 ui_print "    ✅ 软链接建立完成"
 
+# 固定模板签名公钥 (sbc update 据此校验云端模板)
+if [ -f "$MODPATH/etc/template.pub" ]; then
+    cp -f "$MODPATH/etc/template.pub" "$WORKSPACE/etc/template.pub"
+    chmod 644 "$WORKSPACE/etc/template.pub"
+    ui_print "    🔏 模板签名公钥已固定"
+fi
+
+# 设置文件 (下载地址、路径、守护参数)：仅首次安装时写入，保留用户修改
+if [ -f "$MODPATH/etc/sbc.toml" ] && [ ! -f "$WORKSPACE/etc/sbc.toml" ]; then
+    cp -f "$MODPATH/etc/sbc.toml" "$WORKSPACE/etc/sbc.toml"
+    chmod 644 "$WORKSPACE/etc/sbc.toml"
+    ui_print "    ⚙️  已写入默认设置 etc/sbc.toml"
+fi
+
 # ============================================
 # Step 5: 云端下载配置文件
 # ============================================
 ui_print ""
 ui_print ">>> Step 5: 正在同步云端配置..."
 
-TIMESTAMP=$(date +%s)
-DOWNLOAD_URL="${CONFIG_URL}?t=${TIMESTAMP}"
-
 # 优先使用压缩包内自带的 config.template.json (本地测试模式)
+# 与在线更新走同一条 sbc-rs update 流程 (试渲染 + 原子替换)；随模块刷入的文件视为可信，无需签名
 if [ -f "$MODPATH/config.template.json" ]; then
-    cp -f "$MODPATH/config.template.json" "$WORKSPACE/config.template.json"
-    chmod 644 "$WORKSPACE/config.template.json"
-    ui_print "    ✅ 已使用本地配置模板 (Local Override)"
+    LOCAL_ENV_OPT=""
+    [ -f "$MODPATH/.env.example" ] && LOCAL_ENV_OPT="--env-url $MODPATH/.env.example"
+    "$WORKSPACE/bin/sbc-rs" --workspace "$WORKSPACE" update \
+        --template-url "$MODPATH/config.template.json" \
+        $LOCAL_ENV_OPT \
+        --allow-unsigned >/dev/null 2>&1
+    case $? in
+        0|3) ui_print "    ✅ 已使用本地配置模板 (Local Override)" ;;
+        *) ui_print "    ⚠️  本地配置模板未通过校验，将保留现有配置" ;;
+    esac
 else
-    # 使用 curl -k 忽略证书问题，设置超时 10 秒
-    if curl -kfsSL --connect-timeout 10 --max-time 30 "$DOWNLOAD_URL" -o "$WORKSPACE/config.template.json" 2>/dev/null; then
-        # 简单校验
-        if grep -q "inbounds" "$WORKSPACE/config.template.json" 2>/dev/null; then
-            chmod 644 "$WORKSPACE/config.template.json"
-            ui_print "    ✅ 配置同步成功"
-        else
-            ui_print "    ⚠️  下载的配置无效，将保留现有配置"
-            rm -f "$WORKSPACE/config.template.json"
-        fi
-    else
-        ui_print "    ⚠️  网络连接失败或下载超时"
-        ui_print "    💡 提示: 请确保网络通畅后运行 'sbc update' 手动同步"
-    fi
+    # 下载地址取自 etc/sbc.toml；模板须通过 etc/template.pub 的签名校验与试渲染才会替换
+    "$WORKSPACE/bin/sbc-rs" --workspace "$WORKSPACE" update >/dev/null 2>&1
+    case $? in
+        0|3) ui_print "    ✅ 配置同步成功 (签名已校验)" ;;
+        *)
+            ui_print "    ⚠️  下载失败或签名校验未通过，将保留现有配置"
+            ui_print "    💡 提示: 请确保网络通畅后运行 'sbc update' 手动同步"
+            ;;
+    esac
 fi
 
 # ============================================
@@ -116,7 +128,9 @@ if [ ! -f "$WORKSPACE/.env" ]; then
         cp -f "$MODPATH/.env.example" "$WORKSPACE/.env"
         chmod 600 "$WORKSPACE/.env"
         ui_print "    ✅ 已使用本地 .env.example 模板 (Local Override)"
-    elif curl -kfsSL --connect-timeout 10 --max-time 30 "${ENV_URL}?t=${TIMESTAMP}" -o "$WORKSPACE/.env" 2>/dev/null; then
+    elif [ -f "$WORKSPACE/.env.example" ]; then
+        # Step 5 中由 sbc-rs update 随模板一同下载
+        cp -f "$WORKSPACE/.env.example" "$WORKSPACE/.env"
         chmod 600 "$WORKSPACE/.env"
         ui_print "    ✅ .env 模板已从云端拉取"
     else
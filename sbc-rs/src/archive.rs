@@ -0,0 +1,90 @@
+// 模板包解包 (.zip / .tar / .tar.gz)，按内容的文件头识别格式
+use anyhow::{Context, Result, bail};
+use flate2::read::GzDecoder;
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read};
+use std::path::{Component, Path};
+
+/// 解包后的总大小上限，防止压缩炸弹
+const MAX_UNPACKED: u64 = 64 * 1024 * 1024;
+
+/// 按扩展名判断地址或路径是否指向模板包 (忽略查询参数)
+pub fn is_archive(source: &str) -> bool {
+    let path = source.split(['?', '#']).next().unwrap_or_default().to_ascii_lowercase();
+    [".zip", ".tar", ".tar.gz", ".tgz"].iter().any(|ext| path.ends_with(ext))
+}
+
+/// 解包全部普通文件，返回 "相对路径 -> 内容"；含绝对路径或 `..` 的条目直接拒绝
+pub fn unpack(data: &[u8]) -> Result<BTreeMap<String, Vec<u8>>> {
+    if data.starts_with(b"PK\x03\x04") {
+        unpack_zip(data)
+    } else if data.starts_with(&[0x1f, 0x8b]) {
+        unpack_tar(GzDecoder::new(data))
+    } else if data.len() > 262 && &data[257..262] == b"ustar" {
+        unpack_tar(data)
+    } else {
+        bail!("无法识别的模板包格式 (支持 zip / tar / tar.gz)")
+    }
+}
+
+/// 按文件名查找包内文件，多个同名时取层级最浅的一个 (兼容带顶层目录的打包方式)
+pub fn find<'a>(files: &'a BTreeMap<String, Vec<u8>>, name: &str) -> Option<&'a [u8]> {
+    files.iter()
+        .filter(|(path, _)| Path::new(path).file_name().is_some_and(|n| n == name))
+        .min_by_key(|(path, _)| path.matches('/').count())
+        .map(|(_, data)| data.as_slice())
+}
+
+fn checked_path(name: &str) -> Result<String> {
+    let path = Path::new(name);
+    if path.components().any(|c| !matches!(c, Component::Normal(_) | Component::CurDir)) {
+        bail!("模板包中包含不安全的路径: {}", name);
+    }
+    Ok(path.components()
+        .filter_map(|c| match c {
+            Component::Normal(p) => Some(p.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/"))
+}
+
+fn read_limited(reader: impl Read, total: &mut u64) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(MAX_UNPACKED - *total + 1).read_to_end(&mut data)?;
+    *total += data.len() as u64;
+    if *total > MAX_UNPACKED {
+        bail!("模板包解包后超过 {} 字节", MAX_UNPACKED);
+    }
+    Ok(data)
+}
+
+fn unpack_zip(data: &[u8]) -> Result<BTreeMap<String, Vec<u8>>> {
+    let mut zip = zip::ZipArchive::new(Cursor::new(data)).context("zip 模板包损坏")?;
+    let mut files = BTreeMap::new();
+    let mut total = 0;
+    for i in 0..zip.len() {
+        let entry = zip.by_index(i)?;
+        if !entry.is_file() {
+            continue;
+        }
+        let path = checked_path(entry.name())?;
+        files.insert(path, read_limited(entry, &mut total)?);
+    }
+    Ok(files)
+}
+
+fn unpack_tar(reader: impl Read) -> Result<BTreeMap<String, Vec<u8>>> {
+    let mut tar = tar::Archive::new(reader);
+    let mut files = BTreeMap::new();
+    let mut total = 0;
+    for entry in tar.entries().context("tar 模板包损坏")? {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = checked_path(&entry.path()?.to_string_lossy())?;
+        files.insert(path, read_limited(entry, &mut total)?);
+    }
+    Ok(files)
+}
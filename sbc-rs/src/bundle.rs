@@ -0,0 +1,63 @@
+// 模板包清单 (bundle.json)：列出包内全部文件及其 SHA-256，以及所需的最低版本
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use crate::checksum;
+
+/// 清单文件名，位于包的根目录 (或唯一的顶层目录中)
+pub const MANIFEST_FILE: &str = "bundle.json";
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// 包版本，仅用于展示与目录命名
+    pub version: String,
+    /// 所需的最低 sbc-rs 构建时间 (RFC 3339 或其前缀，如 "2026-01-14")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_sbc_rs: Option<String>,
+    /// 所需的最低 sing-box 版本 (如 "1.12.0")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_sing_box: Option<String>,
+    /// 相对路径 -> SHA-256
+    pub files: BTreeMap<String, String>,
+}
+
+impl Manifest {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        serde_json::from_slice(data).context("模板包清单 bundle.json 格式无效")
+    }
+
+    /// 包内文件须与清单完全一致：逐个比对哈希，清单外的文件 (除清单及其签名) 一律拒绝
+    pub fn verify(&self, files: &BTreeMap<String, Vec<u8>>) -> Result<()> {
+        for (path, expected) in &self.files {
+            let data = files.get(path).with_context(|| format!("模板包缺少清单中的文件: {}", path))?;
+            let actual = checksum::sha256_hex(data);
+            if !actual.eq_ignore_ascii_case(expected) {
+                bail!("模板包文件 {} 哈希不匹配: 清单为 {}，实际为 {}", path, expected, actual);
+            }
+        }
+        let signature = format!("{}.minisig", MANIFEST_FILE);
+        if let Some(extra) = files.keys().find(|p| !self.files.contains_key(*p) && **p != MANIFEST_FILE && **p != signature) {
+            bail!("模板包中包含清单未列出的文件: {}", extra);
+        }
+        Ok(())
+    }
+}
+
+/// 比较点分数字版本 (忽略 `-` / `+` 之后的预发布与构建后缀)，actual >= min 时返回 true
+pub fn version_at_least(actual: &str, min: &str) -> bool {
+    let parse = |v: &str| -> Vec<u64> {
+        v.trim_start_matches('v')
+            .split(['-', '+'])
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .map(|n| n.parse().unwrap_or(0))
+            .collect()
+    };
+    let (mut a, mut m) = (parse(actual), parse(min));
+    let len = a.len().max(m.len());
+    a.resize(len, 0);
+    m.resize(len, 0);
+    a >= m
+}
@@ -0,0 +1,7 @@
+// 内容摘要 (历史版本、下载校验共用)
+use sha2::{Digest, Sha256};
+
+/// 计算 SHA-256，返回小写十六进制字符串
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -1,4 +1,4 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use std::path::PathBuf;
 use crate::build;
 
@@ -11,6 +11,14 @@ use crate::build;
     long_about = None
 )]
 pub struct Cli {
+    /// 关闭日志与错误信息中的机密脱敏 (仅用于本地调试)
+    #[arg(long, global = true)]
+    pub no_redact: bool,
+
+    /// 工作空间路径 (默认取环境变量 WORKSPACE，否则为 /data/adb/sing-box-workspace)
+    #[arg(long, global = true)]
+    pub workspace: Option<PathBuf>,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -19,46 +27,389 @@ pub struct Cli {
 pub enum Commands {
     /// 从模板渲染配置
     Render {
-        /// 配置模板文件的路径
+        /// 配置模板文件的路径 (`-` 表示标准输入，默认为设置中的 paths.template)
         #[arg(short, long)]
-        template: PathBuf,
+        template: Option<PathBuf>,
 
-        /// 输出配置文件的路径
+        /// 输出配置文件的路径 (`-` 表示标准输出，默认为设置中的 paths.config)
         #[arg(short, long)]
-        output: PathBuf,
-    },
-    /// 从远程 URL 更新模板
-    Update {
-        /// 配置模板的 URL/路径
-        #[arg(short = 'u', long)]
-        template_url: String,
+        output: Option<PathBuf>,
 
-        /// 保存配置模板的本地路径
-        #[arg(short = 't', long)]
-        template_path: PathBuf,
+        /// 写入前使用 `sing-box check` 预检渲染结果
+        #[arg(long)]
+        check: bool,
+
+        /// 以紧凑单行格式输出
+        #[arg(long, conflicts_with = "indent")]
+        compact: bool,
+
+        /// 缩进空格数 (默认 2)
+        #[arg(long)]
+        indent: Option<usize>,
 
-        /// 环境示例文件的 URL/路径 (可选)
+        /// 按键名排序输出
         #[arg(long)]
-        env_url: Option<String>,
+        sort_keys: bool,
 
-        /// 保存环境示例文件的本地路径 (可选)
+        /// 存在未设置的变量时渲染失败，而不是告警后继续
         #[arg(long)]
-        env_path: Option<PathBuf>,
+        strict: bool,
+
+        /// 紧凑输出，且内容未变化时跳过写入 (减少闪存写入)
+        #[arg(long, conflicts_with_all = ["compact", "indent"])]
+        minify: bool,
+
+        /// 渲染后执行的 Rhai 脚本 (可选)
+        #[arg(long)]
+        script: Option<PathBuf>,
     },
+    /// 从远程 URL 更新模板
+    Update(UpdateArgs),
+    /// 按 update.json 更新 sbc-rs 与 sing-box 二进制
+    SelfUpdate(SelfUpdateArgs),
     /// 以后台监控模式运行 sing-box
     Run {
-        /// 要使用的配置文件路径
+        /// 要使用的配置文件路径 (默认为设置中的 paths.config)
         #[arg(short, long)]
-        config: PathBuf,
+        config: Option<PathBuf>,
 
         /// 配置模板文件路径 (可选，用于自动渲染)
         #[arg(short, long)]
         template: Option<PathBuf>,
 
-        /// sing-box 的工作目录 (可选，缓存/UI 文件将放置于此)
+        /// sing-box 的工作目录 (缓存/UI 文件将放置于此，默认为设置中的 paths.working_dir)
         #[arg(short = 'D', long)]
         working_dir: Option<PathBuf>,
+
+        /// 自动渲染后使用 `sing-box check` 预检，未通过时保留现有配置
+        #[arg(long)]
+        check: bool,
+
+        /// 自动渲染时存在未设置的变量即失败 (已有配置时继续使用现有配置)
+        #[arg(long)]
+        strict: bool,
+
+        /// 渲染后执行的 Rhai 脚本 (可选，默认使用设置中的 paths.script，若存在)
+        #[arg(long)]
+        script: Option<PathBuf>,
     },
     /// 优雅地停止正在运行的监控进程
     Stop,
+    /// 列出模板中引用的全部占位符变量
+    Vars {
+        /// 配置模板文件的路径 (默认为设置中的 paths.template)
+        #[arg(short, long)]
+        template: Option<PathBuf>,
+
+        /// 额外加载的 .env 文件 (用于判断变量是否已设置，默认为设置中的 paths.env_file)
+        #[arg(long)]
+        env_file: Option<PathBuf>,
+
+        /// 以 JSON 格式输出
+        #[arg(long)]
+        json: bool,
+
+        /// 根据模板生成 .env.example 并写入指定路径
+        #[arg(long)]
+        env_example: Option<PathBuf>,
+    },
+    /// 列出已保存的模板历史版本
+    History {
+        /// 配置模板文件的路径 (默认为设置中的 paths.template)
+        #[arg(short, long)]
+        template: Option<PathBuf>,
+
+        /// 以 JSON 格式输出
+        #[arg(long)]
+        json: bool,
+    },
+    /// 显示生效的设置 (etc/sbc.toml)，或其中的单个键
+    Settings {
+        /// 设置项，如 update.template_url (可选)
+        key: Option<String>,
+
+        /// 严格检查设置文件 (未知的键、类型错误等)，有问题时以非零状态退出
+        #[arg(long, conflicts_with = "key")]
+        validate: bool,
+    },
+    /// .env 文件维护
+    Env {
+        #[command(subcommand)]
+        command: EnvCommands,
+    },
+    /// 模板发布通道
+    Channel {
+        #[command(subcommand)]
+        command: ChannelCommands,
+    },
+    /// 远程规则集的本地缓存
+    Ruleset {
+        #[command(subcommand)]
+        command: RulesetCommands,
+    },
+    /// 订阅工具
+    Sub {
+        #[command(subcommand)]
+        command: SubCommands,
+    },
+    /// 将模板回滚到某个历史版本并重新渲染
+    Rollback {
+        /// 历史版本 ID (可选，默认回滚到上一个不同的版本)
+        id: Option<String>,
+
+        /// 配置模板文件的路径 (默认为设置中的 paths.template)
+        #[arg(short, long)]
+        template: Option<PathBuf>,
+
+        /// 同时恢复的 .env.example 路径 (默认为设置中的 paths.env_example)
+        #[arg(long)]
+        env_example: Option<PathBuf>,
+
+        /// 回滚后重新渲染到此配置文件 (默认为设置中的 paths.config)
+        #[arg(short, long)]
+        config: Option<PathBuf>,
+
+        /// 重新渲染后使用 `sing-box check` 预检
+        #[arg(long)]
+        check: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum EnvCommands {
+    /// 将 .env.example 中新增的变量 (含注释与默认值) 补充到 .env，已有内容保持不变
+    Migrate {
+        /// .env.example 文件路径 (默认为设置中的 paths.env_example)
+        #[arg(long)]
+        example: Option<PathBuf>,
+
+        /// 要补充的 .env 文件路径 (默认为设置中的 paths.env_file)
+        #[arg(long)]
+        env_file: Option<PathBuf>,
+
+        /// 仅显示将追加的内容，不写入文件
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ChannelCommands {
+    /// 从服务器的通道索引列出可用通道，并标出当前通道
+    List {
+        /// 通道索引地址 (默认为设置中的 update.channel_index)
+        #[arg(long)]
+        index: Option<String>,
+
+        /// 以 JSON 格式输出
+        #[arg(long)]
+        json: bool,
+
+        #[command(flatten)]
+        fetch: FetchArgs,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum RulesetCommands {
+    /// 下载模板引用的全部远程规则集到缓存目录 (设置中的 ruleset.cache_dir)，已是最新的不重复下载
+    Sync {
+        /// 配置模板文件的路径 (默认为设置中的 paths.template)
+        #[arg(short, long)]
+        template: Option<PathBuf>,
+
+        /// 渲染模板时加载的 .env 文件 (默认为设置中的 paths.env_file)
+        #[arg(long)]
+        env_file: Option<PathBuf>,
+
+        /// 并行下载数 (默认为设置中的 ruleset.jobs)
+        #[arg(short, long)]
+        jobs: Option<usize>,
+
+        /// 以 JSON 格式输出
+        #[arg(long)]
+        json: bool,
+
+        #[command(flatten)]
+        fetch: FetchArgs,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SubCommands {
+    /// 在前台运行本地订阅网关 (`run` 在 subscription.gateway 开启时会自动在后台运行)
+    Serve {
+        /// 监听地址 (默认为设置中的 subscription.listen)
+        #[arg(long)]
+        listen: Option<String>,
+
+        #[command(flatten)]
+        fetch: FetchArgs,
+    },
+    /// 将分享链接 (可为 base64 列表) 或 Clash / mihomo YAML 订阅转换为 sing-box 出站 JSON
+    Convert {
+        /// 订阅来源：文件路径、http(s) 地址，或 `-` 表示标准输入
+        #[arg(default_value = "-")]
+        input: String,
+
+        /// 输出文件路径 (默认输出到标准输出)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// 下载订阅时使用的 User-Agent (机场据此返回对应格式)
+        #[arg(long)]
+        user_agent: Option<String>,
+
+        #[command(flatten)]
+        fetch: FetchArgs,
+    },
+    /// 检查 .env 中各订阅 (SUB_URL_n) 的可用性、节点数以及剩余流量与到期时间
+    Check {
+        /// .env 文件路径 (默认为设置中的 paths.env_file)
+        #[arg(long)]
+        env_file: Option<PathBuf>,
+
+        /// 请求订阅时使用的 User-Agent (多数机场仅对 Clash 类客户端返回流量信息)
+        #[arg(long, default_value = "clash.meta")]
+        user_agent: String,
+
+        /// 以 JSON 格式输出
+        #[arg(long)]
+        json: bool,
+
+        #[command(flatten)]
+        fetch: FetchArgs,
+    },
+}
+
+#[derive(Args)]
+pub struct UpdateArgs {
+    /// 配置模板的 URL 或本地路径 (支持 file://，以及含模板的 .zip / .tar / .tar.gz 模板包；
+    /// 可重复指定多个镜像，按顺序尝试；默认为设置中的 update.template_url)
+    #[arg(short = 'u', long)]
+    pub template_url: Vec<String>,
+
+    /// 切换到指定发布通道 (如 beta)：新模板通过校验与试渲染后才会写入设置中的 update.channel
+    #[arg(long, conflicts_with = "template_url")]
+    pub channel: Option<String>,
+
+    /// 保存配置模板的本地路径 (默认为设置中的 paths.template)
+    #[arg(short = 't', long)]
+    pub template_path: Option<PathBuf>,
+
+    /// 环境示例文件的 URL 或本地路径 (可重复指定多个镜像；默认为设置中的 update.env_example_url)
+    #[arg(long)]
+    pub env_url: Vec<String>,
+
+    /// 保存环境示例文件的本地路径 (默认为设置中的 paths.env_example)
+    #[arg(long)]
+    pub env_path: Option<PathBuf>,
+
+    /// 用于校验模板签名 (minisign) 的公钥文件 (默认为设置中的 paths.pubkey)
+    #[arg(long)]
+    pub pubkey: Option<PathBuf>,
+
+    /// 允许接受未签名或签名不匹配的模板 (不安全)
+    #[arg(long)]
+    pub allow_unsigned: bool,
+
+    /// 未固定公钥时是否跳过校验 (取自设置 update.allow_unpinned，不提供命令行参数)
+    #[arg(skip)]
+    pub allow_unpinned: bool,
+
+    /// 试渲染新模板时使用的 .env 文件 (默认为设置中的 paths.env_file)
+    #[arg(long)]
+    pub env_file: Option<PathBuf>,
+
+    /// 试渲染结果额外通过 `sing-box check` 预检
+    #[arg(long)]
+    pub check: bool,
+
+    /// 更新后将 .env.example 中新增的变量补充到 .env (--env-file)
+    #[arg(long)]
+    pub migrate_env: bool,
+
+    /// 在 etc/history 中保留的模板历史版本数 (0 表示不保留，默认为设置中的 update.keep_history)
+    #[arg(long)]
+    pub keep_history: Option<usize>,
+
+    /// 与新渲染结果比较的运行中配置 (默认为设置中的 paths.config)
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// 生效配置有变化时通过监护进程重启 sing-box (服务未运行时不启动)
+    #[arg(long)]
+    pub apply: bool,
+
+    /// 以 JSON 格式输出结果 (unchanged / changed / failed)
+    #[arg(long)]
+    pub json: bool,
+
+    #[command(flatten)]
+    pub fetch: FetchArgs,
+}
+
+#[derive(Args)]
+pub struct SelfUpdateArgs {
+    /// 更新清单地址 (默认为设置中的 self_update.manifest_url，未设置时取 module.prop 的 updateJson)
+    #[arg(long)]
+    pub manifest_url: Option<String>,
+
+    /// 仅检查并列出将替换的二进制，不做任何改动 (有新版本时退出码为 0，否则为 3)
+    #[arg(long)]
+    pub check: bool,
+
+    /// 即使清单中的 versionCode 不高于当前版本也执行更新
+    #[arg(long)]
+    pub force: bool,
+
+    /// 替换后不重启服务
+    #[arg(long)]
+    pub no_restart: bool,
+
+    #[command(flatten)]
+    pub fetch: FetchArgs,
+}
+
+/// 下载相关的通用参数 (超时与重试)
+#[derive(Args)]
+pub struct FetchArgs {
+    /// 每次尝试的连接超时 (秒)
+    #[arg(long, default_value_t = 10)]
+    pub connect_timeout: u64,
+
+    /// 每次尝试的读取超时 (秒)
+    #[arg(long, default_value_t = 30)]
+    pub read_timeout: u64,
+
+    /// 每个镜像的最大尝试次数
+    #[arg(long, default_value_t = 3)]
+    pub retries: u32,
+
+    /// 首次重试前的等待时间 (毫秒)，之后按指数增长并附加随机抖动
+    #[arg(long, default_value_t = 1000)]
+    pub retry_backoff_ms: u64,
+
+    /// 下载代理：http:// 或 socks5:// 地址，`auto` 使用运行中 sing-box 的 mixed 入站，`direct` 忽略环境变量直连。
+    /// 未指定时使用设置中的 update.proxy，仍未设置则遵循 HTTP(S)_PROXY / ALL_PROXY / NO_PROXY
+    #[arg(long)]
+    pub proxy: Option<String>,
+
+    /// `--proxy auto` 读取的已渲染配置 (默认为设置中的 paths.config)
+    #[arg(long)]
+    pub proxy_config: Option<PathBuf>,
+}
+
+// 与上面的命令行默认值一致，供不经命令行的下载 (如 run 内置的订阅网关) 使用
+impl Default for FetchArgs {
+    fn default() -> Self {
+        Self {
+            connect_timeout: 10,
+            read_timeout: 30,
+            retries: 3,
+            retry_backoff_ms: 1000,
+            proxy: None,
+            proxy_config: None,
+        }
+    }
 }
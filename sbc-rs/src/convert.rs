@@ -0,0 +1,656 @@
+// 订阅转换：分享链接 (ss / vmess / vless / trojan / hysteria2 / tuic) 与 Clash / mihomo YAML -> sing-box 出站
+// 未能转换的字段不会静默丢弃，而是逐节点给出提示
+use anyhow::{Context, Result, bail};
+use percent_encoding::percent_decode_str;
+use serde_json::{Map, Value, json};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashSet};
+use crate::subscription::{self, Format};
+
+/// 整份订阅的转换结果
+#[derive(Default)]
+pub struct Converted {
+    pub outbounds: Vec<Value>,
+    /// 逐节点的提示：忽略的字段、跳过的节点
+    pub warnings: Vec<String>,
+}
+
+impl Converted {
+    fn push(&mut self, name: &str, result: Result<(Value, Vec<String>)>, seen: &mut HashSet<String>) {
+        let (outbound, warnings) = match result {
+            Ok(converted) => converted,
+            Err(e) => {
+                self.warnings.push(format!("{}: 已跳过，{:#}", name, e));
+                return;
+            }
+        };
+        let Value::Object(fields) = outbound else { return };
+        // type 与 tag 放在最前，便于阅读
+        let field = |key: &str| fields.get(key).map(|v| v.as_str().map_or(v.to_string(), str::to_string)).unwrap_or_default();
+        let fallback = format!("{}:{}", field("server"), field("server_port"));
+        let tag = subscription::unique(Some(name).filter(|n| !n.is_empty()).unwrap_or(&fallback).to_string(), seen);
+        let mut node = Map::new();
+        node.insert("type".into(), field("type").into());
+        node.insert("tag".into(), tag.clone().into());
+        node.extend(fields.into_iter().filter(|(k, _)| k != "type"));
+        self.warnings.extend(warnings.into_iter().map(|w| format!("{}: {}", tag, w)));
+        self.outbounds.push(node.into());
+    }
+}
+
+/// 转换一份订阅 (格式自动识别)。sing-box JSON 原样取出其中的节点
+pub fn convert(body: &[u8]) -> Result<Converted> {
+    let (format, text) = subscription::detect(body)?;
+    let mut out = Converted::default();
+    let mut seen = HashSet::new();
+    match format {
+        Format::SingBox => {
+            let root: Value = serde_json::from_str(&text).context("sing-box 订阅不是有效的 JSON")?;
+            let nodes = root.get("outbounds").and_then(Value::as_array).into_iter().flatten();
+            out.outbounds = nodes.filter(|o| o.get("server").is_some()).cloned().collect();
+        }
+        Format::Links { .. } => {
+            for link in text.lines().map(str::trim).filter(|l| l.contains("://")) {
+                out.push(&subscription::link_name(link), from_link(link), &mut seen);
+            }
+        }
+        Format::Clash => {
+            let root: serde_yaml::Value = serde_yaml::from_str(&text).context("Clash 订阅不是有效的 YAML")?;
+            let proxies = root.get("proxies").and_then(serde_yaml::Value::as_sequence).context("Clash 订阅中没有 proxies 列表")?;
+            for proxy in proxies {
+                let name = proxy.get("name").and_then(serde_yaml::Value::as_str).unwrap_or_default();
+                out.push(name, from_clash(proxy), &mut seen);
+            }
+        }
+    }
+    if out.outbounds.is_empty() {
+        bail!("订阅中没有可转换的节点");
+    }
+    Ok(out)
+}
+
+// 节点字段：记录读取过的键，从未读取的即为不支持的字段。
+// 嵌套一层的映射 (如 ws-opts) 展开为 `ws-opts.path` 形式的键
+struct Fields {
+    map: Map<String, Value>,
+    used: RefCell<BTreeSet<String>>,
+}
+
+impl Fields {
+    fn new(map: Map<String, Value>) -> Self {
+        let mut flat = Map::new();
+        for (key, value) in map {
+            match value {
+                Value::Object(inner) => {
+                    for (k, v) in inner {
+                        flat.insert(format!("{}.{}", key, k), v);
+                    }
+                }
+                other => {
+                    flat.insert(key, other);
+                }
+            }
+        }
+        Self { map: flat, used: RefCell::new(BTreeSet::new()) }
+    }
+
+    // 空字符串与 null 视为未设置
+    fn get(&self, key: &str) -> Option<&Value> {
+        self.used.borrow_mut().insert(key.to_string());
+        self.map.get(key).filter(|v| !v.is_null() && v.as_str() != Some(""))
+    }
+
+    fn str(&self, key: &str) -> Option<String> {
+        match self.get(key)? {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        }
+    }
+
+    fn u64(&self, key: &str) -> Option<u64> {
+        match self.get(key)? {
+            Value::Number(n) => n.as_u64(),
+            Value::String(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    fn bool(&self, key: &str) -> bool {
+        match self.get(key) {
+            Some(Value::Bool(b)) => *b,
+            Some(Value::Number(n)) => n.as_u64() == Some(1),
+            Some(Value::String(s)) => matches!(s.as_str(), "1" | "true"),
+            _ => false,
+        }
+    }
+
+    // 数组，或以逗号分隔的字符串
+    fn list(&self, key: &str) -> Vec<String> {
+        match self.get(key) {
+            Some(Value::Array(items)) => items.iter().filter_map(|v| v.as_str().map(str::to_string)).collect(),
+            Some(Value::String(s)) => s.split(',').map(str::trim).filter(|s| !s.is_empty()).map(str::to_string).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    // 已知但对 sing-box 无意义的字段
+    fn ignore(&self, keys: &[&str]) {
+        self.used.borrow_mut().extend(keys.iter().map(|k| k.to_string()));
+    }
+
+    fn unsupported(&self) -> Vec<String> {
+        let used = self.used.borrow();
+        let keys: Vec<&str> = self.map.iter()
+            .filter(|(k, v)| !used.contains(*k) && !v.is_null() && v.as_str() != Some(""))
+            .map(|(k, _)| k.as_str())
+            .collect();
+        match keys.is_empty() {
+            true => Vec::new(),
+            false => vec![format!("忽略不支持的字段: {}", keys.join(", "))],
+        }
+    }
+}
+
+// 可选字段仅在有值时写入
+fn set(outbound: &mut Value, key: &str, value: Option<impl Into<Value>>) {
+    if let Some(value) = value {
+        outbound[key] = value.into();
+    }
+}
+
+#[derive(Default)]
+struct Tls {
+    server_name: Option<String>,
+    insecure: bool,
+    alpn: Vec<String>,
+    fingerprint: Option<String>,
+    /// (public_key, short_id)
+    reality: Option<(String, Option<String>)>,
+}
+
+impl Tls {
+    fn build(self) -> Value {
+        let mut tls = json!({ "enabled": true });
+        set(&mut tls, "server_name", self.server_name);
+        if self.insecure {
+            tls["insecure"] = true.into();
+        }
+        if !self.alpn.is_empty() {
+            tls["alpn"] = self.alpn.into();
+        }
+        // reality 客户端必须启用 uTLS
+        let fingerprint = self.fingerprint.or_else(|| self.reality.is_some().then(|| "chrome".to_string()));
+        if let Some(fingerprint) = fingerprint {
+            tls["utls"] = json!({ "enabled": true, "fingerprint": fingerprint });
+        }
+        if let Some((public_key, short_id)) = self.reality {
+            let mut reality = json!({ "enabled": true, "public_key": public_key });
+            set(&mut reality, "short_id", short_id);
+            tls["reality"] = reality;
+        }
+        tls
+    }
+}
+
+#[derive(Default)]
+struct Transport {
+    network: String,
+    host: Vec<String>,
+    path: Option<String>,
+    service_name: Option<String>,
+    headers: Map<String, Value>,
+    max_early_data: Option<u64>,
+    early_data_header: Option<String>,
+}
+
+impl Transport {
+    fn build(self) -> Result<Option<Value>> {
+        let transport = match self.network.as_str() {
+            "" | "tcp" | "none" => return Ok(None),
+            "ws" => {
+                let mut path = self.path.unwrap_or_else(|| "/".into());
+                let (mut max_early_data, mut header) = (self.max_early_data, self.early_data_header);
+                // v2rayN 风格的 early data 写在路径里: /path?ed=2048
+                if let Some((p, ed)) = path.clone().split_once("?ed=")
+                    && let Ok(n) = ed.parse()
+                {
+                    path = p.to_string();
+                    max_early_data = Some(n);
+                    header.get_or_insert_with(|| "Sec-WebSocket-Protocol".into());
+                }
+                let mut headers = self.headers;
+                if let Some(host) = self.host.into_iter().next() {
+                    headers.entry("Host").or_insert(host.into());
+                }
+                let mut ws = json!({ "type": "ws", "path": path });
+                if !headers.is_empty() {
+                    ws["headers"] = headers.into();
+                }
+                set(&mut ws, "max_early_data", max_early_data);
+                set(&mut ws, "early_data_header_name", header);
+                ws
+            }
+            "grpc" => {
+                let mut grpc = json!({ "type": "grpc" });
+                set(&mut grpc, "service_name", self.service_name);
+                grpc
+            }
+            "h2" | "http" => {
+                let mut http = json!({ "type": "http" });
+                if !self.host.is_empty() {
+                    http["host"] = self.host.into();
+                }
+                set(&mut http, "path", self.path);
+                http
+            }
+            "httpupgrade" => {
+                let mut upgrade = json!({ "type": "httpupgrade" });
+                set(&mut upgrade, "host", self.host.into_iter().next());
+                set(&mut upgrade, "path", self.path);
+                upgrade
+            }
+            other => bail!("不支持的传输方式: {}", other),
+        };
+        Ok(Some(transport))
+    }
+}
+
+/// 转换一条分享链接，返回不含 tag 的出站与提示
+pub fn from_link(link: &str) -> Result<(Value, Vec<String>)> {
+    let (scheme, _) = link.split_once("://").context("不是分享链接")?;
+    match scheme.to_ascii_lowercase().as_str() {
+        "ss" => ss_link(link),
+        "vmess" => vmess_link(link),
+        "vless" => v2ray_link("vless", link),
+        "trojan" => v2ray_link("trojan", link),
+        "hysteria2" | "hy2" => hysteria2_link(link),
+        "tuic" => tuic_link(link),
+        other => bail!("不支持的协议: {}", other),
+    }
+}
+
+// scheme://user@host:port?query#name 的各部分 (均已解码)
+struct Link {
+    user: String,
+    host: String,
+    port: u16,
+    params: Fields,
+}
+
+fn decode(s: &str) -> String {
+    percent_decode_str(s).decode_utf8_lossy().to_string()
+}
+
+fn parse_link(link: &str) -> Result<Link> {
+    let rest = link.split_once("://").map_or(link, |(_, rest)| rest);
+    let rest = rest.split('#').next().unwrap_or_default();
+    let (authority, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let authority = authority.trim_end_matches('/');
+    let (user, host_port) = authority.rsplit_once('@').unwrap_or(("", authority));
+    let (host, port) = match host_port.strip_prefix('[') {
+        Some(v6) => v6.split_once("]:").context("缺少端口")?,
+        None => host_port.rsplit_once(':').context("缺少端口")?,
+    };
+    let port = port.parse().with_context(|| format!("端口无效: {}", port))?;
+    let params = query.split('&')
+        .filter(|p| !p.is_empty())
+        .map(|pair| {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(k), Value::String(decode(v)))
+        })
+        .collect();
+    Ok(Link { user: decode(user), host: host.to_string(), port, params: Fields::new(params) })
+}
+
+fn base_outbound(kind: &str, host: &str, port: impl Into<Value>) -> Value {
+    json!({ "type": kind, "server": host, "server_port": port.into() })
+}
+
+// SIP002 (ss://base64(method:password)@host:port) 与旧格式 (ss://base64(method:password@host:port))
+fn ss_link(link: &str) -> Result<(Value, Vec<String>)> {
+    let body = link.split_once("://").map_or(link, |(_, b)| b).split('#').next().unwrap_or_default();
+    let owned;
+    let link = match body.contains('@') {
+        true => link,
+        false => {
+            let (encoded, query) = body.split_once('?').map_or((body, None), |(e, q)| (e, Some(q)));
+            let decoded = subscription::decode_base64_text(encoded).context("ss 链接不是有效的 base64")?;
+            owned = format!("ss://{}{}", decoded, query.map(|q| format!("?{}", q)).unwrap_or_default());
+            &owned
+        }
+    };
+    let l = parse_link(link)?;
+    let user = match l.user.contains(':') {
+        true => l.user.clone(),
+        false => subscription::decode_base64_text(&l.user).context("无法解析 ss 的加密方式与密码")?,
+    };
+    let (method, password) = user.split_once(':').context("无法解析 ss 的加密方式与密码")?;
+    let mut outbound = base_outbound("shadowsocks", &l.host, l.port);
+    outbound["method"] = method.into();
+    outbound["password"] = password.into();
+    if let Some(plugin) = l.params.str("plugin") {
+        let (name, opts) = plugin.split_once(';').unwrap_or((&plugin, ""));
+        outbound["plugin"] = ss_plugin(name)?.into();
+        if !opts.is_empty() {
+            outbound["plugin_opts"] = opts.into();
+        }
+    }
+    if l.params.bool("uot") {
+        outbound["udp_over_tcp"] = true.into();
+    }
+    Ok((outbound, l.params.unsupported()))
+}
+
+fn ss_plugin(name: &str) -> Result<&'static str> {
+    match name {
+        "obfs" | "obfs-local" | "simple-obfs" => Ok("obfs-local"),
+        "v2ray-plugin" => Ok("v2ray-plugin"),
+        other => bail!("sing-box 不支持的 ss 插件: {}", other),
+    }
+}
+
+fn vmess_link(link: &str) -> Result<(Value, Vec<String>)> {
+    let payload = link.split_once("://").map_or(link, |(_, p)| p);
+    let json = subscription::decode_base64_text(payload).context("vmess 链接不是有效的 base64")?;
+    let map: Map<String, Value> = serde_json::from_str(&json).context("vmess 链接内容不是有效的 JSON")?;
+    let f = Fields::new(map);
+    f.ignore(&["v", "ps", "encryption"]);
+    let mut outbound = base_outbound("vmess", &f.str("add").context("缺少服务器地址")?, f.u64("port").context("缺少端口")?);
+    outbound["uuid"] = f.str("id").context("缺少 id")?.into();
+    outbound["security"] = f.str("scy").unwrap_or_else(|| "auto".into()).into();
+    outbound["alter_id"] = f.u64("aid").unwrap_or(0).into();
+
+    let host = f.str("host");
+    if f.str("tls").is_some_and(|t| t == "tls") {
+        outbound["tls"] = Tls {
+            server_name: f.str("sni").or_else(|| host.clone()),
+            insecure: f.bool("allowInsecure") | f.bool("skip-cert-verify"),
+            alpn: f.list("alpn"),
+            fingerprint: f.str("fp"),
+            reality: None,
+        }.build();
+    }
+    // tcp 的 type=http 为 HTTP 伪装；grpc 的 type 为传输模式 (sing-box 无对应项)
+    let mut network = f.str("net").unwrap_or_else(|| "tcp".into());
+    let header = f.str("type");
+    if network == "tcp" && header.as_deref() == Some("http") {
+        network = "http".into();
+    } else if header.as_deref().is_some_and(|h| h != "none" && network != "grpc") {
+        bail!("不支持的伪装类型: {}", header.unwrap_or_default());
+    }
+    let path = f.str("path");
+    let transport = Transport {
+        service_name: path.clone().filter(|_| network == "grpc"),
+        path: path.filter(|_| network != "grpc"),
+        host: host.into_iter().flat_map(|h| h.split(',').map(str::to_string).collect::<Vec<_>>()).collect(),
+        network,
+        ..Default::default()
+    };
+    set(&mut outbound, "transport", transport.build()?);
+    Ok((outbound, f.unsupported()))
+}
+
+fn v2ray_link(kind: &str, link: &str) -> Result<(Value, Vec<String>)> {
+    let l = parse_link(link)?;
+    let p = &l.params;
+    let mut outbound = base_outbound(kind, &l.host, l.port);
+    if kind == "vless" {
+        outbound["uuid"] = l.user.clone().into();
+        set(&mut outbound, "flow", p.str("flow"));
+        set(&mut outbound, "packet_encoding", p.str("packetEncoding"));
+        p.ignore(&["encryption"]);
+    } else {
+        outbound["password"] = l.user.clone().into();
+    }
+
+    let default_security = if kind == "trojan" { "tls" } else { "none" };
+    let security = p.str("security").unwrap_or_else(|| default_security.into());
+    let host = p.str("host");
+    match security.as_str() {
+        "none" => {}
+        "tls" | "reality" => {
+            let reality = match security == "reality" {
+                true => Some((p.str("pbk").context("reality 缺少 pbk (公钥)")?, p.str("sid"))),
+                false => None,
+            };
+            let (sni, peer) = (p.str("sni"), p.str("peer"));
+            outbound["tls"] = Tls {
+                server_name: sni.or(peer).or_else(|| host.clone()),
+                insecure: p.bool("allowInsecure") | p.bool("insecure"),
+                alpn: p.list("alpn"),
+                fingerprint: p.str("fp"),
+                reality,
+            }.build();
+        }
+        other => bail!("不支持的安全类型: {}", other),
+    }
+
+    let mut network = p.str("type").unwrap_or_else(|| "tcp".into());
+    if network == "tcp" && p.str("headerType").as_deref() == Some("http") {
+        network = "http".into();
+    }
+    p.ignore(&["mode"]);
+    let transport = Transport {
+        host: host.into_iter().collect(),
+        path: p.str("path"),
+        service_name: p.str("serviceName"),
+        network,
+        ..Default::default()
+    };
+    set(&mut outbound, "transport", transport.build()?);
+    Ok((outbound, p.unsupported()))
+}
+
+fn hysteria2_link(link: &str) -> Result<(Value, Vec<String>)> {
+    let l = parse_link(link)?;
+    let p = &l.params;
+    let mut outbound = base_outbound("hysteria2", &l.host, l.port);
+    outbound["password"] = l.user.clone().into();
+    if let Some(obfs) = p.str("obfs") {
+        let mut obfs = json!({ "type": obfs });
+        set(&mut obfs, "password", p.str("obfs-password"));
+        outbound["obfs"] = obfs;
+    }
+    // 端口跳跃: 20000-30000,40000 -> ["20000:30000", "40000"]
+    if let Some(ports) = p.str("mport") {
+        outbound["server_ports"] = ports.split(',').map(|r| r.trim().replace('-', ":")).collect::<Vec<_>>().into();
+    }
+    outbound["tls"] = Tls {
+        server_name: p.str("sni"),
+        insecure: p.bool("insecure"),
+        alpn: p.list("alpn"),
+        ..Default::default()
+    }.build();
+    Ok((outbound, p.unsupported()))
+}
+
+fn tuic_link(link: &str) -> Result<(Value, Vec<String>)> {
+    let l = parse_link(link)?;
+    let p = &l.params;
+    let (uuid, password) = l.user.split_once(':').context("tuic 链接缺少 uuid:password")?;
+    let mut outbound = base_outbound("tuic", &l.host, l.port);
+    outbound["uuid"] = uuid.into();
+    outbound["password"] = password.into();
+    set(&mut outbound, "congestion_control", p.str("congestion_control"));
+    set(&mut outbound, "udp_relay_mode", p.str("udp_relay_mode"));
+    let mut tls = Tls {
+        server_name: p.str("sni"),
+        insecure: p.bool("allow_insecure") | p.bool("insecure"),
+        alpn: p.list("alpn"),
+        ..Default::default()
+    }.build();
+    if p.bool("disable_sni") {
+        tls["disable_sni"] = true.into();
+    }
+    outbound["tls"] = tls;
+    Ok((outbound, p.unsupported()))
+}
+
+/// 转换一个 Clash / mihomo 节点，返回不含 tag 的出站与提示
+pub fn from_clash(proxy: &serde_yaml::Value) -> Result<(Value, Vec<String>)> {
+    let Value::Object(map) = serde_json::to_value(proxy).context("节点无法解析")? else {
+        bail!("节点不是映射");
+    };
+    let f = Fields::new(map);
+    f.ignore(&["name", "udp"]);
+    let kind = f.str("type").context("缺少 type")?;
+    let server = f.str("server").context("缺少 server")?;
+    let port = f.u64("port");
+    let mut tls_required = false;
+
+    let mut outbound = match kind.as_str() {
+        "ss" => {
+            let mut ss = base_outbound("shadowsocks", &server, port.context("缺少 port")?);
+            ss["method"] = f.str("cipher").context("缺少 cipher")?.into();
+            ss["password"] = f.str("password").context("缺少 password")?.into();
+            if let Some(plugin) = f.str("plugin") {
+                ss["plugin"] = ss_plugin(&plugin)?.into();
+                ss["plugin_opts"] = clash_plugin_opts(&plugin, &f).into();
+            }
+            if f.bool("udp-over-tcp") {
+                ss["udp_over_tcp"] = true.into();
+            }
+            ss
+        }
+        "vmess" => {
+            let mut vmess = base_outbound("vmess", &server, port.context("缺少 port")?);
+            vmess["uuid"] = f.str("uuid").context("缺少 uuid")?.into();
+            vmess["security"] = f.str("cipher").unwrap_or_else(|| "auto".into()).into();
+            vmess["alter_id"] = f.u64("alterId").unwrap_or(0).into();
+            vmess
+        }
+        "vless" => {
+            let mut vless = base_outbound("vless", &server, port.context("缺少 port")?);
+            vless["uuid"] = f.str("uuid").context("缺少 uuid")?.into();
+            set(&mut vless, "flow", f.str("flow"));
+            set(&mut vless, "packet_encoding", f.str("packet-encoding"));
+            vless
+        }
+        "trojan" => {
+            tls_required = true;
+            let mut trojan = base_outbound("trojan", &server, port.context("缺少 port")?);
+            trojan["password"] = f.str("password").context("缺少 password")?.into();
+            trojan
+        }
+        "hysteria2" => {
+            tls_required = true;
+            let mut hy2 = base_outbound("hysteria2", &server, port.unwrap_or(443));
+            hy2["password"] = f.str("password").context("缺少 password")?.into();
+            if let Some(ports) = f.str("ports") {
+                hy2["server_ports"] = ports.split(',').map(|r| r.trim().replace('-', ":")).collect::<Vec<_>>().into();
+            }
+            if let Some(obfs) = f.str("obfs") {
+                let mut obfs = json!({ "type": obfs });
+                set(&mut obfs, "password", f.str("obfs-password"));
+                hy2["obfs"] = obfs;
+            }
+            set(&mut hy2, "up_mbps", f.str("up").and_then(|s| leading_number(&s)));
+            set(&mut hy2, "down_mbps", f.str("down").and_then(|s| leading_number(&s)));
+            hy2
+        }
+        "tuic" => {
+            tls_required = true;
+            let mut tuic = base_outbound("tuic", &server, port.context("缺少 port")?);
+            tuic["uuid"] = f.str("uuid").context("缺少 uuid")?.into();
+            set(&mut tuic, "password", f.str("password"));
+            set(&mut tuic, "congestion_control", f.str("congestion-controller"));
+            set(&mut tuic, "udp_relay_mode", f.str("udp-relay-mode"));
+            if f.bool("reduce-rtt") {
+                tuic["zero_rtt_handshake"] = true.into();
+            }
+            set(&mut tuic, "heartbeat", f.u64("heartbeat-interval").map(|ms| format!("{}ms", ms)));
+            tuic
+        }
+        "socks5" | "http" => {
+            let mut proxy = base_outbound(if kind == "http" { "http" } else { "socks" }, &server, port.context("缺少 port")?);
+            set(&mut proxy, "username", f.str("username"));
+            set(&mut proxy, "password", f.str("password"));
+            proxy
+        }
+        other => bail!("不支持的节点类型: {}", other),
+    };
+
+    if tls_required || f.bool("tls") {
+        let reality = f.str("reality-opts.public-key").map(|key| (key, f.str("reality-opts.short-id")));
+        let (sni, servername) = (f.str("sni"), f.str("servername"));
+        let tls = Tls {
+            server_name: sni.or(servername),
+            insecure: f.bool("skip-cert-verify"),
+            alpn: f.list("alpn"),
+            fingerprint: f.str("client-fingerprint"),
+            reality,
+        };
+        outbound["tls"] = tls.build();
+    }
+    set(&mut outbound, "transport", clash_transport(&f)?);
+    set(&mut outbound, "multiplex", clash_multiplex(&f));
+    Ok((outbound, f.unsupported()))
+}
+
+// plugin-opts 映射 -> SIP003 选项字符串
+fn clash_plugin_opts(plugin: &str, f: &Fields) -> String {
+    let mut opts = Vec::new();
+    if plugin == "obfs" {
+        opts.extend(f.str("plugin-opts.mode").map(|m| format!("obfs={}", m)));
+        opts.extend(f.str("plugin-opts.host").map(|h| format!("obfs-host={}", h)));
+    } else {
+        opts.extend(f.str("plugin-opts.mode").map(|m| format!("mode={}", m)));
+        opts.extend(f.str("plugin-opts.host").map(|h| format!("host={}", h)));
+        opts.extend(f.str("plugin-opts.path").map(|p| format!("path={}", p)));
+        if f.bool("plugin-opts.tls") {
+            opts.push("tls".into());
+        }
+        if f.bool("plugin-opts.mux") {
+            opts.push("mux=1".into());
+        }
+    }
+    opts.join(";")
+}
+
+fn clash_transport(f: &Fields) -> Result<Option<Value>> {
+    let network = f.str("network").unwrap_or_default();
+    let transport = match network.as_str() {
+        "ws" => Transport {
+            path: f.str("ws-opts.path"),
+            headers: f.get("ws-opts.headers").and_then(Value::as_object).cloned().unwrap_or_default(),
+            max_early_data: f.u64("ws-opts.max-early-data"),
+            early_data_header: f.str("ws-opts.early-data-header-name"),
+            ..Default::default()
+        },
+        "grpc" => Transport { service_name: f.str("grpc-opts.grpc-service-name"), ..Default::default() },
+        "h2" => Transport { host: f.list("h2-opts.host"), path: f.str("h2-opts.path"), ..Default::default() },
+        "http" => Transport {
+            path: f.list("http-opts.path").into_iter().next(),
+            host: f.get("http-opts.headers")
+                .and_then(|h| h.get("Host"))
+                .and_then(Value::as_array)
+                .map(|hosts| hosts.iter().filter_map(|h| h.as_str().map(str::to_string)).collect())
+                .unwrap_or_default(),
+            ..Default::default()
+        },
+        _ => Transport::default(),
+    };
+    Transport { network, ..transport }.build()
+}
+
+fn clash_multiplex(f: &Fields) -> Option<Value> {
+    if !f.bool("smux.enabled") {
+        return None;
+    }
+    let mut multiplex = json!({ "enabled": true });
+    set(&mut multiplex, "protocol", f.str("smux.protocol"));
+    set(&mut multiplex, "max_connections", f.u64("smux.max-connections"));
+    set(&mut multiplex, "min_streams", f.u64("smux.min-streams"));
+    set(&mut multiplex, "max_streams", f.u64("smux.max-streams"));
+    if f.bool("smux.padding") {
+        multiplex["padding"] = true.into();
+    }
+    Some(multiplex)
+}
+
+// "100 Mbps" -> 100
+fn leading_number(s: &str) -> Option<u64> {
+    let digits: String = s.trim().chars().take_while(char::is_ascii_digit).collect();
+    digits.parse().ok()
+}
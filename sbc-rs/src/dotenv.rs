@@ -0,0 +1,116 @@
+use anyhow::Result;
+use std::fs;
+use std::path::Path;
+
+// 简单的 .env 解析器：KEY=VALUE，忽略空行与 # 注释，去除一对外层引号
+// 单引号内的内容原样保留；双引号内支持 \\、\" 与 \n 转义
+pub fn parse(content: &str) -> Vec<(String, String)> {
+    let mut vars = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue; }
+        if let Some((k, v)) = line.split_once('=') {
+            vars.push((k.trim().to_string(), unquote(v.trim())));
+        }
+    }
+    vars
+}
+
+fn unquote(v: &str) -> String {
+    if v.len() >= 2 && v.starts_with('\'') && v.ends_with('\'') {
+        return v[1..v.len() - 1].to_string();
+    }
+    if !(v.len() >= 2 && v.starts_with('"') && v.ends_with('"')) {
+        return v.to_string();
+    }
+    let mut out = String::new();
+    let mut chars = v[1..v.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some(e @ ('\\' | '"')) => out.push(e),
+            // 其他反斜杠原样保留 (如正则中的 \.)
+            Some(other) => { out.push('\\'); out.push(other); }
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// 按 parse 的规则为值加引号：默认使用单引号 (内容原样保留)，含换行时改用带转义的双引号
+pub fn quote(value: &str) -> String {
+    if !value.contains('\n') {
+        return format!("'{}'", value);
+    }
+    let escaped = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+// 读取 .env 文件，文件不存在时返回空列表
+pub fn read(path: &Path) -> Result<Vec<(String, String)>> {
+    if !path.exists() { return Ok(Vec::new()); }
+    Ok(parse(&fs::read_to_string(path)?))
+}
+
+/// .env 与 .env.example 的差异
+pub struct Migration {
+    /// 需追加到 .env 末尾的文本 (无缺失变量时为空)
+    pub appended: String,
+    /// .env 中缺失、已从示例补齐的变量
+    pub added: Vec<String>,
+    /// .env 中存在、但示例中已不再出现的变量
+    pub unused: Vec<String>,
+}
+
+/// 对比示例文件，将缺失变量连同其上方的注释与默认值原样补到末尾；已有内容不做任何改动
+pub fn migrate(env_content: &str, example_content: &str) -> Migration {
+    let existing: Vec<String> = parse(env_content).into_iter().map(|(k, _)| k).collect();
+    let example_keys: Vec<String> = parse(example_content).into_iter().map(|(k, _)| k).collect();
+
+    let mut blocks = Vec::new();
+    let mut added = Vec::new();
+    // 紧邻变量上方的连续注释行视为该变量的说明
+    let mut comments: Vec<&str> = Vec::new();
+    for line in example_content.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            comments.clear();
+        } else if trimmed.starts_with('#') {
+            comments.push(line);
+        } else if let Some((k, _)) = trimmed.split_once('=') {
+            let key = k.trim().to_string();
+            if !existing.contains(&key) && !added.contains(&key) {
+                let mut block = comments.join("\n");
+                if !block.is_empty() {
+                    block.push('\n');
+                }
+                block.push_str(line);
+                blocks.push(block);
+                added.push(key);
+            }
+            comments.clear();
+        }
+    }
+
+    let unused = existing.iter()
+        .filter(|k| !example_keys.contains(k))
+        .cloned()
+        .collect();
+
+    let mut appended = String::new();
+    if !blocks.is_empty() {
+        // 原文件缺少结尾换行时先补上，避免与最后一行粘连
+        if !env_content.is_empty() {
+            appended.push_str(if env_content.ends_with('\n') { "\n" } else { "\n\n" });
+        }
+        appended.push_str(&format!("# --- 以下变量由 sbc-rs env migrate 于 {} 从 .env.example 补充 ---\n", chrono::Local::now().format("%Y-%m-%d")));
+        appended.push_str(&blocks.join("\n\n"));
+        appended.push('\n');
+    }
+
+    Migration { appended, added, unused }
+}
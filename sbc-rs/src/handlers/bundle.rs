@@ -0,0 +1,210 @@
+use anyhow::{Context, Result, anyhow, bail};
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use log::{info, warn};
+use sbc_rs::bundle::{self, MANIFEST_FILE, Manifest};
+use sbc_rs::{archive, checksum, signature};
+use sbc_rs::settings::Workspace;
+use crate::cli::UpdateArgs;
+use crate::handlers::{check, history};
+use crate::handlers::update::{self, BUNDLE_ENV_EXAMPLE, BUNDLE_TEMPLATE, UpdateOutcome};
+
+/// Whether an unpacked archive is a managed bundle, i.e. carries a bundle.json manifest
+pub fn is_managed(files: &BTreeMap<String, Vec<u8>>) -> bool {
+    archive::find(files, MANIFEST_FILE).is_some()
+}
+
+// Re-root the archive at the directory holding bundle.json; anything outside of it is refused
+fn rebase(files: &BTreeMap<String, Vec<u8>>) -> Result<BTreeMap<String, Vec<u8>>> {
+    let manifest = files.keys()
+        .filter(|p| Path::new(p).file_name().is_some_and(|n| n == MANIFEST_FILE))
+        .min_by_key(|p| p.matches('/').count())
+        .context("Bundle contains no manifest")?;
+    let prefix = &manifest[..manifest.len() - MANIFEST_FILE.len()];
+    files.iter()
+        .map(|(path, data)| match path.strip_prefix(prefix) {
+            Some(rel) => Ok((rel.to_string(), data.clone())),
+            None => Err(anyhow!("Bundle file {} is outside of the manifest directory {}", path, prefix)),
+        })
+        .collect()
+}
+
+// Directory name of a bundle: its version plus the manifest hash, so a re-published version never collides
+fn bundle_id(manifest: &Manifest, data: &[u8]) -> String {
+    let version: String = manifest.version.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') { c } else { '_' })
+        .collect();
+    format!("{}-{}", version.trim_start_matches('.'), &checksum::sha256_hex(data)[..12])
+}
+
+// Refuse bundles written for a newer sbc-rs or sing-box than the one installed
+fn check_compat(manifest: &Manifest) -> Result<()> {
+    if let Some(ref min) = manifest.min_sbc_rs {
+        let built = chrono::DateTime::parse_from_rfc3339(crate::build::BUILD_TIME_3339)
+            .context("Invalid build time")?
+            .to_utc();
+        // Either a full RFC 3339 timestamp or just a date, which means "built on or after that day"
+        let compatible = match chrono::DateTime::parse_from_rfc3339(min) {
+            Ok(min) => built >= min.to_utc(),
+            Err(_) => built.date_naive() >= chrono::NaiveDate::parse_from_str(min, "%Y-%m-%d")
+                .with_context(|| format!("Invalid min_sbc_rs in bundle manifest: {}", min))?,
+        };
+        if !compatible {
+            bail!("❌ Bundle {} requires sbc-rs built on or after {}, this one was built {}. Run self-update first.", manifest.version, min, crate::build::BUILD_TIME_3339);
+        }
+    }
+    if let Some(ref min) = manifest.min_sing_box {
+        let installed = check::singbox_version()
+            .with_context(|| format!("❌ Bundle {} requires sing-box >= {}, but the installed version is unknown", manifest.version, min))?;
+        if !bundle::version_at_least(&installed, min) {
+            bail!("❌ Bundle {} requires sing-box >= {}, installed is {}. Run self-update first.", manifest.version, min, installed);
+        }
+    }
+    Ok(())
+}
+
+// Write the bundle into a staging directory and verify it from disk before it gets its final name
+fn stage(dir: &Path, manifest: &Manifest, files: &BTreeMap<String, Vec<u8>>) -> Result<()> {
+    let staging = dir.with_file_name(format!(".staging-{}", dir.file_name().unwrap_or_default().to_string_lossy()));
+    let _ = fs::remove_dir_all(&staging);
+    let result = (|| -> Result<()> {
+        for (path, data) in files {
+            let target = staging.join(path);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&target, data)?;
+        }
+        // Read back what landed on disk: a full partition must not activate a truncated bundle
+        for (path, expected) in &manifest.files {
+            let actual = checksum::sha256_hex(&fs::read(staging.join(path))?);
+            if !actual.eq_ignore_ascii_case(expected) {
+                bail!("Staged file {} does not match the manifest", path);
+            }
+        }
+        fs::rename(&staging, dir)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_dir_all(&staging);
+    }
+    result.with_context(|| format!("Failed to stage bundle into {:?}", dir))
+}
+
+// Atomically point `link` at `target` (symlink to a temporary name, then rename over the old link)
+fn replace_link(link: &Path, target: &Path) -> Result<()> {
+    if fs::read_link(link).is_ok_and(|t| t == target) {
+        return Ok(());
+    }
+    let tmp = link.with_extension("link.tmp");
+    let _ = fs::remove_file(&tmp);
+    symlink(target, &tmp)?;
+    fs::rename(&tmp, link).with_context(|| format!("Failed to replace {:?}", link))
+}
+
+// Previously installed bundles kept besides the active one and those referenced by etc/history
+const KEEP_PREVIOUS: usize = 1;
+
+// Keep the active bundle, every bundle a history entry can roll back to, and the most recently installed other
+fn prune(ws: &Workspace, active: &str) {
+    let referenced = history::bundles(ws);
+    let mut old: Vec<(SystemTime, PathBuf)> = fs::read_dir(ws.bundles_dir())
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|e| e.file_name() != active && !e.file_name().to_string_lossy().starts_with('.'))
+        .filter_map(|e| Some((e.metadata().ok()?.modified().ok()?, e.path())))
+        .collect();
+    old.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+    let removable = old.into_iter().skip(KEEP_PREVIOUS)
+        .filter(|(_, path)| !path.file_name().is_some_and(|n| referenced.contains(&*n.to_string_lossy())));
+    for (_, path) in removable {
+        if let Err(e) = fs::remove_dir_all(&path) {
+            warn!("⚠️ Failed to remove old bundle {:?}: {}", path, e);
+        }
+    }
+}
+
+/// Id of the active bundle, i.e. the directory etc/bundle points at
+pub fn active(ws: &Workspace) -> Option<String> {
+    let target = fs::read_link(ws.bundle_link()).ok()?;
+    Some(target.file_name()?.to_string_lossy().to_string())
+}
+
+/// Whether bundle `id` is still installed in etc/bundles
+pub fn installed(ws: &Workspace, id: &str) -> bool {
+    ws.bundles_dir().join(id).join(BUNDLE_TEMPLATE).is_file()
+}
+
+/// Point etc/bundle back at an installed bundle and link the template (and .env.example, if the
+/// bundle ships one) into it again. Used by rollback to restore a bundle recorded in etc/history.
+pub fn activate(ws: &Workspace, template_path: &Path, env_example: &Path, id: &str) -> Result<()> {
+    let dir = ws.bundles_dir().join(id);
+    let link = ws.bundle_link();
+    replace_link(&link, &Path::new("bundles").join(id))?;
+    replace_link(template_path, &std::path::absolute(link.join(BUNDLE_TEMPLATE))?)?;
+    if dir.join(BUNDLE_ENV_EXAMPLE).is_file() {
+        replace_link(env_example, &std::path::absolute(link.join(BUNDLE_ENV_EXAMPLE))?)?;
+    }
+    info!("✅ Bundle {} re-activated ({:?}).", id, dir);
+    Ok(())
+}
+
+/// Verify, stage and activate a managed bundle. The template and .env.example become links into
+/// etc/bundle, so switching that one link replaces the template, its fragments and the example together.
+pub fn install(args: &UpdateArgs, ws: &Workspace, template_path: &Path, env_file: &Path, files: &BTreeMap<String, Vec<u8>>, keep: usize) -> Result<UpdateOutcome> {
+    let files = rebase(files)?;
+    let manifest_data = &files[MANIFEST_FILE];
+    let manifest = Manifest::parse(manifest_data)?;
+    info!("📦 Bundle {} with {} file(s).", manifest.version, manifest.files.len());
+
+    let signature_name = format!("{}{}", MANIFEST_FILE, signature::SIGNATURE_SUFFIX);
+    update::verify_signature(args, "Bundle manifest", manifest_data, || {
+        files.get(&signature_name)
+            .map(|s| String::from_utf8_lossy(s).to_string())
+            .ok_or_else(|| anyhow!("Bundle contains no {}", signature_name))
+    })?;
+    manifest.verify(&files).context("❌ Refusing bundle that does not match its manifest")?;
+    info!("🔐 All bundle files match the manifest.");
+    check_compat(&manifest)?;
+
+    let template = files.get(BUNDLE_TEMPLATE)
+        .with_context(|| format!("❌ Bundle manifest lists no {}", BUNDLE_TEMPLATE))?;
+    let template = std::str::from_utf8(template).context("Template is not valid UTF-8")?;
+    update::trial_render(args, template_path, env_file, template)
+        .context("❌ Validation failed: bundled template does not render, keeping the current bundle")?;
+
+    let id = bundle_id(&manifest, manifest_data);
+    let dir = ws.bundles_dir().join(&id);
+    let link = ws.bundle_link();
+    let link_target = Path::new("bundles").join(&id);
+    let template_target = std::path::absolute(link.join(BUNDLE_TEMPLATE))?;
+    let env_target = std::path::absolute(link.join(BUNDLE_ENV_EXAMPLE))?;
+    let env_path = args.env_path.as_deref().filter(|_| files.contains_key(BUNDLE_ENV_EXAMPLE));
+    let in_place = fs::read_link(&link).is_ok_and(|t| t == link_target)
+        && fs::read_link(template_path).is_ok_and(|t| t == template_target)
+        && env_path.is_none_or(|p| fs::read_link(p).is_ok_and(|t| t == env_target));
+    if in_place && dir.is_dir() {
+        info!("✅ Bundle {} is already active.", manifest.version);
+        return Ok(UpdateOutcome::Unchanged);
+    }
+
+    if !dir.is_dir() {
+        stage(&dir, &manifest, &files)?;
+    }
+    // Snapshot what is installed now so it can be rolled back to
+    update::record_history(args, ws, template_path, keep);
+
+    replace_link(&link, &link_target)?;
+    replace_link(template_path, &template_target)?;
+    if let Some(path) = env_path {
+        replace_link(path, &env_target)?;
+    }
+    info!("✅ Bundle {} activated ({:?}).", manifest.version, dir);
+
+    prune(ws, &id);
+    Ok(UpdateOutcome::Updated)
+}
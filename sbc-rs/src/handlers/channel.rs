@@ -0,0 +1,52 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sbc_rs::settings::Workspace;
+use crate::cli::FetchArgs;
+use crate::handlers::fetch::Fetcher;
+
+// 服务器上的通道索引 (channels.json)
+#[derive(Serialize, Deserialize)]
+struct ChannelIndex {
+    channels: Vec<ChannelInfo>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChannelInfo {
+    name: String,
+    #[serde(default)]
+    description: String,
+}
+
+pub fn handle_channel_list(ws: &Workspace, index: Option<String>, json: bool, mut fetch: FetchArgs) -> Result<()> {
+    let settings = &ws.settings.update;
+    let url = index.unwrap_or_else(|| settings.channel_index.clone());
+    if fetch.proxy.is_none() {
+        fetch.proxy = settings.proxy.clone();
+    }
+
+    let body = Fetcher::new(&fetch, &ws.config())?
+        .with_auth(&settings.auth, &ws.env_file())?
+        .get_string(&url)
+        .with_context(|| format!("无法获取通道索引: {}", url))?;
+    let index: ChannelIndex = serde_json::from_str(&body)
+        .with_context(|| format!("通道索引格式无效: {}", url))?;
+
+    if json {
+        let list: Vec<_> = index.channels.iter().map(|c| serde_json::json!({
+            "name": c.name,
+            "description": c.description,
+            "current": c.name == settings.channel,
+        })).collect();
+        println!("{}", serde_json::to_string_pretty(&list)?);
+        return Ok(());
+    }
+
+    for c in &index.channels {
+        let marker = if c.name == settings.channel { "  ← 当前" } else { "" };
+        println!("{:<16} {}{}", c.name, c.description, marker);
+    }
+    if !index.channels.iter().any(|c| c.name == settings.channel) {
+        println!("(当前通道 {} 不在索引中)", settings.channel);
+    }
+    Ok(())
+}
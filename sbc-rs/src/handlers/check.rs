@@ -0,0 +1,65 @@
+use anyhow::{Context, Result, bail};
+use std::env;
+use std::path::Path;
+use std::process::Command;
+use log::info;
+
+// 定位 sing-box 可执行文件
+// 优先级: SBC_SINGBOX_BIN 环境变量 (用于测试) -> 与 sbc-rs 同目录的 sing-box -> PATH 中的 sing-box
+pub fn locate_singbox_bin() -> String {
+    if let Ok(bin) = env::var("SBC_SINGBOX_BIN")
+        && !bin.is_empty()
+    {
+        return bin;
+    }
+    if let Ok(exe_path) = env::current_exe()
+        && let Some(parent) = exe_path.parent()
+    {
+        let sibling = parent.join("sing-box");
+        if sibling.exists() {
+            return sibling.to_string_lossy().to_string();
+        }
+    }
+    "sing-box".to_string()
+}
+
+// 调用 `sing-box check -c <config>` 预检配置，失败时将其 stderr 作为错误返回
+pub fn check_config(config: &Path) -> Result<()> {
+    let singbox_bin = locate_singbox_bin();
+    info!("🔍 正在预检配置: {} check -c {:?}", singbox_bin, config);
+
+    let output = Command::new(&singbox_bin)
+        .arg("check")
+        .arg("-c")
+        .arg(config)
+        .output()
+        .with_context(|| format!("无法执行 {} check", singbox_bin))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let detail = if stderr.trim().is_empty() { stdout.trim() } else { stderr.trim() };
+        bail!("sing-box check 未通过 ({}):\n{}", output.status, detail);
+    }
+
+    info!("✅ sing-box check 通过。");
+    Ok(())
+}
+
+// 读取 `sing-box version` 输出首行中的版本号，如 "sing-box version 1.12.0" -> "1.12.0"
+pub fn singbox_version() -> Result<String> {
+    let singbox_bin = locate_singbox_bin();
+    let output = Command::new(&singbox_bin)
+        .arg("version")
+        .output()
+        .with_context(|| format!("无法执行 {} version", singbox_bin))?;
+    if !output.status.success() {
+        bail!("{} version 执行失败 ({})", singbox_bin, output.status);
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().last())
+        .map(str::to_string)
+        .with_context(|| format!("无法解析 {} version 的输出", singbox_bin))
+}
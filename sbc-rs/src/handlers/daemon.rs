@@ -1,58 +1,47 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use nix::sys::signal::{self, Signal};
 use nix::unistd::Pid;
-use crate::handlers::render;
+use crate::handlers::{check, render, sub};
+use sbc_rs::{dotenv, redact};
+use sbc_rs::settings::Workspace;
 use std::env;
 use std::fs;
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 use std::thread;
 use std::time::Duration;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use log::{info, warn, error};
 
-fn get_workspace_path(config_path: &PathBuf) -> PathBuf {
-    // 优先从环境变量获取
-    if let Ok(ws) = env::var("WORKSPACE") {
-        return PathBuf::from(ws);
+// 简单的 .env 加载器
+pub(crate) fn load_env_file(path: &Path) -> Result<()> {
+    for (k, v) in read_env_file(path)? {
+        unsafe { env::set_var(k, v); }
     }
-    // 兜底：从配置文件路径推导 (etc/config.json -> etc -> workspace)
-    config_path.parent()
-        .and_then(|p| p.parent())
-        .map(PathBuf::from)
-        .unwrap_or_else(|| PathBuf::from("/data/adb/sing-box-workspace"))
-}
-
-fn get_pid_file_path(workspace: &PathBuf) -> PathBuf {
-    env::var("SBC_PID_FILE").map(PathBuf::from).unwrap_or_else(|_| workspace.join("var/run/sing-box.pid"))
+    Ok(())
 }
 
-// 简单的 .env 加载器
-fn load_env_file(path: &PathBuf) -> Result<()> {
-    if !path.exists() { return Ok(()); }
-    let content = fs::read_to_string(path)?;
-    for line in content.lines() {
-        let line = line.trim();
-        if line.is_empty() || line.starts_with('#') { continue; }
-        if let Some((k, v)) = line.split_once('=') {
-            let clean_v = v.trim().trim_matches('"').trim_matches('\'');
-            unsafe { env::set_var(k.trim(), clean_v); }
+// 读取 .env 文件 (不写入进程环境)，其中的敏感值登记后在日志中脱敏
+pub(crate) fn read_env_file(path: &Path) -> Result<Vec<(String, String)>> {
+    let vars = dotenv::read(path)?;
+    for (k, v) in &vars {
+        if redact::is_secret_name(k) {
+            redact::register(v);
         }
     }
-    Ok(())
+    Ok(vars)
 }
 
-pub fn handle_run(config_path: Option<PathBuf>, template_path: Option<PathBuf>, working_dir: Option<PathBuf>) -> Result<()> {
-    // 0. 路径解析
-    // 如果没传 config_path，则假定在默认位置
-    let resolved_config = config_path.unwrap_or_else(|| PathBuf::from("/data/adb/sing-box-workspace/etc/config.json"));
-    let workspace = get_workspace_path(&resolved_config);
-    let pid_file = get_pid_file_path(&workspace);
-    let stop_flag = workspace.join("STOP");
+pub fn handle_run(ws: &Workspace, config_path: Option<PathBuf>, template_path: Option<PathBuf>, working_dir: Option<PathBuf>, check: bool, strict: bool, script: Option<PathBuf>) -> Result<()> {
+    // 0. 路径解析：未显式指定的路径均取自工作空间设置
+    let resolved_config = config_path.unwrap_or_else(|| ws.config());
+    let pid_file = ws.pid_file();
+    let stop_flag = ws.stop_flag();
+    let daemon_settings = &ws.settings.daemon;
 
     // 1. 加载环境配置
-    let env_path = workspace.join(".env");
+    let env_path = ws.env_file();
     if let Err(e) = load_env_file(&env_path) {
         warn!("⚠️ 无法在 {:?} 加载 .env 文件: {}", env_path, e);
     }
@@ -66,7 +55,7 @@ pub fn handle_run(config_path: Option<PathBuf>, template_path: Option<PathBuf>,
     }).context("设置信号处理程序失败")?;
 
     let mut retry_count = 0;
-    let max_retries = 4;
+    let max_retries = daemon_settings.max_retries;
 
     // 确保 PID 文件存放目录存在
     if let Some(parent) = pid_file.parent() {
@@ -78,8 +67,18 @@ pub fn handle_run(config_path: Option<PathBuf>, template_path: Option<PathBuf>,
     fs::write(&pid_file, my_pid.to_string()).context("无法写入 PID 文件")?;
     info!("📌 监护进程 PID 已写入: {} -> {:?}", my_pid, pid_file);
 
+    let render_opts = render::RenderOptions { strict, ..render::workspace_options(ws, script, check) };
+    if let Some(ref s) = render_opts.script {
+        info!("📜 已启用渲染脚本: {:?}", s);
+    }
+
+    // 订阅网关须在 sing-box 启动前就绪；启动失败时仅告警，providers 会拿到空列表
+    if ws.settings.subscription.gateway && let Err(e) = sub::spawn_gateway(ws) {
+        warn!("⚠️ 订阅网关启动失败: {:#}", e);
+    }
+
     // 工作目录准备
-    let final_wd = working_dir.unwrap_or_else(|| workspace.clone());
+    let final_wd = working_dir.unwrap_or_else(|| ws.working_dir());
     if !final_wd.exists() {
         fs::create_dir_all(&final_wd).context("无法创建工作目录")?;
     }
@@ -92,16 +91,15 @@ pub fn handle_run(config_path: Option<PathBuf>, template_path: Option<PathBuf>,
         }
 
         // 2. 日志轮转
-        if let Some(log_file) = env::var_os("LOG_FILE").map(PathBuf::from) {
-             if log_file.exists() {
-                if let Ok(metadata) = fs::metadata(&log_file) {
-                    if metadata.len() > 1024 * 1024 { // 1MB
-                        let old_log = log_file.with_extension("log.old");
-                        let _ = fs::rename(&log_file, old_log);
-                        info!("🔄 日志已轮转 (超过 1MB)");
-                    }
+        let log_file = ws.log_file();
+        if log_file.exists() {
+            if let Ok(metadata) = fs::metadata(&log_file) {
+                if metadata.len() > daemon_settings.log_max_bytes {
+                    let old_log = log_file.with_extension("log.old");
+                    let _ = fs::rename(&log_file, old_log);
+                    info!("🔄 日志已轮转 (超过 {} 字节)", daemon_settings.log_max_bytes);
                 }
-             }
+            }
         }
 
         info!("🚀 正在启动 sing-box 监控进程...");
@@ -111,21 +109,20 @@ pub fn handle_run(config_path: Option<PathBuf>, template_path: Option<PathBuf>,
         // 3. 自动渲染
         if let Some(ref template) = template_path {
             info!("🎨 正在从模板自动渲染配置: {:?}", template);
-            render::handle_render(template.clone(), resolved_config.clone())?;
-            info!("✅ 配置渲染成功。");
+            match render::handle_render(template.clone(), resolved_config.clone(), &render_opts) {
+                Ok(()) => info!("✅ 配置渲染成功。"),
+                // 预检或严格模式未通过时，若已有旧配置则继续使用，避免因坏配置耗尽重试次数
+                Err(e) if (check || strict) && resolved_config.exists() => {
+                    error!("❌ 配置渲染失败: {:#}", e);
+                    warn!("⚠️ 继续使用现有配置: {:?}", resolved_config);
+                }
+                Err(e) => return Err(e),
+            }
         }
 
         // 4. 定位并启动进程
         use std::os::unix::process::CommandExt;
-        let mut singbox_bin = "sing-box".to_string();
-        if let Ok(exe_path) = env::current_exe() {
-            if let Some(parent) = exe_path.parent() {
-                let sibling = parent.join("sing-box");
-                if sibling.exists() {
-                    singbox_bin = sibling.to_string_lossy().to_string();
-                }
-            }
-        }
+        let singbox_bin = check::locate_singbox_bin();
 
         info!("💨 执行指令: {} run -c {:?} -D {:?}", singbox_bin, resolved_config, final_wd);
         
@@ -189,18 +186,81 @@ pub fn handle_run(config_path: Option<PathBuf>, template_path: Option<PathBuf>,
             break;
         }
 
-        info!("⏳ 将在 10 秒后进行第 {}/{} 次重启尝试...", retry_count, max_retries);
-        thread::sleep(Duration::from_secs(10));
+        info!("⏳ 将在 {} 秒后进行第 {}/{} 次重启尝试...", daemon_settings.retry_delay, retry_count, max_retries);
+        thread::sleep(Duration::from_secs(daemon_settings.retry_delay));
     }
 
     let _ = fs::remove_file(&pid_file);
     Ok(())
 }
 
-pub fn handle_stop() -> Result<()> {
-    // deduce workspace for stop too
-    let workspace = PathBuf::from(env::var("WORKSPACE").unwrap_or_else(|_| "/data/adb/sing-box-workspace".into()));
-    let pid_file = get_pid_file_path(&workspace);
+// 正在运行的监护进程 PID (PID 文件存在且进程存活)
+pub fn supervisor_pid(ws: &Workspace) -> Option<i32> {
+    let pid: i32 = fs::read_to_string(ws.pid_file()).ok()?.trim().parse().ok()?;
+    signal::kill(Pid::from_raw(pid), None).ok()?;
+    Some(pid)
+}
+
+// 监护进程当前托管的 sing-box 子进程 (扫描 /proc/*/stat 中的父进程号，忽略僵尸进程)
+pub fn core_pid(supervisor: i32) -> Option<i32> {
+    fs::read_dir("/proc").ok()?.flatten().find_map(|e| {
+        let pid: i32 = e.file_name().to_str()?.parse().ok()?;
+        let stat = fs::read_to_string(e.path().join("stat")).ok()?;
+        // 进程名可能含空格，字段从最后一个 ')' 之后开始: state ppid ...
+        let mut fields = stat.rsplit_once(')')?.1.split_whitespace();
+        let state = fields.next()?;
+        let ppid: i32 = fields.next()?.parse().ok()?;
+        (ppid == supervisor && state != "Z").then_some(pid)
+    })
+}
+
+// 重启监护进程，并等待 secs 秒确认 sing-box 仍在运行
+pub fn restart_and_check(ws: &Workspace, secs: u64) -> Result<()> {
+    handle_stop(ws)?;
+    spawn_supervisor(ws)?;
+    info!("⏳ 等待 {} 秒确认 sing-box 已启动...", secs);
+    thread::sleep(Duration::from_secs(secs));
+    match supervisor_pid(ws).and_then(core_pid) {
+        Some(pid) => {
+            info!("✅ sing-box 运行中 (PID {})。", pid);
+            Ok(())
+        }
+        None => bail!("重启 {} 秒后 sing-box 仍未运行", secs),
+    }
+}
+
+// 以工作空间 bin 目录中的 sbc-rs 在后台启动监护进程 (与 `sbc start` 等价)
+pub fn spawn_supervisor(ws: &Workspace) -> Result<()> {
+    use std::os::unix::process::CommandExt;
+    let exe = ws.bin_dir().join("sbc-rs");
+    let log_file = ws.log_file();
+    if let Some(parent) = log_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let log = fs::OpenOptions::new().create(true).append(true).open(&log_file)
+        .with_context(|| format!("无法打开日志文件: {:?}", log_file))?;
+
+    info!("🚀 正在启动监护进程: {:?}", exe);
+    Command::new(&exe)
+        .arg("--workspace")
+        .arg(&ws.root)
+        .arg("run")
+        .arg("--template")
+        .arg(ws.template())
+        .arg("--check")
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        // 独立进程组，调用方退出后继续运行
+        .process_group(0)
+        .spawn()
+        .with_context(|| format!("无法启动监护进程: {:?}", exe))?;
+    Ok(())
+}
+
+pub fn handle_stop(ws: &Workspace) -> Result<()> {
+    // 与 run 使用同一工作空间解析结果
+    let pid_file = ws.pid_file();
     
     if !pid_file.exists() {
         warn!("⚠️ 未发现运行中的实例 (PID 文件缺失: {:?})。", pid_file);
@@ -216,15 +276,24 @@ pub fn handle_stop() -> Result<()> {
     match signal::kill(pid, Signal::SIGTERM) {
         Ok(_) => {
             info!("⏳ 正在等待进程退出...");
-            for _ in 0..50 { 
-                thread::sleep(Duration::from_millis(100));
-                if signal::kill(pid, None).is_err() { 
-                    info!("✅ 进程已正常退出。");
-                    let _ = fs::remove_file(pid_file);
-                    return Ok(());
-                }
+            if wait_exit(pid, 50) {
+                info!("✅ 进程已正常退出。");
+                let _ = fs::remove_file(pid_file);
+                return Ok(());
+            }
+            // 旧实例仍在时不能返回成功，否则调用方会在其之外再启动一个监护进程
+            warn!("⚠️ 进程 {} 在 5 秒后仍未退出，发送 SIGKILL...", pid_num);
+            let core = core_pid(pid_num);
+            let _ = signal::kill(pid, Signal::SIGKILL);
+            // 监护进程被强制结束时不会再转发信号，其托管的 sing-box 需一并结束
+            if let Some(core) = core {
+                let _ = signal::kill(Pid::from_raw(core), Signal::SIGKILL);
             }
-            warn!("⚠️ 进程 {} 在 5 秒后仍未退出。", pid_num);
+            if !wait_exit(pid, 20) {
+                bail!("进程 {} 在 SIGKILL 后仍未退出", pid_num);
+            }
+            warn!("⚠️ 进程 {} 已被强制结束。", pid_num);
+            let _ = fs::remove_file(pid_file);
         },
         Err(e) => {
             error!("发送信号失败: {} (进程可能已经结束)", e);
@@ -234,3 +303,13 @@ pub fn handle_stop() -> Result<()> {
 
     Ok(())
 }
+
+// 每 100ms 检查一次，最多 tries 次；僵尸进程 (如本进程启动后已退出的监护进程) 视为已退出
+fn wait_exit(pid: Pid, tries: u32) -> bool {
+    (0..tries).any(|_| {
+        thread::sleep(Duration::from_millis(100));
+        signal::kill(pid, None).is_err()
+            || fs::read_to_string(format!("/proc/{}/stat", pid))
+                .is_ok_and(|stat| stat.rsplit_once(')').is_some_and(|(_, rest)| rest.trim_start().starts_with('Z')))
+    })
+}
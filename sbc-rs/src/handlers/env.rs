@@ -0,0 +1,43 @@
+use anyhow::{Context, Result};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use log::{info, warn};
+use sbc_rs::dotenv::{self, Migration};
+
+/// 将 .env.example 中新增的变量补充到 .env (仅追加，不改动已有内容)
+pub fn migrate_env(example: &Path, env_file: &Path, dry_run: bool) -> Result<Migration> {
+    let example_content = fs::read_to_string(example)
+        .with_context(|| format!("读取 .env.example 失败: {:?}", example))?;
+    let env_content = if env_file.exists() {
+        fs::read_to_string(env_file)
+            .with_context(|| format!("读取 .env 文件失败: {:?}", env_file))?
+    } else {
+        String::new()
+    };
+
+    let migration = dotenv::migrate(&env_content, &example_content);
+    for key in &migration.unused {
+        warn!("⚠️ .env 中的 {} 已不在 .env.example 中，可能已不再使用 (未做改动)", key);
+    }
+    if migration.added.is_empty() {
+        info!("✅ .env 已包含 .env.example 中的全部变量");
+        return Ok(migration);
+    }
+
+    if dry_run {
+        info!("📝 将补充 {} 个变量: {}", migration.added.len(), migration.added.join(", "));
+        print!("{}", migration.appended);
+    } else {
+        // 以追加方式写入，保留原文件内容与权限
+        let mut file = OpenOptions::new().create(true).append(true).open(env_file)
+            .with_context(|| format!("无法写入 .env 文件: {:?}", env_file))?;
+        file.write_all(migration.appended.as_bytes())?;
+        info!("📝 已向 {:?} 补充 {} 个变量: {}", env_file, migration.added.len(), migration.added.join(", "));
+    }
+    Ok(migration)
+}
+
+pub fn handle_env_migrate(example: &Path, env_file: &Path, dry_run: bool) -> Result<()> {
+    migrate_env(example, env_file, dry_run).map(|_| ())
+}
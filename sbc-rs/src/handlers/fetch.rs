@@ -0,0 +1,474 @@
+use anyhow::{Context, Result, anyhow, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::net::{SocketAddr, TcpStream};
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::{info, warn};
+use percent_encoding::{NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+use sbc_rs::{dotenv, redact};
+use sbc_rs::settings::SourceAuth;
+use crate::cli::FetchArgs;
+
+// Validators of a previously fetched artifact
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct ArtifactMeta {
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+pub enum Fetched {
+    Body(Vec<u8>, ArtifactMeta),
+    NotModified,
+}
+
+// A response whose status and headers matter to the caller
+pub struct Response {
+    pub status: u16,
+    // Header names are lower-cased
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+// Outcome of a single attempt: retry the same mirror, or give up on it
+enum AttemptError {
+    Transient(anyhow::Error),
+    Fatal(anyhow::Error),
+    // 401 / 403, explained with the credentials (not) configured for the URL
+    Denied(u16),
+}
+
+// Headers sent to every URL under a prefix, with the values already resolved from .env.
+// A variable that is not set only fails requests under that prefix.
+struct AuthRule {
+    prefix: String,
+    headers: Result<Vec<(String, String)>, String>,
+}
+
+// Where requests are routed
+enum ProxyMode {
+    Direct,
+    Fixed(String),
+    // HTTP(S)_PROXY / ALL_PROXY / NO_PROXY, resolved per URL
+    Env,
+}
+
+/// HTTP downloader with mirror fallback, per-attempt timeouts, exponential backoff and proxy support
+pub struct Fetcher {
+    mode: ProxyMode,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+    // One agent per proxy URL ("" = direct), so connections are pooled per route
+    agents: Mutex<HashMap<String, ureq::Agent>>,
+    attempts: u32,
+    backoff: Duration,
+    auth: Vec<AuthRule>,
+    user_agent: Option<String>,
+}
+
+impl Fetcher {
+    /// `default_config` is the rendered config.json used by `--proxy auto` unless `--proxy-config` is given
+    pub fn new(args: &FetchArgs, default_config: &Path) -> Result<Self> {
+        let mode = match args.proxy.as_deref() {
+            None => ProxyMode::Env,
+            Some("direct") => ProxyMode::Direct,
+            Some("auto") => {
+                let config = args.proxy_config.as_deref().unwrap_or(default_config);
+                match detect_mixed_inbound(config) {
+                    Some(proxy) => {
+                        info!("🧭 Using sing-box mixed inbound as proxy: {}", display_proxy(&proxy));
+                        ProxyMode::Fixed(proxy)
+                    }
+                    None => {
+                        info!("🧭 No reachable mixed inbound in {:?}, connecting without it.", config);
+                        ProxyMode::Env
+                    }
+                }
+            }
+            Some(url) => {
+                info!("🧭 Using proxy: {}", display_proxy(url));
+                ProxyMode::Fixed(url.to_string())
+            }
+        };
+
+        let fetcher = Self {
+            mode,
+            connect_timeout: Duration::from_secs(args.connect_timeout),
+            read_timeout: Duration::from_secs(args.read_timeout),
+            agents: Mutex::new(HashMap::new()),
+            attempts: args.retries.max(1),
+            backoff: Duration::from_millis(args.retry_backoff_ms),
+            auth: Vec::new(),
+            user_agent: None,
+        };
+        // Reject a malformed explicit proxy up front rather than on every mirror
+        if let ProxyMode::Fixed(ref proxy) = fetcher.mode {
+            fetcher.agent(Some(proxy))?;
+        }
+        Ok(fetcher)
+    }
+
+    /// Attach per-source credentials. Each setting names a variable, looked up in the .env file
+    /// and then in the process environment; the resolved values are registered for log redaction.
+    pub fn with_auth(mut self, rules: &[SourceAuth], env_file: &Path) -> Result<Self> {
+        let vars: HashMap<String, String> = match rules.is_empty() || !env_file.exists() {
+            true => HashMap::new(),
+            false => dotenv::read(env_file)?.into_iter().collect(),
+        };
+        for rule in rules {
+            let basic = match (&rule.username, &rule.password) {
+                (Some(user), Some(password)) => Some((user, password)),
+                (None, None) => None,
+                _ => bail!("❌ update.auth for {}: username and password must be set together", rule.prefix),
+            };
+            if rule.bearer.is_none() && basic.is_none() && rule.headers.is_empty() {
+                bail!("❌ update.auth for {}: no bearer, username/password or headers given", rule.prefix);
+            }
+            if rule.prefix.starts_with("http://") {
+                warn!("⚠️ Credentials for {} are sent without TLS.", rule.prefix);
+            }
+
+            let lookup = |name: &String| -> Result<String, String> {
+                let value = vars.get(name).cloned()
+                    .or_else(|| env::var(name).ok())
+                    .filter(|v| !v.is_empty())
+                    .ok_or_else(|| format!("❌ update.auth for {}: variable {} is not set in .env", rule.prefix, name))?;
+                redact::register(&value);
+                Ok(value)
+            };
+            let headers = (|| {
+                let mut headers = Vec::new();
+                if let Some(name) = &rule.bearer {
+                    headers.push(("Authorization".to_string(), format!("Bearer {}", lookup(name)?)));
+                }
+                if let Some((user, password)) = basic {
+                    let encoded = BASE64.encode(format!("{}:{}", lookup(user)?, lookup(password)?));
+                    redact::register(&encoded);
+                    headers.push(("Authorization".to_string(), format!("Basic {}", encoded)));
+                }
+                for (header, name) in &rule.headers {
+                    headers.push((header.clone(), lookup(name)?));
+                }
+                Ok(headers)
+            })();
+            self.auth.push(AuthRule { prefix: rule.prefix.clone(), headers });
+        }
+        Ok(self)
+    }
+
+    /// Send a custom User-Agent (subscription servers pick the response format from it)
+    pub fn with_user_agent(mut self, user_agent: Option<&str>) -> Self {
+        self.user_agent = user_agent.map(str::to_string);
+        self
+    }
+
+    // Most specific rule whose prefix covers the URL (a prefix never matches half a host or path segment)
+    fn auth_for(&self, url: &str) -> Option<&AuthRule> {
+        self.auth.iter()
+            .filter(|rule| {
+                url.strip_prefix(&rule.prefix).is_some_and(|rest| {
+                    rest.is_empty() || rule.prefix.ends_with('/') || rest.starts_with(['/', '?', '#'])
+                })
+            })
+            .max_by_key(|rule| rule.prefix.len())
+    }
+
+    fn denied(&self, url: &str, code: u16) -> anyhow::Error {
+        match self.auth_for(url) {
+            Some(rule) => anyhow!("HTTP {}: the server rejected the credentials configured for {} (update.auth)", code, rule.prefix),
+            None => anyhow!("HTTP {}: the server requires credentials; configure update.auth in etc/sbc.toml for this source", code),
+        }
+    }
+
+    fn agent(&self, proxy: Option<&str>) -> Result<ureq::Agent> {
+        let key = proxy.unwrap_or_default().to_string();
+        let mut agents = self.agents.lock().unwrap();
+        if let Some(agent) = agents.get(&key) {
+            return Ok(agent.clone());
+        }
+        let mut builder = ureq::AgentBuilder::new()
+            .timeout_connect(self.connect_timeout)
+            .timeout_read(self.read_timeout);
+        if let Some(proxy) = proxy {
+            // ureq takes the userinfo verbatim, so percent-encoded credentials are decoded first
+            builder = builder.proxy(ureq::Proxy::new(decode_proxy_credentials(proxy))
+                .with_context(|| format!("Invalid proxy URL: {}", display_proxy(proxy)))?);
+        }
+        let agent = builder.build();
+        agents.insert(key, agent.clone());
+        Ok(agent)
+    }
+
+    // Request builder for a URL, routed through the proxy that applies to it
+    fn request(&self, url: &str) -> Result<ureq::Request> {
+        let proxy = match self.mode {
+            ProxyMode::Direct => None,
+            ProxyMode::Fixed(ref proxy) => Some(proxy.clone()),
+            ProxyMode::Env => env_proxy_for(url),
+        };
+        let mut request = self.agent(proxy.as_deref())?.get(url);
+        // ureq only authenticates the CONNECT used for https; plain http requests go to the
+        // proxy directly and need the header themselves (never added to tunnelled requests)
+        if let Some(credentials) = proxy.as_deref().and_then(http_proxy_credentials)
+            && url.starts_with("http://")
+        {
+            request = request.set("Proxy-Authorization", &format!("Basic {}", BASE64.encode(&credentials)));
+        }
+        if let Some(ref user_agent) = self.user_agent {
+            request = request.set("User-Agent", user_agent);
+        }
+        if let Some(rule) = self.auth_for(url) {
+            let headers = rule.headers.as_ref().map_err(|e| anyhow!("{}", e))?;
+            for (header, value) in headers {
+                request = request.set(header, value);
+            }
+        }
+        Ok(request)
+    }
+
+    /// Download the first mirror that answers, trying each one in order.
+    /// Validators are only sent to the mirror that produced them, and only if the local copy still exists.
+    /// Sources may also be `file://` URLs or plain filesystem paths.
+    pub fn fetch(&self, urls: &[String], previous: Option<&ArtifactMeta>, have_local: bool) -> Result<Fetched> {
+        let mut failures = Vec::new();
+        for url in urls {
+            let validators = previous.filter(|p| have_local && &p.url == url);
+            let result = match local_path(url) {
+                Some(path) => read_local(path).map(|body| Fetched::Body(body, ArtifactMeta { url: url.clone(), ..Default::default() })),
+                None => self.with_retries(url, |request| attempt(request, url, validators)),
+            };
+            match result {
+                Ok(fetched) => {
+                    info!("🪞 Served by mirror: {}", url);
+                    return Ok(fetched);
+                }
+                Err(e) => {
+                    warn!("⚠️ Mirror failed: {}: {:#}", url, e);
+                    failures.push(format!("{}: {:#}", url, e));
+                }
+            }
+        }
+        bail!("All {} mirror(s) failed:\n  - {}", urls.len(), failures.join("\n  - "))
+    }
+
+    /// Plain GET of a single URL (e.g. a detached signature), with the same retry policy
+    pub fn get_string(&self, url: &str) -> Result<String> {
+        String::from_utf8(self.get_bytes(url)?).with_context(|| format!("{} is not valid UTF-8", url))
+    }
+
+    /// GET of a single binary artifact, with the same retry policy
+    pub fn get_bytes(&self, url: &str) -> Result<Vec<u8>> {
+        if let Some(path) = local_path(url) {
+            return read_local(path);
+        }
+        self.with_retries(url, |request| {
+            let mut body = Vec::new();
+            call(request)?
+                .into_reader()
+                .read_to_end(&mut body)
+                .map_err(|e| AttemptError::Transient(e.into()))?;
+            Ok(body)
+        })
+    }
+
+    /// GET of a single URL that returns error statuses instead of raising them.
+    /// Only transport failures, server errors and rate limiting are retried.
+    pub fn get_response(&self, url: &str) -> Result<Response> {
+        self.with_retries(url, |request| {
+            let resp = match request.call() {
+                Ok(resp) => resp,
+                Err(ureq::Error::Status(code, _)) if code >= 500 || code == 429 => {
+                    return Err(AttemptError::Transient(anyhow!("HTTP {}", code)));
+                }
+                Err(ureq::Error::Status(_, resp)) => resp,
+                Err(e) => return Err(AttemptError::Transient(e.into())),
+            };
+            let status = resp.status();
+            let headers = resp.headers_names().into_iter()
+                .filter_map(|name| Some((name.to_ascii_lowercase(), resp.header(&name)?.to_string())))
+                .collect();
+            let mut body = Vec::new();
+            resp.into_reader().read_to_end(&mut body).map_err(|e| AttemptError::Transient(e.into()))?;
+            Ok(Response { status, headers, body })
+        })
+    }
+
+    fn with_retries<T>(&self, url: &str, mut op: impl FnMut(ureq::Request) -> Result<T, AttemptError>) -> Result<T> {
+        let mut attempt_no = 1;
+        loop {
+            match op(self.request(url)?) {
+                Ok(value) => return Ok(value),
+                Err(AttemptError::Fatal(e)) => return Err(e),
+                Err(AttemptError::Denied(code)) => return Err(self.denied(url, code)),
+                Err(AttemptError::Transient(e)) if attempt_no >= self.attempts => return Err(e),
+                Err(AttemptError::Transient(e)) => {
+                    let delay = backoff_delay(self.backoff, attempt_no);
+                    warn!("⚠️ Attempt {}/{} for {} failed: {:#}. Retrying in {:?}...", attempt_no, self.attempts, url, e, delay);
+                    thread::sleep(delay);
+                    attempt_no += 1;
+                }
+            }
+        }
+    }
+}
+
+fn attempt(mut request: ureq::Request, url: &str, validators: Option<&ArtifactMeta>) -> Result<Fetched, AttemptError> {
+    if let Some(prev) = validators {
+        if let Some(ref etag) = prev.etag {
+            request = request.set("If-None-Match", etag);
+        }
+        if let Some(ref last_modified) = prev.last_modified {
+            request = request.set("If-Modified-Since", last_modified);
+        }
+    }
+
+    let resp = call(request)?;
+    if resp.status() == 304 {
+        return Ok(Fetched::NotModified);
+    }
+
+    let meta = ArtifactMeta {
+        url: url.to_string(),
+        etag: resp.header("ETag").map(str::to_string),
+        last_modified: resp.header("Last-Modified").map(str::to_string),
+    };
+    // A connection dropped mid-body is transient as well
+    let mut body = Vec::new();
+    resp.into_reader().read_to_end(&mut body).map_err(|e| AttemptError::Transient(e.into()))?;
+    Ok(Fetched::Body(body, meta))
+}
+
+// `file://` URLs and anything without a scheme are read from the filesystem
+fn local_path(source: &str) -> Option<&Path> {
+    match source.split_once("://") {
+        Some(("file", path)) => Some(Path::new(path)),
+        Some(_) => None,
+        None => Some(Path::new(source)),
+    }
+}
+
+fn read_local(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).with_context(|| format!("Failed to read {:?}", path))
+}
+
+fn call(request: ureq::Request) -> Result<ureq::Response, AttemptError> {
+    match request.call() {
+        Ok(resp) => Ok(resp),
+        // Server errors and rate limiting are worth retrying; other statuses will not change
+        Err(ureq::Error::Status(code, _)) if code >= 500 || code == 429 => {
+            Err(AttemptError::Transient(anyhow!("HTTP {}", code)))
+        }
+        Err(ureq::Error::Status(code @ (401 | 403), _)) => Err(AttemptError::Denied(code)),
+        Err(ureq::Error::Status(code, _)) => Err(AttemptError::Fatal(anyhow!("HTTP {}", code))),
+        Err(e) => Err(AttemptError::Transient(e.into())),
+    }
+}
+
+// base * 2^(n-1), plus up to 50% jitter so that many devices do not retry in lockstep
+fn backoff_delay(base: Duration, attempt_no: u32) -> Duration {
+    let delay = base.saturating_mul(1 << (attempt_no - 1).min(6));
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+    let jitter = delay.mul_f64(f64::from(nanos % 1000) / 2000.0);
+    delay + jitter
+}
+
+// Proxy for a URL from the conventional environment variables (lower-case variants included)
+fn env_proxy_for(url: &str) -> Option<String> {
+    let var = |names: &[&str]| names.iter().find_map(|n| env::var(n).ok().filter(|v| !v.is_empty()));
+    let (scheme, rest) = url.split_once("://")?;
+    let host = rest.split(['/', '?', '#']).next()?.rsplit('@').next()?;
+    let host = host.rsplit_once(':').map_or(host, |(h, _)| h);
+
+    if let Some(no_proxy) = var(&["NO_PROXY", "no_proxy"]) {
+        let bypass = no_proxy.split(',').map(str::trim).filter(|p| !p.is_empty()).any(|pattern| {
+            let pattern = pattern.trim_start_matches('.');
+            pattern == "*" || host == pattern || host.ends_with(&format!(".{}", pattern))
+        });
+        if bypass {
+            return None;
+        }
+    }
+
+    match scheme {
+        "https" => var(&["HTTPS_PROXY", "https_proxy"]),
+        _ => var(&["HTTP_PROXY", "http_proxy"]),
+    }
+    .or_else(|| var(&["ALL_PROXY", "all_proxy"]))
+}
+
+// First mixed inbound of the rendered config, as a proxy URL, if something is listening on it
+fn detect_mixed_inbound(config: &Path) -> Option<String> {
+    let root: Value = serde_json::from_str(&fs::read_to_string(config).ok()?).ok()?;
+    let inbound = root.get("inbounds")?.as_array()?.iter()
+        .find(|i| i.get("type").and_then(Value::as_str) == Some("mixed"))?;
+    let port = u16::try_from(inbound.get("listen_port")?.as_u64()?).ok()?;
+
+    // Wildcard or IPv6 listeners are reached through loopback
+    let host = match inbound.get("listen").and_then(Value::as_str) {
+        Some(listen) if !listen.contains(':') && listen != "0.0.0.0" => listen,
+        _ => "127.0.0.1",
+    };
+    let addr: SocketAddr = format!("{}:{}", host, port).parse().ok()?;
+    TcpStream::connect_timeout(&addr, Duration::from_secs(1)).ok()?;
+
+    let user = inbound.get("users").and_then(Value::as_array).and_then(|u| u.first());
+    let credentials = user.and_then(|u| {
+        let username = u.get("username")?.as_str()?;
+        let password = u.get("password").and_then(Value::as_str).unwrap_or_default();
+        let encoded = utf8_percent_encode(password, NON_ALPHANUMERIC).to_string();
+        redact::register(password);
+        redact::register(&encoded);
+        Some(format!("{}:{}@", utf8_percent_encode(username, NON_ALPHANUMERIC), encoded))
+    });
+    Some(format!("http://{}{}:{}", credentials.unwrap_or_default(), host, port))
+}
+
+// Decoded "user:password" of an HTTP proxy URL
+fn http_proxy_credentials(proxy: &str) -> Option<String> {
+    let rest = match proxy.split_once("://") {
+        Some(("http", rest)) => rest,
+        Some(_) => return None,
+        None => proxy,
+    };
+    rest.rsplit_once('@').map(|(credentials, _)| decode_userinfo(credentials))
+}
+
+// Proxy URL with its percent-encoded credentials decoded
+fn decode_proxy_credentials(proxy: &str) -> String {
+    let (scheme, rest) = match proxy.split_once("://") {
+        Some((scheme, rest)) => (format!("{}://", scheme), rest),
+        None => (String::new(), proxy),
+    };
+    match rest.rsplit_once('@') {
+        Some((credentials, server)) => format!("{}{}@{}", scheme, decode_userinfo(credentials), server),
+        None => proxy.to_string(),
+    }
+}
+
+fn decode_userinfo(credentials: &str) -> String {
+    match credentials.split_once(':') {
+        Some((user, password)) => format!("{}:{}",
+            percent_decode_str(user).decode_utf8_lossy(),
+            percent_decode_str(password).decode_utf8_lossy()),
+        None => percent_decode_str(credentials).decode_utf8_lossy().into_owned(),
+    }
+}
+
+// Proxy URL without credentials, for logs and errors
+fn display_proxy(proxy: &str) -> String {
+    match (proxy.split_once("://"), proxy.rsplit_once('@')) {
+        (Some((scheme, _)), Some((_, server))) => format!("{}://***@{}", scheme, server),
+        _ => proxy.to_string(),
+    }
+}
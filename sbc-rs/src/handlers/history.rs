@@ -0,0 +1,189 @@
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use log::info;
+use sbc_rs::checksum;
+use sbc_rs::settings::Workspace;
+use std::collections::BTreeSet;
+use crate::handlers::{bundle, daemon, render};
+
+const ENTRY_META: &str = "entry.json";
+const TEMPLATE_FILE: &str = "config.template.json";
+const ENV_EXAMPLE_FILE: &str = ".env.example";
+
+#[derive(Serialize, Deserialize)]
+struct EntryMeta {
+    created: String,
+    template: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    env_example: Option<String>,
+    /// 记录时 etc/bundle 指向的模板包 ID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bundle: Option<String>,
+}
+
+struct Entry {
+    id: String,
+    dir: PathBuf,
+    meta: EntryMeta,
+}
+
+// 按 ID (时间戳) 升序列出全部历史版本，损坏的条目直接跳过
+fn list_entries(dir: &Path) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|e| {
+            let dir = e.path();
+            let meta = fs::read_to_string(dir.join(ENTRY_META)).ok()
+                .and_then(|s| serde_json::from_str(&s).ok())?;
+            Some(Entry { id: e.file_name().to_string_lossy().to_string(), dir, meta })
+        })
+        .collect();
+    entries.sort_by(|a, b| a.id.cmp(&b.id));
+    entries
+}
+
+fn file_hash(path: &Path) -> Option<String> {
+    fs::read(path).ok().map(|data| checksum::sha256_hex(&data))
+}
+
+/// 将当前模板 (及 .env.example) 存入历史；与最新版本相同时跳过。keep 为 0 时不记录
+pub fn record(ws: &Workspace, template: &Path, env_example: Option<&Path>, keep: usize) -> Result<Option<String>> {
+    let Some(template_hash) = file_hash(template).filter(|_| keep > 0) else {
+        return Ok(None);
+    };
+    let env_hash = env_example.and_then(file_hash);
+    let active_bundle = bundle::active(ws);
+
+    let dir = ws.history_dir();
+    let entries = list_entries(&dir);
+    if entries.last().is_some_and(|e| e.meta.template == template_hash && e.meta.env_example == env_hash && e.meta.bundle == active_bundle) {
+        return Ok(None);
+    }
+
+    // 同一秒内多次记录时追加序号
+    let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S").to_string();
+    let mut id = stamp.clone();
+    let mut n = 2;
+    while dir.join(&id).exists() {
+        id = format!("{}-{}", stamp, n);
+        n += 1;
+    }
+
+    let entry_dir = dir.join(&id);
+    fs::create_dir_all(&entry_dir)
+        .with_context(|| format!("无法创建历史目录: {:?}", entry_dir))?;
+    fs::copy(template, entry_dir.join(TEMPLATE_FILE))?;
+    if let (Some(path), Some(_)) = (env_example, &env_hash) {
+        fs::copy(path, entry_dir.join(ENV_EXAMPLE_FILE))?;
+    }
+    let meta = EntryMeta {
+        created: chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+        template: template_hash,
+        env_example: env_hash,
+        bundle: active_bundle,
+    };
+    fs::write(entry_dir.join(ENTRY_META), serde_json::to_string_pretty(&meta)?)?;
+
+    // 只保留最近 keep 个版本
+    let entries = list_entries(&dir);
+    for old in entries.iter().take(entries.len().saturating_sub(keep)) {
+        let _ = fs::remove_dir_all(&old.dir);
+    }
+
+    Ok(Some(id))
+}
+
+/// 历史版本引用的模板包 ID，清理旧包时须保留
+pub fn bundles(ws: &Workspace) -> BTreeSet<String> {
+    list_entries(&ws.history_dir()).into_iter().filter_map(|e| e.meta.bundle).collect()
+}
+
+pub fn handle_history(ws: &Workspace, template: PathBuf, json: bool) -> Result<()> {
+    let entries = list_entries(&ws.history_dir());
+    let current = file_hash(&template);
+
+    if json {
+        let list: Vec<_> = entries.iter().map(|e| serde_json::json!({
+            "id": e.id,
+            "created": e.meta.created,
+            "template": e.meta.template,
+            "env_example": e.meta.env_example,
+            "bundle": e.meta.bundle,
+            "current": current.as_ref() == Some(&e.meta.template),
+        })).collect();
+        println!("{}", serde_json::to_string_pretty(&list)?);
+        return Ok(());
+    }
+
+    if entries.is_empty() {
+        println!("暂无历史版本 ({:?})", ws.history_dir());
+        return Ok(());
+    }
+    println!("{:<20} {:<26} {:<14} .env.example", "ID", "时间", "模板哈希");
+    for e in entries.iter().rev() {
+        let marker = if current.as_ref() == Some(&e.meta.template) { "  ← 当前" } else { "" };
+        println!(
+            "{:<20} {:<26} {:<14} {}{}",
+            e.id,
+            e.meta.created,
+            e.meta.template.get(..12).unwrap_or(&e.meta.template),
+            if e.meta.env_example.is_some() { "有" } else { "-" },
+            marker,
+        );
+    }
+    Ok(())
+}
+
+pub fn handle_rollback(ws: &Workspace, id: Option<String>, template: Option<PathBuf>, env_example: Option<PathBuf>, config: Option<PathBuf>, check: bool) -> Result<()> {
+    let template = template.unwrap_or_else(|| ws.template());
+    let env_example = env_example.unwrap_or_else(|| ws.env_example());
+    let entries = list_entries(&ws.history_dir());
+    let current = file_hash(&template);
+
+    // 1. 选择目标版本：指定 ID，或最近一个与当前模板不同的版本
+    let target = match id {
+        Some(ref id) => entries.iter().find(|e| &e.id == id)
+            .with_context(|| format!("找不到历史版本: {}", id))?,
+        None => match entries.iter().rev().find(|e| current.as_ref() != Some(&e.meta.template)) {
+            Some(e) => e,
+            None => bail!("没有可回滚的历史版本"),
+        },
+    };
+    if let Some(ref id) = target.meta.bundle
+        && !bundle::installed(ws, id)
+    {
+        bail!("历史版本 {} 所属的模板包 {} 已被清理，无法回滚", target.id, id);
+    }
+    info!("⏪ 回滚到历史版本 {} ({})", target.id, target.meta.created);
+
+    // 先读入目标内容，再记录当前版本 (记录时的清理可能删除目标目录)
+    let template_body = fs::read(target.dir.join(TEMPLATE_FILE))?;
+    let env_body = fs::read(target.dir.join(ENV_EXAMPLE_FILE)).ok();
+    record(ws, &template, Some(&env_example), ws.settings.update.keep_history.max(entries.len()))?;
+
+    // 2. 属于模板包的版本重新指向该包，否则原子替换模板文件
+    if let Some(ref id) = target.meta.bundle {
+        bundle::activate(ws, &template, &env_example, id)?;
+    } else {
+        let tmp_path = template.with_extension("tmp");
+        fs::write(&tmp_path, template_body)?;
+        fs::rename(&tmp_path, &template)?;
+        if let Some(body) = env_body {
+            let tmp_env = env_example.with_extension("tmp");
+            fs::write(&tmp_env, body)?;
+            fs::rename(&tmp_env, &env_example)?;
+        }
+    }
+    info!("✅ 模板已恢复: {:?}", template);
+
+    // 3. 重新渲染
+    let env_path = ws.env_file();
+    daemon::load_env_file(&env_path)
+        .with_context(|| format!("加载 .env 文件失败: {:?}", env_path))?;
+    let opts = render::workspace_options(ws, None, check);
+    render::handle_render(template, config.unwrap_or_else(|| ws.config()), &opts)
+}
@@ -1,3 +1,14 @@
 pub mod render;
 pub mod update;
 pub mod daemon;
+pub mod check;
+pub mod fetch;
+pub mod vars;
+pub mod history;
+pub mod env;
+pub mod settings;
+pub mod channel;
+pub mod self_update;
+pub mod bundle;
+pub mod ruleset;
+pub mod sub;
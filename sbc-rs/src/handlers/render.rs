@@ -1,220 +1,128 @@
 use anyhow::{Context, Result};
-use serde_json::{Value, Map};
-use std::collections::HashMap;
 use std::env;
 use std::fs;
-use std::path::PathBuf;
-use log::{warn, info}; // Added info for new log messages
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use log::info;
+use sbc_rs::renderer::{OutputFormat, Renderer};
+use sbc_rs::{ruleset, script, subscription};
+use sbc_rs::settings::{RulesetSettings, Workspace};
+use crate::handlers::check;
 
-pub fn handle_render(template: PathBuf, output: PathBuf) -> Result<()> {
-    // 1. 收集环境变量
-    let env_vars: HashMap<String, String> = env::vars().collect();
-
-    // 2. 读取模板
-    info!("正在读取模板文件: {:?}", template); // Added info log
-    let template_content = fs::read_to_string(&template)
-        .with_context(|| format!("读取模板文件失败: {:?}", template))?;
-
-    // 2.1 移除注释
-    let json_content = strip_comments(&template_content);
+// `-` 表示标准输入/输出
+fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
 
-    // 3. 解析模板为 JSON
-    let root: Value = serde_json::from_str(&json_content)
-        .context("无法将模板解析为有效的 JSON。请确保输入格式正确。")?;
+#[derive(Default)]
+pub struct RenderOptions {
+    /// 写入前使用 `sing-box check` 预检
+    pub check: bool,
+    pub format: OutputFormat,
+    /// 内容未变化时跳过写入
+    pub skip_unchanged: bool,
+    /// 存在未设置的变量时渲染失败
+    pub strict: bool,
+    /// 渲染脚本 (Rhai)
+    pub script: Option<PathBuf>,
+    /// 将已缓存的远程规则集改写为本地文件 (缓存目录与策略)
+    pub local_rulesets: Option<(PathBuf, RulesetSettings)>,
+    /// 将 providers 的订阅地址改写到本地订阅网关 (缓存目录与监听地址)
+    pub subscription_gateway: Option<(PathBuf, String)>,
+}
 
-    // 4. 处理抽象语法树 (AST)
-    let processed_root = process_value(root, &env_vars)?;
+/// 设置中开启 ruleset.local 时返回规则集缓存目录与策略
+pub fn local_rulesets(ws: &Workspace) -> Option<(PathBuf, RulesetSettings)> {
+    ws.settings.ruleset.local.then(|| (ws.ruleset_cache(), ws.settings.ruleset.clone()))
+}
 
-    // 5. 写入输出
-    let output_content = serde_json::to_string_pretty(&processed_root)?;
-    fs::write(&output, output_content)
-        .with_context(|| format!("写入输出文件失败: {:?}", output))?;
-    
-    info!("渲染完成，输出文件已写入: {:?}", output); // Added info log
-    Ok(())
+/// 设置中开启 subscription.gateway 时返回订阅缓存目录与网关监听地址
+pub fn subscription_gateway(ws: &Workspace) -> Option<(PathBuf, String)> {
+    ws.settings.subscription.gateway.then(|| (ws.subscription_cache(), ws.settings.subscription.listen.clone()))
 }
 
-fn process_value(v: Value, env: &HashMap<String, String>) -> Result<Value> {
-    match v {
-        Value::Object(map) => {
-            let mut new_map = Map::new();
-            for (k, v) in map {
-                let processed_v = process_value(v, env)?;
-                new_map.insert(k, processed_v);
-            }
-            Ok(Value::Object(new_map))
-        }
-        Value::Array(arr) => {
-            let mut new_arr = Vec::new();
-            for v in arr {
-                // 检查数组项级别的 {{VAR}} (Magic Unwrap 候选)
-                if let Value::String(ref s) = v {
-                    if let Some(var_name) = extract_structural_placeholder(s) {
-                        if let Some(parsed_val) = resolve_env_var(var_name, env)? {
-                            // Magic Unwrap: 如果是数组则展开
-                            if let Value::Array(inner_arr) = parsed_val {
-                                info!("发现数组占位符 {{{{{}}}}}，正在展开数组。", var_name); // Added info log
-                                for inner_item in inner_arr {
-                                    new_arr.push(process_value(inner_item, env)?);
-                                }
-                            } else {
-                                // 不是数组，直接添加
-                                new_arr.push(process_value(parsed_val, env)?);
-                            }
-                        } else {
-                            warn!("数组中的占位符 {{{{{}}}}} 未找到或为空，跳过该项。", var_name);
-                        }
-                        continue;
-                    }
-                }
-                new_arr.push(process_value(v, env)?);
-            }
-            Ok(Value::Array(new_arr))
-        }
-        Value::String(s) => {
-            // 通用字符串处理
-            // 1. 检查结构化替换 {{VAR}} (有效的 JSON 对象替换)
-            if let Some(var_name) = extract_structural_placeholder(&s) {
-                if let Some(parsed_val) = resolve_env_var(var_name, env)? {
-                    info!("发现结构化占位符 {{{{{}}}}}，正在替换为解析后的值。", var_name); // Added info log
-                    return process_value(parsed_val, env);
-                } else {
-                    warn!("值中的占位符 {{{{{}}}}} 未找到或为空，保留原样。", var_name);
-                    return Ok(Value::String(s));
-                }
-            }
-            
-            // 2. 字符串插值 ${VAR}
-            Ok(Value::String(interpolate_string(&s, env)))
-        }
-        _ => Ok(v),
+/// 工作空间的渲染选项：未指定脚本时使用工作空间中的渲染脚本 (若存在)，并按设置改写规则集与订阅地址。
+/// `run`、`rollback` 与 `update` 共用，保证三者渲染出的配置一致
+pub fn workspace_options(ws: &Workspace, script: Option<PathBuf>, check: bool) -> RenderOptions {
+    let default_script = ws.script();
+    RenderOptions {
+        check,
+        script: script.or_else(|| default_script.exists().then_some(default_script)),
+        local_rulesets: local_rulesets(ws),
+        subscription_gateway: subscription_gateway(ws),
+        ..Default::default()
     }
 }
 
-// 辅助函数：查找并解析环境变量为 JSON
-fn resolve_env_var(var_name: &str, env: &HashMap<String, String>) -> Result<Option<Value>> {
-    if let Some(env_val) = env.get(var_name) {
-        let env_val = env_val.trim();
-        if env_val.is_empty() {
-            return Ok(None);
-        }
-        let parsed: Value = serde_json::from_str(env_val)
-            .with_context(|| format!("无法将环境变量 '{}' 解析为 JSON: {}", var_name, env_val))?;
-        Ok(Some(parsed))
-    } else {
-        Ok(None)
+/// 按选项组装渲染管线：当前进程的环境变量，依次执行脚本、本地规则集与订阅网关改写。
+/// `render` 与 `update` 比较有效配置时共用，保证两者的结果一致
+pub fn renderer(opts: &RenderOptions) -> Renderer {
+    let mut renderer = Renderer::new().process_env().strict(opts.strict);
+    if let Some(ref script_path) = opts.script {
+        renderer = renderer.filter(script::script_filter(script_path.clone()));
+    }
+    // 在脚本之后执行，脚本新增的远程规则集同样会被改写
+    if let Some((ref cache_dir, ref settings)) = opts.local_rulesets {
+        renderer = renderer.filter(ruleset::local_filter(cache_dir.clone(), settings.clone()));
+    }
+    if let Some((ref cache_dir, ref listen)) = opts.subscription_gateway {
+        renderer = renderer.filter(subscription::gateway_filter(cache_dir.clone(), listen.clone()));
     }
+    renderer
 }
 
-// 检查精确的 "{{VAR}}" 模式
-fn extract_structural_placeholder(s: &str) -> Option<&str> {
-    if s.starts_with("{{") && s.ends_with("}}") {
-        // 提取内容
-        let content = &s[2..s.len()-2];
-        // 确保严格的字母数字/下划线以避免误报？
-        // 实际上，在这种情况下，仅检查括号就足以作为强信号。
-        Some(content.trim())
+pub fn handle_render(template: PathBuf, output: PathBuf, opts: &RenderOptions) -> Result<()> {
+    // 1. 读取模板
+    let template_content = if is_stdio(&template) {
+        info!("正在从标准输入读取模板...");
+        let mut buf = String::new();
+        io::stdin().read_to_string(&mut buf).context("读取标准输入失败")?;
+        buf
     } else {
-        None
-    }
-}
+        info!("正在读取模板文件: {:?}", template);
+        fs::read_to_string(&template)
+            .with_context(|| format!("读取模板文件失败: {:?}", template))?
+    };
+
+    // 2. 使用当前进程的环境变量渲染
+    let rendered = renderer(opts).render_str(&template_content)?;
+    let output_content = opts.format.to_string(&rendered.value)?;
 
-// 简单地插值 ${VAR}
-fn interpolate_string(s: &str, env: &HashMap<String, String>) -> String {
-    let mut result = s.to_string();
-    // 逻辑：查找 ${...} 块并替换。
-    // 迭代替换。
-    // 注意：这个简单的实现不处理转义。
-    // 假设配置不会将 ${} 用于其他目的。
-    
-    let mut search_start = 0;
-    while let Some(start_idx) = result[search_start..].find("${") {
-        let abs_start = search_start + start_idx;
-        if let Some(end_offset) = result[abs_start..].find('}') {
-            let abs_end = abs_start + end_offset;
-            let var_name = &result[abs_start+2..abs_end];
-            
-            // 检查是否主要是字母数字
-            if var_name.chars().all(|c| c.is_alphanumeric() || c == '_') {
-                if let Some(val) = env.get(var_name) {
-                     result.replace_range(abs_start..=abs_end, val);
-                     // 调整 search_start 以避免如果 val 包含 ${...} 时的无限循环（我们通常不递归插值环境变量值）
-                     search_start = abs_start + val.len();
-                } else {
-                    // 变量未找到。是保持严格还是原样？
-                    // 通常，如果期望值，保持原样可能会破坏配置。
-                    // 但 shell 行为是空字符串。
-                    // 让我们替换为空字符串？或者保留原始字面量？
-                    // 用户说“传统 shell 构造”，通常 envsubst 替换为空。
-                    // 为了健壮的清理，让我们替换为空。
-                    // 但是：也许警告？
-                    warn!("变量 ${{{}}} 未找到，替换为空字符串。", var_name);
-                    result.replace_range(abs_start..=abs_end, "");
-                    search_start = abs_start;
-                }
-            } else {
-                // 不是有效的变量名，跳过
-                search_start = abs_end + 1;
-            }
-        } else {
-            break;
-        }
+    // 3. 内容未变化时跳过写入
+    if opts.skip_unchanged && !is_stdio(&output)
+        && fs::read_to_string(&output).is_ok_and(|old| old == output_content)
+    {
+        info!("输出内容未变化，跳过写入: {:?}", output);
+        return Ok(());
     }
-    result
-}
 
-fn strip_comments(input: &str) -> String {
-    let mut out = String::with_capacity(input.len());
-    let mut chars = input.chars().peekable();
-    let mut in_quote = false;
-    let mut escaped = false;
+    // 4. 写入临时文件 (输出到标准输出时放在系统临时目录，仅供预检使用)
+    let tmp_output = if is_stdio(&output) {
+        env::temp_dir().join(format!("sbc-rs-render-{}.json", std::process::id()))
+    } else {
+        output.with_extension("tmp")
+    };
+    fs::write(&tmp_output, &output_content)
+        .with_context(|| format!("写入输出文件失败: {:?}", tmp_output))?;
+
+    // 5. 预检 (可选)：仅在 sing-box check 通过后才替换现有配置
+    if opts.check && let Err(e) = check::check_config(&tmp_output) {
+        let _ = fs::remove_file(&tmp_output);
+        return Err(e.context("渲染结果未通过 sing-box check，已保留现有配置"));
+    }
 
-    while let Some(c) = chars.next() {
-        if in_quote {
-            out.push(c);
-            if escaped {
-                escaped = false;
-            } else if c == '\\' {
-                escaped = true;
-            } else if c == '"' {
-                in_quote = false;
-            }
-        } else {
-            // 检查注释开始
-            if c == '/' {
-                if let Some(&next_c) = chars.peek() {
-                    if next_c == '/' {
-                        // 行注释: 跳过直到换行符
-                        chars.next(); // 消耗第二个 /
-                        while let Some(&nc) = chars.peek() {
-                            if nc == '\n' {
-                                break;
-                            }
-                            chars.next();
-                        }
-                        continue;
-                    } else if next_c == '*' {
-                        // Block comment: skip until */
-                        chars.next(); // consume *
-                        while let Some(nc) = chars.next() {
-                            if nc == '*' {
-                                if let Some(&nnc) = chars.peek() {
-                                    if nnc == '/' {
-                                        chars.next(); // consume /
-                                        break;
-                                    }
-                                }
-                            }
-                        }
-                        continue;
-                    }
-                }
-            }
-            if c == '"' {
-                in_quote = true;
-            }
-            out.push(c);
-        }
+    // 6. 输出到标准输出或原子替换
+    if is_stdio(&output) {
+        let _ = fs::remove_file(&tmp_output);
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(output_content.as_bytes())?;
+        stdout.write_all(b"\n")?;
+        return Ok(());
     }
-    out
+    fs::rename(&tmp_output, &output)
+        .with_context(|| format!("写入输出文件失败: {:?}", output))?;
+
+    info!("渲染完成，输出文件已写入: {:?} (替换 {} 处，未设置变量 {} 个)",
+        output, rendered.report.substitutions.len(), rendered.report.missing.len());
+    Ok(())
 }
@@ -0,0 +1,200 @@
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use log::{info, warn};
+use sbc_rs::{checksum, script};
+use sbc_rs::renderer::Renderer;
+use sbc_rs::ruleset::{self, CacheEntry, CacheIndex, INDEX_FILE, RemoteRuleSet};
+use sbc_rs::settings::Workspace;
+use crate::cli::FetchArgs;
+use crate::handlers::daemon;
+use crate::handlers::fetch::{ArtifactMeta, Fetched, Fetcher};
+
+// 二进制规则集 (.srs) 的文件头
+const SRS_MAGIC: &[u8] = b"SRS";
+
+// 单个规则集的同步结果
+#[derive(Serialize)]
+struct SyncResult {
+    tag: String,
+    url: String,
+    /// downloaded | unchanged | failed
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip)]
+    entry: Option<CacheEntry>,
+}
+
+// 拒绝明显不是规则集的内容 (如镜像返回的 HTML 错误页)，避免覆盖可用的缓存
+fn validate(file: &str, data: &[u8]) -> Result<()> {
+    if file.ends_with(".json") {
+        serde_json::from_slice::<serde_json::Value>(data).context("不是有效的 JSON 规则集")?;
+    } else if !data.starts_with(SRS_MAGIC) {
+        bail!("不是有效的二进制规则集 (.srs)");
+    }
+    Ok(())
+}
+
+fn sync_one(fetcher: &Fetcher, cache_dir: &Path, rule_set: &RemoteRuleSet, previous: Option<&CacheEntry>) -> SyncResult {
+    let file = ruleset::cache_file_name(rule_set);
+    let path = cache_dir.join(&file);
+    let now = chrono::Utc::now().to_rfc3339();
+    // 仅当本地文件完好时才发送条件请求，否则强制重新下载
+    let intact = previous.filter(|p| p.file == file)
+        .filter(|p| fs::read(&path).is_ok_and(|data| checksum::sha256_hex(&data) == p.sha256));
+    let meta = previous.map(|p| ArtifactMeta {
+        url: rule_set.url.clone(),
+        etag: p.etag.clone(),
+        last_modified: p.last_modified.clone(),
+    });
+
+    let result = (|| -> Result<(&'static str, CacheEntry)> {
+        match fetcher.fetch(std::slice::from_ref(&rule_set.url), meta.as_ref(), intact.is_some())? {
+            Fetched::NotModified => {
+                let mut entry = intact.cloned().context("服务器返回 304，但本地没有可用的缓存")?;
+                entry.checked = now;
+                Ok(("unchanged", entry))
+            }
+            Fetched::Body(data, meta) => {
+                validate(&file, &data)?;
+                let sha256 = checksum::sha256_hex(&data);
+                // 内容相同时只刷新确认时间，不重写文件
+                let same = intact.filter(|p| p.sha256 == sha256);
+                if same.is_none() {
+                    let tmp = path.with_extension("tmp");
+                    fs::write(&tmp, &data)?;
+                    fs::rename(&tmp, &path).with_context(|| format!("写入缓存文件失败: {:?}", path))?;
+                }
+                let status = if same.is_some() { "unchanged" } else { "downloaded" };
+                let fetched = same.map_or_else(|| now.clone(), |p| p.fetched.clone());
+                Ok((status, CacheEntry {
+                    tag: rule_set.tag.clone(),
+                    file: file.clone(),
+                    sha256,
+                    size: data.len() as u64,
+                    fetched,
+                    checked: now,
+                    etag: meta.etag,
+                    last_modified: meta.last_modified,
+                }))
+            }
+        }
+    })();
+
+    match result {
+        Ok((status, entry)) => SyncResult {
+            tag: rule_set.tag.clone(),
+            url: rule_set.url.clone(),
+            status,
+            file: Some(path.to_string_lossy().into()),
+            error: None,
+            entry: Some(entry),
+        },
+        Err(e) => {
+            warn!("⚠️ 规则集 {} 同步失败: {:#}", rule_set.tag, e);
+            SyncResult {
+                tag: rule_set.tag.clone(),
+                url: rule_set.url.clone(),
+                status: "failed",
+                file: None,
+                error: Some(format!("{:#}", e)),
+                entry: None,
+            }
+        }
+    }
+}
+
+// 删除不再被索引引用的缓存文件 (含中断留下的临时文件)
+fn prune(cache_dir: &Path, index: &CacheIndex) {
+    let keep: BTreeSet<&str> = index.entries.values().map(|e| e.file.as_str()).collect();
+    for entry in fs::read_dir(cache_dir).into_iter().flatten().flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name == INDEX_FILE || keep.contains(name.as_str()) {
+            continue;
+        }
+        match fs::remove_file(entry.path()) {
+            Ok(()) => info!("🧹 已删除不再引用的规则集缓存: {}", name),
+            Err(e) => warn!("⚠️ 无法删除缓存文件 {:?}: {}", entry.path(), e),
+        }
+    }
+}
+
+pub fn handle_ruleset_sync(ws: &Workspace, template: Option<PathBuf>, env_file: Option<PathBuf>, jobs: Option<usize>, json: bool, mut fetch: FetchArgs) -> Result<()> {
+    let settings = &ws.settings.ruleset;
+    let template = template.unwrap_or_else(|| ws.template());
+    let env_file = env_file.unwrap_or_else(|| ws.env_file());
+    let cache_dir = ws.ruleset_cache();
+    if fetch.proxy.is_none() {
+        fetch.proxy = ws.settings.update.proxy.clone();
+    }
+
+    // 1. 按运行时的方式渲染模板 (不做本地化)，收集远程规则集
+    let mut renderer = Renderer::new().process_env();
+    if env_file.exists() {
+        renderer = renderer.vars(daemon::read_env_file(&env_file)
+            .with_context(|| format!("无法读取 .env 文件: {:?}", env_file))?);
+    }
+    if ws.script().exists() {
+        renderer = renderer.filter(script::script_filter(ws.script()));
+    }
+    let content = fs::read_to_string(&template)
+        .with_context(|| format!("读取模板文件失败: {:?}", template))?;
+    let rendered = renderer.render_str(&content)?;
+    let rule_sets = ruleset::remote_rule_sets(&rendered.value);
+    info!("📚 模板中共有 {} 个远程规则集，缓存目录: {:?}", rule_sets.len(), cache_dir);
+
+    // 2. 并行下载
+    fs::create_dir_all(&cache_dir).with_context(|| format!("无法创建缓存目录: {:?}", cache_dir))?;
+    let mut index = CacheIndex::load(&cache_dir);
+    let fetcher = Fetcher::new(&fetch, &ws.config())?
+        .with_auth(&ws.settings.update.auth, &env_file)?;
+    let next = AtomicUsize::new(0);
+    let workers = jobs.unwrap_or(settings.jobs).clamp(1, rule_sets.len().max(1));
+    let mut results: Vec<(usize, SyncResult)> = thread::scope(|s| {
+        let handles: Vec<_> = (0..workers).map(|_| s.spawn(|| {
+            let mut done = Vec::new();
+            loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let Some(rule_set) = rule_sets.get(i) else { break };
+                done.push((i, sync_one(&fetcher, &cache_dir, rule_set, index.entries.get(&rule_set.url))));
+            }
+            done
+        })).collect();
+        handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+    });
+    results.sort_by_key(|(i, _)| *i);
+    let results: Vec<SyncResult> = results.into_iter().map(|(_, r)| r).collect();
+
+    // 3. 更新索引：失败的规则集保留旧缓存，模板中已不存在的规则集移出索引
+    index.entries.retain(|url, _| rule_sets.iter().any(|r| &r.url == url));
+    for result in &results {
+        if let Some(ref entry) = result.entry {
+            index.entries.insert(result.url.clone(), entry.clone());
+        }
+    }
+    index.save(&cache_dir)?;
+    prune(&cache_dir, &index);
+
+    // 4. 输出结果
+    if json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    } else {
+        for r in &results {
+            let detail = r.error.as_deref().unwrap_or_else(|| r.file.as_deref().unwrap_or_default());
+            println!("{:<24} {:<10} {}", r.tag, r.status, detail);
+        }
+    }
+    let failed = results.iter().filter(|r| r.status == "failed").count();
+    if failed > 0 {
+        bail!("❌ {} 个规则集同步失败 (已有缓存保持不变)", failed);
+    }
+    info!("✅ 规则集同步完成: {} 个", results.len());
+    Ok(())
+}
@@ -0,0 +1,268 @@
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use log::{info, error, warn};
+use sbc_rs::{checksum, signature};
+use sbc_rs::settings::Workspace;
+use crate::cli::SelfUpdateArgs;
+use crate::handlers::{daemon, update};
+use crate::handlers::fetch::Fetcher;
+use crate::handlers::update::UpdateOutcome;
+
+// Binaries that may be replaced, with the arguments used to smoke test a downloaded copy
+const BINARIES: [(&str, &str); 2] = [("sbc-rs", "--version"), ("sing-box", "version")];
+
+// update.json as published for the KernelSU manager, plus the per-binary artifacts
+#[derive(Deserialize)]
+struct Manifest {
+    version: String,
+    #[serde(rename = "versionCode")]
+    version_code: u64,
+    #[serde(rename = "zipUrl", default)]
+    zip_url: Option<String>,
+    #[serde(default)]
+    binaries: BTreeMap<String, Artifact>,
+}
+
+#[derive(Deserialize)]
+struct Artifact {
+    url: String,
+    sha256: String,
+}
+
+// A verified download waiting next to the binary it replaces
+struct Staged {
+    target: PathBuf,
+    staged: PathBuf,
+    backup: PathBuf,
+}
+
+// Plain key=value parser for module.prop
+fn read_prop(path: &Path) -> BTreeMap<String, String> {
+    fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|l| l.split_once('='))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .collect()
+}
+
+// Rewrite version/versionCode in module.prop so the manager and the next check see the new release
+fn write_prop_version(path: &Path, manifest: &Manifest) -> Result<()> {
+    let content = fs::read_to_string(path)?;
+    let mut updated: Vec<String> = content.lines().map(|line| {
+        match line.split_once('=').map(|(k, _)| k.trim()) {
+            Some("version") => format!("version={}", manifest.version),
+            Some("versionCode") => format!("versionCode={}", manifest.version_code),
+            _ => line.to_string(),
+        }
+    }).collect();
+    updated.push(String::new());
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, updated.join("\n"))?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+// Installed versionCode: module.prop first, otherwise derived from the build time (CI uses %Y%m%d%H)
+fn installed_version(ws: &Workspace) -> (u64, &'static str) {
+    if let Some(code) = read_prop(&ws.settings.self_update.module_prop).get("versionCode").and_then(|v| v.parse().ok()) {
+        return (code, "module.prop");
+    }
+    let code = chrono::DateTime::parse_from_rfc3339(crate::build::BUILD_TIME_3339)
+        .ok()
+        .and_then(|t| t.to_utc().format("%Y%m%d%H").to_string().parse().ok())
+        .unwrap_or_default();
+    (code, "build time")
+}
+
+fn manifest_url(args: &SelfUpdateArgs, ws: &Workspace) -> Result<String> {
+    let settings = &ws.settings.self_update;
+    args.manifest_url.clone()
+        .or_else(|| settings.manifest_url.clone())
+        .or_else(|| read_prop(&settings.module_prop).remove("updateJson"))
+        .with_context(|| format!(
+            "❌ No update manifest given (--manifest-url), configured (self_update.manifest_url) or found in {:?}.",
+            settings.module_prop
+        ))
+}
+
+// The detached signature sits next to update.json; a cache-busting query (module.prop adds `?t=`) stays at the end
+fn signature_url(url: &str) -> String {
+    match url.split_once('?') {
+        Some((path, query)) => format!("{}{}?{}", path, signature::SIGNATURE_SUFFIX, query),
+        None => format!("{}{}", url, signature::SIGNATURE_SUFFIX),
+    }
+}
+
+// Download, verify and smoke test one binary into `<target>.new`
+fn stage(fetcher: &Fetcher, target: &Path, artifact: &Artifact, smoke_arg: &str) -> Result<Staged> {
+    info!("Downloading {} from: {}", target.display(), artifact.url);
+    let body = fetcher.get_bytes(&artifact.url)
+        .with_context(|| format!("Failed to download {}", artifact.url))?;
+    let actual = checksum::sha256_hex(&body);
+    if !actual.eq_ignore_ascii_case(&artifact.sha256) {
+        bail!("❌ Checksum mismatch for {}: expected {}, got {}", artifact.url, artifact.sha256, actual);
+    }
+    info!("🔐 Checksum verified: {}", actual);
+
+    let staged = target.with_extension("new");
+    fs::write(&staged, &body)?;
+    fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))?;
+    // A binary for the wrong architecture (or a truncated one) fails here, before anything is replaced
+    let output = Command::new(&staged).arg(smoke_arg).output();
+    match output {
+        Ok(out) if out.status.success() => {}
+        Ok(out) => {
+            let _ = fs::remove_file(&staged);
+            bail!("❌ Downloaded {} does not run ({})", target.display(), out.status);
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&staged);
+            return Err(anyhow::Error::new(e).context(format!("❌ Downloaded {} does not run", target.display())));
+        }
+    }
+    Ok(Staged { target: target.to_path_buf(), backup: target.with_extension("old"), staged })
+}
+
+// Move the current binaries aside and the staged ones into place
+fn swap(staged: &[Staged]) -> Result<()> {
+    for (i, s) in staged.iter().enumerate() {
+        let result = (|| -> Result<()> {
+            if s.target.exists() {
+                fs::rename(&s.target, &s.backup)?;
+            }
+            fs::rename(&s.staged, &s.target)?;
+            Ok(())
+        })();
+        if let Err(e) = result {
+            restore(&staged[..=i]);
+            return Err(e.context(format!("Failed to install {:?}", s.target)));
+        }
+    }
+    Ok(())
+}
+
+// Put the previous binaries back
+fn restore(staged: &[Staged]) {
+    for s in staged {
+        if s.backup.exists() && let Err(e) = fs::rename(&s.backup, &s.target) {
+            error!("❌ Failed to restore {:?}: {}", s.target, e);
+        }
+    }
+}
+
+// The new binaries are in place and running, the previous ones are no longer needed
+fn discard_backups(staged: &[Staged]) {
+    for s in staged {
+        match fs::remove_file(&s.backup) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => warn!("⚠️ Failed to remove {:?}: {}", s.backup, e),
+            _ => {}
+        }
+    }
+}
+
+pub fn handle_self_update(mut args: SelfUpdateArgs, ws: &Workspace) -> Result<UpdateOutcome> {
+    if args.fetch.proxy.is_none() {
+        args.fetch.proxy = ws.settings.update.proxy.clone();
+    }
+    let url = manifest_url(&args, ws)?;
+    let fetcher = Fetcher::new(&args.fetch, &ws.config())?;
+
+    info!("Checking for updates: {}", url);
+    let body = fetcher.get_string(&url).with_context(|| format!("Failed to download {}", url))?;
+    // The manifest carries the checksums of everything it installs, so it has to come from the publisher
+    update::verify_pinned(Some(&ws.pubkey()), false, ws.settings.update.allow_unpinned, "Update manifest", body.as_bytes(), || {
+        let sig_url = signature_url(&url);
+        info!("Downloading signature from: {}", sig_url);
+        fetcher.get_string(&sig_url).with_context(|| format!("Failed to download signature from {}", sig_url))
+    })?;
+    let manifest: Manifest = serde_json::from_str(&body).with_context(|| format!("Invalid update manifest: {}", url))?;
+
+    let (current, source) = installed_version(ws);
+    info!("Installed versionCode {} ({}), available {} ({}).", current, source, manifest.version_code, manifest.version);
+    if manifest.version_code <= current && !args.force {
+        info!("✅ Already up to date.");
+        return Ok(UpdateOutcome::Unchanged);
+    }
+    if manifest.binaries.is_empty() {
+        bail!(
+            "❌ Manifest lists no binaries; this release can only be installed by flashing {}",
+            manifest.zip_url.as_deref().unwrap_or("the module zip")
+        );
+    }
+
+    // Only binaries that actually differ from the installed ones are replaced
+    let bin_dir = ws.bin_dir();
+    let mut plan = Vec::new();
+    for (name, artifact) in &manifest.binaries {
+        let Some(&(_, smoke_arg)) = BINARIES.iter().find(|(n, _)| n == name) else {
+            warn!("⚠️ Ignoring unknown binary in manifest: {}", name);
+            continue;
+        };
+        let target = bin_dir.join(name);
+        let installed = fs::read(&target).ok().map(|data| checksum::sha256_hex(&data));
+        if installed.is_some_and(|h| h.eq_ignore_ascii_case(&artifact.sha256)) {
+            info!("{} is already current.", name);
+            continue;
+        }
+        plan.push((target, artifact, smoke_arg));
+    }
+
+    if args.check {
+        for (target, artifact, _) in &plan {
+            println!("{} <- {} (sha256 {})", target.display(), artifact.url, artifact.sha256);
+        }
+        if plan.is_empty() {
+            println!("All binaries are current; only the version number would change.");
+        }
+        return Ok(UpdateOutcome::Updated);
+    }
+
+    let mut staged = Vec::new();
+    for (target, artifact, smoke_arg) in &plan {
+        match stage(&fetcher, target, artifact, smoke_arg) {
+            Ok(s) => staged.push(s),
+            Err(e) => {
+                for s in &staged {
+                    let _ = fs::remove_file(&s.staged);
+                }
+                return Err(e);
+            }
+        }
+    }
+
+    if !staged.is_empty() {
+        let running = daemon::supervisor_pid(ws).is_some();
+        swap(&staged)?;
+        info!("✅ Installed {} binary(ies) into {:?}.", staged.len(), bin_dir);
+
+        if running && args.no_restart {
+            // The service still runs the previous binaries, so they stay as *.old until the new ones have started
+            info!("Service not restarted (--no-restart); the previous binaries are kept as *.old.");
+        } else {
+            if !running {
+                info!("Service not restarted: it is not running.");
+            } else if let Err(e) = daemon::restart_and_check(ws, ws.settings.self_update.health_check_secs) {
+                // Roll back to the binaries that were running before and bring the service back up
+                error!("❌ New binaries failed to start: {:#}", e);
+                let _ = daemon::handle_stop(ws);
+                restore(&staged);
+                daemon::spawn_supervisor(ws)?;
+                bail!("❌ Self-update rolled back to the previous binaries");
+            }
+            discard_backups(&staged);
+        }
+    }
+
+    let prop = &ws.settings.self_update.module_prop;
+    if prop.exists() && let Err(e) = write_prop_version(prop, &manifest) {
+        warn!("⚠️ Failed to update {:?}: {}", prop, e);
+    }
+    info!("✅ Updated to {} ({}).", manifest.version, manifest.version_code);
+    Ok(UpdateOutcome::Updated)
+}
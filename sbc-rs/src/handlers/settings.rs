@@ -0,0 +1,50 @@
+use anyhow::{Context, Result, bail};
+use log::info;
+use sbc_rs::settings::{Settings, Workspace};
+
+/// 输出生效的设置 (etc/sbc.toml 与默认值合并后)，或其中的单个键 (如 update.template_url)
+pub fn handle_settings(ws: &Workspace, key: Option<String>) -> Result<()> {
+    let Some(key) = key else {
+        println!("# 工作空间: {:?}", ws.root);
+        print!("{}", toml::to_string_pretty(&ws.settings)?);
+        return Ok(());
+    };
+
+    // 单个键按生效值输出：update.*_url 代入当前通道
+    let mut effective = ws.settings.clone();
+    effective.update.template_url = ws.settings.update.template_urls(None);
+    effective.update.env_example_url = ws.settings.update.env_example_urls(None);
+    let root = toml::Value::try_from(&effective)?;
+    let value = key.split('.')
+        .try_fold(&root, |v, k| v.get(k))
+        .with_context(|| format!("未知的设置项: {}", key))?;
+    // 供 shell 脚本读取：字符串不带引号，数组每行一个元素，paths.* 解析为工作空间下的完整路径
+    match value {
+        toml::Value::String(s) if key.starts_with("paths.") => println!("{}", ws.path(s.as_ref()).display()),
+        toml::Value::String(s) => println!("{}", s),
+        toml::Value::Array(items) => {
+            for item in items {
+                match item {
+                    toml::Value::String(s) => println!("{}", s),
+                    other => println!("{}", other),
+                }
+            }
+        }
+        other => println!("{}", other),
+    }
+    Ok(())
+}
+
+/// 严格检查 etc/sbc.toml：平时未知的键与无效的表只会警告并回退为默认值，这里逐条列出并返回错误
+pub fn handle_settings_validate(ws: &Workspace) -> Result<()> {
+    let path = ws.settings_file();
+    let problems = Settings::validate(&path)?;
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if !problems.is_empty() {
+        bail!("❌ 设置文件 {:?} 有 {} 处问题", path, problems.len());
+    }
+    info!("✅ 设置文件有效: {:?}", path);
+    Ok(())
+}
@@ -0,0 +1,385 @@
+use anyhow::{Context, Result, anyhow, bail};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::Duration;
+use log::{error, info, warn};
+use serde_json::json;
+use sbc_rs::{convert, redact};
+use sbc_rs::settings::{SubscriptionSettings, Workspace};
+use sbc_rs::subscription::{self, Format, NodeFilter, Source, UserInfo};
+use crate::cli::FetchArgs;
+use crate::handlers::daemon;
+use crate::handlers::fetch::Fetcher;
+
+// 已有缓存时等待上游的时长，超时即返回缓存，避免 sing-box 启动时长时间等待
+const UPSTREAM_BUDGET: Duration = Duration::from_secs(3);
+
+// 本地订阅网关：sing-box 通过 http://<listen>/sub/<编号> 获取订阅，
+// 网关向上游下载、过滤与重命名后返回；上游不可用或响应过慢时返回最近一次可用的缓存
+struct Gateway {
+    cache_dir: PathBuf,
+    config: PathBuf,
+    rules: NodeFilter,
+    convert: bool,
+    fetch: FetchArgs,
+}
+
+impl Gateway {
+    fn fetch_upstream(&self, source: &Source) -> Result<Vec<u8>> {
+        Fetcher::new(&self.fetch, &self.config)?
+            .with_user_agent(source.user_agent.as_deref())
+            .get_bytes(&source.url)
+    }
+
+    // 过滤与重命名，按设置再转换为 sing-box JSON；返回 (响应体, 保留数, 过滤数)
+    fn process(&self, tag: &str, body: &[u8]) -> Result<(Vec<u8>, usize, usize)> {
+        let filtered = subscription::filter(body, &self.rules)?;
+        if !self.convert || filtered.format == Format::SingBox {
+            return Ok((filtered.body, filtered.kept, filtered.dropped));
+        }
+        let converted = convert::convert(&filtered.body)?;
+        for warning in &converted.warnings {
+            warn!("⚠️ 订阅 {} 转换提示: {}", tag, warning);
+        }
+        let body = serde_json::to_vec_pretty(&json!({ "outbounds": converted.outbounds }))?;
+        Ok((body, converted.outbounds.len(), filtered.dropped))
+    }
+
+    // 从上游下载并处理；内容可识别且过滤后仍有节点才更新缓存
+    fn refresh(&self, source: &Source, cache: &Path) -> Result<(Vec<u8>, usize, usize)> {
+        let body = self.fetch_upstream(source)?;
+        let (served, kept, dropped) = self.process(&source.tag, &body)?;
+        if fs::read(cache).ok().as_deref() != Some(body.as_slice()) {
+            let tmp = cache.with_extension("tmp");
+            if let Err(e) = fs::write(&tmp, &body).and_then(|_| fs::rename(&tmp, cache)) {
+                warn!("⚠️ 无法写入订阅缓存 {:?}: {}", cache, e);
+            }
+        }
+        info!("📡 订阅 {} 已从上游获取: 保留 {} 个节点，过滤 {} 个", source.tag, kept, dropped);
+        Ok((served, kept, dropped))
+    }
+
+    // 返回 (状态行, 内容来源, 响应体)
+    fn respond(self: &Arc<Self>, id: &str) -> (&'static str, Option<&'static str>, Vec<u8>) {
+        let sources = subscription::load_sources(&self.cache_dir);
+        let Some(source) = sources.get(id).cloned() else {
+            warn!("⚠️ 未知的订阅编号: {} (配置需经启用网关的渲染生成)", id);
+            return ("404 Not Found", None, "unknown subscription\n".into());
+        };
+        redact::register(&source.url);
+        let cache = self.cache_dir.join(format!("{}.raw", id));
+
+        // 1. 上游：在后台线程中下载。已有缓存时最多等待 UPSTREAM_BUDGET，超时则先返回缓存，
+        //    下载完成后仍会更新缓存供下次使用
+        let (tx, rx) = mpsc::channel();
+        let (gateway, background_source, background_cache) = (self.clone(), source.clone(), cache.clone());
+        thread::spawn(move || {
+            let result = gateway.refresh(&background_source, &background_cache);
+            if let Err(mpsc::SendError(Err(e))) = tx.send(result) {
+                warn!("⚠️ 订阅 {} 后台刷新失败: {:#}", background_source.tag, e);
+            }
+        });
+        let upstream = if cache.exists() {
+            rx.recv_timeout(UPSTREAM_BUDGET).unwrap_or_else(|_| {
+                Err(anyhow!("{} 秒内未响应，将在后台继续刷新", UPSTREAM_BUDGET.as_secs()))
+            })
+        } else {
+            rx.recv().unwrap_or_else(|_| Err(anyhow!("上游请求意外中断")))
+        };
+        match upstream {
+            Ok((served, _, _)) => return ("200 OK", Some("upstream"), served),
+            Err(e) => warn!("⚠️ 订阅 {} 的上游不可用，改用缓存: {:#}", source.tag, e),
+        }
+
+        // 2. 缓存 (过滤规则可能已修改，因此缓存的是上游原文，每次重新过滤)
+        let cached = fs::read(&cache)
+            .context("尚无缓存")
+            .and_then(|body| self.process(&source.tag, &body));
+        match cached {
+            Ok((served, kept, dropped)) => {
+                info!("📦 订阅 {} 使用缓存: 保留 {} 个节点，过滤 {} 个", source.tag, kept, dropped);
+                ("200 OK", Some("cache"), served)
+            }
+            Err(e) => {
+                error!("❌ 订阅 {} 不可用: {:#}", source.tag, e);
+                ("502 Bad Gateway", None, "subscription unavailable\n".into())
+            }
+        }
+    }
+
+    fn handle(self: &Arc<Self>, mut stream: TcpStream) -> Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // 请求头无需处理，读完即可
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let (method, path) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+        let id = path.strip_prefix("/sub/").map(|id| id.split(['?', '#']).next().unwrap_or_default());
+        let (status, origin, body) = match (method, id) {
+            ("GET", Some(id)) => self.respond(id),
+            _ => ("404 Not Found", None, "not found\n".into()),
+        };
+
+        write!(stream, "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n", status, body.len())?;
+        if let Some(origin) = origin {
+            write!(stream, "X-Sbc-Source: {}\r\n", origin)?;
+        }
+        stream.write_all(b"\r\n")?;
+        stream.write_all(&body)?;
+        Ok(())
+    }
+}
+
+// 先绑定端口，端口被占用等错误在启动时即可报告
+fn bind(ws: &Workspace, listen: &str, mut fetch: FetchArgs) -> Result<(TcpListener, Arc<Gateway>)> {
+    if fetch.proxy.is_none() {
+        fetch.proxy = ws.settings.update.proxy.clone();
+    }
+    let gateway = Gateway {
+        cache_dir: ws.subscription_cache(),
+        config: ws.config(),
+        rules: NodeFilter::new(&ws.settings.subscription)?,
+        convert: ws.settings.subscription.convert,
+        fetch,
+    };
+    fs::create_dir_all(&gateway.cache_dir)
+        .with_context(|| format!("无法创建订阅缓存目录: {:?}", gateway.cache_dir))?;
+    let listener = TcpListener::bind(listen).with_context(|| format!("订阅网关无法监听 {}", listen))?;
+    info!("🛰️ 订阅网关已监听: {}", subscription::gateway_base(listen));
+    Ok((listener, Arc::new(gateway)))
+}
+
+fn serve(listener: TcpListener, gateway: Arc<Gateway>) {
+    for stream in listener.incoming().flatten() {
+        let gateway = gateway.clone();
+        thread::spawn(move || {
+            if let Err(e) = gateway.handle(stream) {
+                warn!("⚠️ 订阅网关请求处理失败: {:#}", e);
+            }
+        });
+    }
+}
+
+/// 随 `run` 在后台启动网关。上游只尝试一次，失败即用缓存，避免 sing-box 等待过久
+pub fn spawn_gateway(ws: &Workspace) -> Result<()> {
+    let fetch = FetchArgs { retries: 1, ..Default::default() };
+    let (listener, gateway) = bind(ws, &ws.settings.subscription.listen, fetch)?;
+    thread::spawn(move || serve(listener, gateway));
+    Ok(())
+}
+
+/// 在前台运行网关 (调试或不经 `run` 托管时使用)
+pub fn handle_sub_serve(ws: &Workspace, listen: Option<String>, fetch: FetchArgs) -> Result<()> {
+    let listen = listen.unwrap_or_else(|| ws.settings.subscription.listen.clone());
+    let (listener, gateway) = bind(ws, &listen, fetch)?;
+    serve(listener, gateway);
+    Ok(())
+}
+
+/// 将订阅 (文件、URL 或标准输入) 转换为 sing-box 出站，输出 {"outbounds": [...]}
+pub fn handle_sub_convert(ws: &Workspace, input: String, output: Option<PathBuf>, user_agent: Option<String>, mut fetch: FetchArgs) -> Result<()> {
+    if fetch.proxy.is_none() {
+        fetch.proxy = ws.settings.update.proxy.clone();
+    }
+    let body = match input.as_str() {
+        "-" => {
+            let mut buf = Vec::new();
+            io::stdin().read_to_end(&mut buf).context("读取标准输入失败")?;
+            buf
+        }
+        _ => Fetcher::new(&fetch, &ws.config())?
+            .with_user_agent(user_agent.as_deref())
+            .get_bytes(&input)
+            .with_context(|| format!("无法读取订阅: {}", input))?,
+    };
+    let converted = convert::convert(&body)?;
+    for warning in &converted.warnings {
+        warn!("⚠️ {}", warning);
+    }
+    let content = serde_json::to_string_pretty(&json!({ "outbounds": converted.outbounds }))?;
+    match output.as_deref() {
+        None => println!("{}", content),
+        Some(path) if path == Path::new("-") => println!("{}", content),
+        Some(path) => {
+            let tmp = path.with_extension("tmp");
+            fs::write(&tmp, content + "\n")?;
+            fs::rename(&tmp, path).with_context(|| format!("写入输出文件失败: {:?}", path))?;
+        }
+    }
+    info!("🔄 已转换 {} 个节点 ({} 条提示)", converted.outbounds.len(), converted.warnings.len());
+    Ok(())
+}
+
+const GIB: f64 = (1u64 << 30) as f64;
+
+// 单个订阅的检查结果
+#[derive(Serialize)]
+struct CheckResult {
+    /// 对应的环境变量 (订阅地址含令牌，不输出)
+    var: String,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<&'static str>,
+    /// 按 subscription.include / exclude 过滤后的节点数
+    nodes: usize,
+    filtered: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    userinfo: Option<UserInfo>,
+    /// 剩余流量 (字节)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remaining: Option<u64>,
+    /// 到期时间 (RFC 3339)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expire: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    days_left: Option<i64>,
+    warnings: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+// .env 中的值覆盖进程环境变量 (与渲染一致)；返回按编号排序的 (变量名, 名称, 地址)，名称取 PROVIDER_NAME_n
+fn subscription_vars(env_file: &Path) -> Result<Vec<(String, String, String)>> {
+    let mut vars: HashMap<String, String> = env::vars().collect();
+    vars.extend(daemon::read_env_file(env_file).with_context(|| format!("无法读取 .env 文件: {:?}", env_file))?);
+    let mut subs: Vec<(u32, String)> = vars.iter()
+        .filter(|(_, url)| !url.trim().is_empty())
+        .filter_map(|(key, url)| Some((key.strip_prefix("SUB_URL_")?.parse().ok()?, url.trim().to_string())))
+        .collect();
+    subs.sort();
+    Ok(subs.into_iter().map(|(n, url)| {
+        let name = vars.get(&format!("PROVIDER_NAME_{}", n)).filter(|v| !v.trim().is_empty()).cloned();
+        (format!("SUB_URL_{}", n), name.unwrap_or_else(|| format!("SUB_URL_{}", n)), url)
+    }).collect())
+}
+
+fn check_one(fetcher: &Fetcher, rules: &NodeFilter, settings: &SubscriptionSettings, (var, name, url): (String, String, String)) -> CheckResult {
+    redact::register(&url);
+    let mut result = CheckResult {
+        var, name, status: None, format: None, nodes: 0, filtered: 0,
+        userinfo: None, remaining: None, expire: None, days_left: None, warnings: Vec::new(), error: None,
+    };
+    let resp = match fetcher.get_response(&url) {
+        Ok(resp) => resp,
+        Err(e) => {
+            result.error = Some(format!("{:#}", e));
+            return result;
+        }
+    };
+    result.status = Some(resp.status);
+    if !(200..300).contains(&resp.status) {
+        result.error = Some(format!("HTTP {}", resp.status));
+        return result;
+    }
+
+    // 1. 内容
+    result.format = subscription::detect(&resp.body).ok().map(|(format, _)| format.name());
+    match subscription::filter(&resp.body, rules) {
+        Ok(filtered) => (result.nodes, result.filtered) = (filtered.kept, filtered.dropped),
+        Err(e) => result.error = Some(format!("{:#}", e)),
+    }
+
+    // 2. 流量与到期
+    let Some(info) = resp.headers.get("subscription-userinfo").and_then(|h| subscription::parse_userinfo(h)) else {
+        return result;
+    };
+    result.userinfo = Some(info);
+    result.remaining = info.remaining();
+    if let Some(remaining) = result.remaining {
+        if remaining == 0 {
+            result.warnings.push("流量已用尽".into());
+        } else if (remaining as f64) < settings.warn_traffic_gb * GIB {
+            result.warnings.push(format!("剩余流量 {:.2} GB，低于 {} GB", remaining as f64 / GIB, settings.warn_traffic_gb));
+        }
+    }
+    if let Some(expire) = info.expire.and_then(|ts| chrono::DateTime::from_timestamp(ts, 0)) {
+        let expire = expire.with_timezone(&chrono::Local);
+        let days_left = expire.signed_duration_since(chrono::Local::now()).num_days();
+        let date = expire.format("%Y-%m-%d");
+        if expire <= chrono::Local::now() {
+            result.warnings.push(format!("套餐已于 {} 到期", date));
+        } else if settings.warn_days > 0 && days_left < settings.warn_days as i64 {
+            result.warnings.push(format!("套餐将于 {} 到期 (剩余 {} 天)", date, days_left));
+        }
+        result.expire = Some(expire.to_rfc3339());
+        result.days_left = Some(days_left);
+    }
+    result
+}
+
+/// 逐个请求 SUB_URL_n，报告 HTTP 状态、节点数与 Subscription-Userinfo 中的流量和到期时间。
+/// 流量或天数低于阈值时警告；任一订阅不可用时返回错误
+pub fn handle_sub_check(ws: &Workspace, env_file: Option<PathBuf>, user_agent: String, json: bool, mut fetch: FetchArgs) -> Result<()> {
+    let settings = &ws.settings.subscription;
+    if fetch.proxy.is_none() {
+        fetch.proxy = ws.settings.update.proxy.clone();
+    }
+    let env_file = env_file.unwrap_or_else(|| ws.env_file());
+    let subs = subscription_vars(&env_file)?;
+    if subs.is_empty() {
+        bail!("❌ 未在 {:?} 或环境变量中找到任何 SUB_URL_n", env_file);
+    }
+    let rules = NodeFilter::new(settings)?;
+    let fetcher = Fetcher::new(&fetch, &ws.config())?.with_user_agent(Some(&user_agent));
+
+    let results: Vec<CheckResult> = subs.into_iter().map(|sub| check_one(&fetcher, &rules, settings, sub)).collect();
+    for r in &results {
+        match r.error {
+            Some(ref e) => warn!("⚠️ 订阅 {} 不可用: {}", r.name, e),
+            None => info!("📡 订阅 {}: {} 个节点", r.name, r.nodes),
+        }
+        for warning in &r.warnings {
+            warn!("⚠️ 订阅 {}: {}", r.name, warning);
+        }
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    } else {
+        for r in &results {
+            let status = r.status.map_or_else(|| "-".to_string(), |s| s.to_string());
+            let detail = match r.error {
+                Some(ref e) => e.clone(),
+                None => format!("{} 个节点 (过滤 {} 个)", r.nodes, r.filtered),
+            };
+            println!("{:<20} {:<5} {:<16} {}", r.name, status, r.format.unwrap_or("-"), detail);
+            if let Some(info) = r.userinfo {
+                let total = match info.total {
+                    0 => "不限".to_string(),
+                    total => format!("{:.2} GB", total as f64 / GIB),
+                };
+                let mut usage = format!("已用 {:.2} GB / {}", info.used() as f64 / GIB, total);
+                if let Some(remaining) = r.remaining {
+                    usage += &format!("，剩余 {:.2} GB", remaining as f64 / GIB);
+                }
+                if let (Some(expire), Some(days)) = (r.expire.as_deref(), r.days_left) {
+                    usage += &format!("；{} 到期 (剩余 {} 天)", &expire[..10], days.max(0));
+                }
+                println!("{:<20} {}", "", usage);
+            }
+        }
+    }
+
+    let failed = results.iter().filter(|r| r.error.is_some()).count();
+    if failed > 0 {
+        bail!("❌ {} 个订阅检查失败", failed);
+    }
+    Ok(())
+}
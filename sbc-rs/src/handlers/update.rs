@@ -1,59 +1,469 @@
-use anyhow::{Context, Result, bail};
+use anyhow::{Context, Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::PathBuf;
-use std::time::SystemTime;
-use log::{info, error};
-
-pub fn handle_update(
-    template_url: String,
-    template_path: PathBuf,
-    env_url: Option<String>,
-    env_path: Option<PathBuf>,
-) -> Result<()> {
+use std::path::{Path, PathBuf};
+use log::{info, error, warn};
+use sbc_rs::{archive, lint, redact, signature};
+use sbc_rs::renderer::{OutputFormat, Renderer};
+use sbc_rs::settings::{self, Settings, Workspace};
+use crate::cli::UpdateArgs;
+use crate::handlers::{bundle, check, daemon, env, history, render};
+use crate::handlers::fetch::{ArtifactMeta, Fetched, Fetcher};
+
+/// Exit status used when the effective config has not changed (HTTP 304, identical content or identical rendering)
+pub const EXIT_UNCHANGED: i32 = 3;
+
+// Member names inside a template bundle (.zip / .tar / .tar.gz)
+pub(crate) const BUNDLE_TEMPLATE: &str = "config.template.json";
+pub(crate) const BUNDLE_ENV_EXAMPLE: &str = ".env.example";
+
+pub enum UpdateOutcome {
+    Updated,
+    Unchanged,
+}
+
+// Metadata file stored next to the template, keyed by artifact file name
+#[derive(Serialize, Deserialize, Default)]
+struct UpdateMeta {
+    artifacts: BTreeMap<String, ArtifactMeta>,
+}
+
+fn meta_path(template_path: &Path) -> PathBuf {
+    template_path.with_extension("meta.json")
+}
+
+fn artifact_key(path: &Path) -> String {
+    path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+// What a path held before the update: a regular file, a symlink (managed bundles) or nothing
+enum Saved {
+    Missing,
+    Link(PathBuf),
+    File(Vec<u8>),
+}
+
+// The installed files as they were before the update, put back when the new ones fail the final checks
+struct Snapshot(Vec<(PathBuf, Saved)>);
+
+impl Snapshot {
+    fn take(paths: &[&Path]) -> Result<Self> {
+        let mut saved = Vec::with_capacity(paths.len());
+        for path in paths {
+            let state = match fs::symlink_metadata(path) {
+                Ok(m) if m.file_type().is_symlink() => Saved::Link(fs::read_link(path)?),
+                Ok(_) => Saved::File(fs::read(path).with_context(|| format!("Failed to read {:?}", path))?),
+                Err(_) => Saved::Missing,
+            };
+            saved.push((path.to_path_buf(), state));
+        }
+        Ok(Self(saved))
+    }
+
+    fn restore(&self) -> Result<()> {
+        for (path, state) in &self.0 {
+            let tmp = path.with_extension("restore.tmp");
+            let _ = fs::remove_file(&tmp);
+            match state {
+                Saved::Missing => match fs::remove_file(path) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => continue,
+                },
+                Saved::Link(target) => std::os::unix::fs::symlink(target, &tmp)?,
+                Saved::File(data) => fs::write(&tmp, data)?,
+            }
+            fs::rename(&tmp, path).with_context(|| format!("Failed to restore {:?}", path))?;
+        }
+        warn!("⏪ Restored the previous template and .env.example.");
+        Ok(())
+    }
+}
+
+impl UpdateMeta {
+    fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+// A downloaded template, either bare or unpacked from a bundle together with its extras
+struct TemplateSource {
+    template: String,
+    bundle: bool,
+    signature: Option<String>,
+    env_example: Option<Vec<u8>>,
+}
+
+fn open_source(url: &str, body: Vec<u8>, files: Option<BTreeMap<String, Vec<u8>>>) -> Result<TemplateSource> {
+    let Some(files) = files else {
+        return Ok(TemplateSource {
+            template: String::from_utf8(body).context("Template is not valid UTF-8")?,
+            bundle: false,
+            signature: None,
+            env_example: None,
+        });
+    };
+    let template = archive::find(&files, BUNDLE_TEMPLATE)
+        .with_context(|| format!("Bundle {} contains no {}", url, BUNDLE_TEMPLATE))?;
+    let signature_name = format!("{}{}", BUNDLE_TEMPLATE, signature::SIGNATURE_SUFFIX);
+    info!("📦 Unpacked bundle with {} file(s).", files.len());
+    Ok(TemplateSource {
+        template: String::from_utf8(template.to_vec()).context("Template is not valid UTF-8")?,
+        bundle: true,
+        signature: archive::find(&files, &signature_name).map(|s| String::from_utf8_lossy(s).to_string()),
+        env_example: archive::find(&files, BUNDLE_ENV_EXAMPLE).map(<[u8]>::to_vec),
+    })
+}
+
+// Verify the detached minisign signature published next to the template (or shipped inside its bundle)
+fn verify_template(args: &UpdateArgs, fetcher: &Fetcher, template_url: &str, source: &TemplateSource) -> Result<()> {
+    // The signature is published next to the template on the mirror that served it
+    let sig = || match (&source.signature, source.bundle) {
+        (Some(sig), _) => Ok(sig.clone()),
+        (None, true) => Err(anyhow!("Bundle contains no {}{}", BUNDLE_TEMPLATE, signature::SIGNATURE_SUFFIX)),
+        (None, false) => {
+            let sig_url = format!("{}{}", template_url, signature::SIGNATURE_SUFFIX);
+            info!("Downloading signature from: {}", sig_url);
+            fetcher.get_string(&sig_url).with_context(|| format!("Failed to download signature from {}", sig_url))
+        }
+    };
+    verify_signature(args, "Template", source.template.as_bytes(), sig)
+}
+
+// Check `data` against the pinned public key; `sig` is only fetched once a key is known to exist
+pub(crate) fn verify_signature(args: &UpdateArgs, what: &str, data: &[u8], sig: impl FnOnce() -> Result<String>) -> Result<()> {
+    verify_pinned(args.pubkey.as_deref(), args.allow_unsigned, args.allow_unpinned, what, data, sig)
+}
+
+// The same check for callers without UpdateArgs (self-update verifies update.json with the same key)
+pub(crate) fn verify_pinned(pubkey: Option<&Path>, allow_unsigned: bool, allow_unpinned: bool, what: &str, data: &[u8], sig: impl FnOnce() -> Result<String>) -> Result<()> {
+    let Some(pubkey_path) = pubkey.filter(|p| p.exists()) else {
+        if allow_unsigned || allow_unpinned {
+            let reason = if allow_unsigned { "--allow-unsigned" } else { "update.allow_unpinned" };
+            warn!("⚠️ No public key pinned, skipping {} signature verification ({}).", what.to_lowercase(), reason);
+            return Ok(());
+        }
+        bail!("❌ No public key pinned for {} verification (--pubkey). Pin the publisher key, or set update.allow_unpinned = true in etc/sbc.toml to accept downloads without one.", what.to_lowercase());
+    };
+    let public_key = signature::load_public_key(pubkey_path)?;
+    let result = sig().and_then(|sig| signature::verify(&public_key, data, &sig));
+
+    match result {
+        Ok(()) => {
+            info!("🔏 {} signature verified.", what);
+            Ok(())
+        }
+        Err(e) if allow_unsigned => {
+            warn!("⚠️ {} signature not verified, accepted anyway (--allow-unsigned): {:#}", what, e);
+            Ok(())
+        }
+        Err(e) => Err(e.context(format!("❌ Refusing unsigned or tampered {}", what.to_lowercase()))),
+    }
+}
+
+// Fill in the download sources and paths not given on the command line from etc/sbc.toml
+fn apply_settings(args: &mut UpdateArgs, ws: &Workspace) -> Result<()> {
+    let settings = &ws.settings.update;
+    if let Some(ref channel) = args.channel {
+        settings::validate_channel(channel)?;
+        if !settings.has_channels() {
+            bail!("❌ update.template_url in etc/sbc.toml has no {} placeholder, channels are not available.", settings::CHANNEL_PLACEHOLDER);
+        }
+    }
+    // URLs on the command line describe the whole job; the configured sources are only used as a set
+    if args.template_url.is_empty() {
+        args.template_url = settings.template_urls(args.channel.as_deref());
+        if args.env_url.is_empty() {
+            args.env_url = settings.env_example_urls(args.channel.as_deref());
+        }
+    }
+    // A bundle may carry its own .env.example
+    if !args.env_url.is_empty() || args.template_url.iter().any(|u| archive::is_archive(u)) {
+        args.env_path.get_or_insert_with(|| ws.env_example());
+    }
+    args.pubkey.get_or_insert_with(|| ws.pubkey());
+    args.allow_unpinned = settings.allow_unpinned;
+    if args.fetch.proxy.is_none() {
+        args.fetch.proxy = settings.proxy.clone();
+    }
+    Ok(())
+}
+
+// Persist a channel switch once the channel's template has been accepted
+fn save_channel(ws: &Workspace, channel: &str) -> Result<()> {
+    if ws.settings.update.channel == channel {
+        return Ok(());
+    }
+    let path = ws.settings_file();
+    Settings::store(&path, "update", "channel", channel)?;
+    info!("📌 Switched from channel '{}' to '{}' (saved to {:?}).", ws.settings.update.channel, channel, path);
+    Ok(())
+}
+
+// Trial render the downloaded template against the current .env, exactly as `run` would
+pub(crate) fn trial_render(args: &UpdateArgs, template_path: &Path, env_file: &Path, body: &str) -> Result<()> {
+    let env_vars = daemon::read_env_file(env_file)
+        .with_context(|| format!("Failed to read .env file {:?}", env_file))?;
+    let rendered = Renderer::new()
+        .process_env()
+        .vars(env_vars)
+        .render_str(body)?;
+    for warning in lint::lint_config(&rendered.value)? {
+        warn!("⚠️ {}", warning);
+    }
+    if !rendered.report.missing.is_empty() {
+        warn!("⚠️ New template references unset variables: {}", rendered.report.missing.join(", "));
+    }
+
+    let output = OutputFormat::default().to_string(&rendered.value)?;
+    let trial_path = template_path.with_extension("trial.json");
+    fs::write(&trial_path, output)?;
+    let result = if args.check { check::check_config(&trial_path) } else { Ok(()) };
+    let _ = fs::remove_file(&trial_path);
+    result
+}
+
+// History is a safety net: failing to write it must not fail the update itself
+pub(crate) fn record_history(args: &UpdateArgs, ws: &Workspace, template_path: &Path, keep: usize) {
+    match history::record(ws, template_path, args.env_path.as_deref(), keep) {
+        Ok(Some(id)) => info!("🗂️ Saved template history entry {}", id),
+        Ok(None) => {}
+        Err(e) => warn!("⚠️ Failed to save template history: {:#}", e),
+    }
+}
+
+// Result of an update as printed by --json
+#[derive(Serialize, Default)]
+struct UpdateReport {
+    /// unchanged | changed | failed
+    status: &'static str,
+    template_updated: bool,
+    env_example_updated: bool,
+    restarted: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+// Render the installed template into a temp file the way `run` will, and compare it with the running config.
+// Objects compare regardless of key order; arrays (rules, outbounds) keep their order.
+fn effective_config_changed(args: &UpdateArgs, ws: &Workspace, template_path: &Path, env_file: &Path, config: &Path) -> Result<bool> {
+    let opts = render::workspace_options(ws, None, false);
+    let env_vars = daemon::read_env_file(env_file)
+        .with_context(|| format!("Failed to read .env file {:?}", env_file))?;
+    let renderer = render::renderer(&opts).vars(env_vars);
+    let template = fs::read_to_string(template_path)
+        .with_context(|| format!("Failed to read template {:?}", template_path))?;
+    let rendered = renderer.render_str(&template)?;
+
+    // The trial render ran without the script, so its output still has to pass the check
+    let next_path = config.with_extension("next.json");
+    fs::write(&next_path, OutputFormat::default().to_string(&rendered.value)?)?;
+    let checked = match (args.check, opts.script) {
+        (true, Some(_)) => check::check_config(&next_path),
+        _ => Ok(()),
+    };
+    let _ = fs::remove_file(&next_path);
+    checked.context("❌ Rendered config with etc/render.rhai does not pass sing-box check")?;
+
+    let current: Option<Value> = fs::read_to_string(config).ok().and_then(|s| serde_json::from_str(&s).ok());
+    Ok(current.as_ref() != Some(&rendered.value))
+}
+
+pub fn handle_update(args: UpdateArgs, ws: &Workspace) -> Result<UpdateOutcome> {
+    let json = args.json;
+    let mut report = UpdateReport::default();
+    let result = update(args, ws, &mut report);
+    if json {
+        match result {
+            Ok(UpdateOutcome::Updated) => report.status = "changed",
+            Ok(UpdateOutcome::Unchanged) => report.status = "unchanged",
+            Err(ref e) => {
+                report.status = "failed";
+                report.error = Some(redact::mask(&format!("{:#}", e)));
+            }
+        }
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    }
+    result
+}
+
+fn update(mut args: UpdateArgs, ws: &Workspace, report: &mut UpdateReport) -> Result<UpdateOutcome> {
+    apply_settings(&mut args, ws)?;
+    if args.template_url.is_empty() {
+        bail!("❌ No template URL given (--template-url) or configured (update.template_url in etc/sbc.toml).");
+    }
+    let template_path = &args.template_path.clone().unwrap_or_else(|| ws.template());
+    let env_file = args.env_file.clone().unwrap_or_else(|| ws.env_file());
+    let keep_history = args.keep_history.unwrap_or(ws.settings.update.keep_history);
+    let fetcher = Fetcher::new(&args.fetch, &ws.config())?
+        .with_auth(&ws.settings.update.auth, &env_file)?;
     info!("📡 Connecting to remote server...");
 
-    // Generate cache buster
-    let timestamp = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)?
-        .as_secs();
-    let cache_buster = format!("?t={}", timestamp);
+    let meta_file = meta_path(template_path);
+    let mut meta = UpdateMeta::load(&meta_file);
+    // Everything an update may replace: the template, .env.example, the active bundle link and a channel switch
+    let settings_file = ws.settings_file();
+    let bundle_link = ws.bundle_link();
+    let mut snapshot_paths = vec![template_path.as_path(), bundle_link.as_path(), settings_file.as_path()];
+    snapshot_paths.extend(args.env_path.as_deref());
+    let snapshot = Snapshot::take(&snapshot_paths)?;
+    let mut env_updated = false;
+    let mut bundled_env = None;
+    let mut managed = false;
 
     // 1. Update Template
-    let full_template_url = format!("{}{}", template_url, cache_buster);
-    info!("Downloading template from: {}", full_template_url);
-    
-    let template_body = ureq::get(&full_template_url)
-        .call()
-        .with_context(|| format!("Failed to download template from {}", full_template_url))?
-        .into_string()?;
-
-    // Validation: Check for "inbounds" to ensure it's a valid config (manifest check)
-    if !template_body.contains("inbounds") {
-        bail!("❌ Validation failed: Downloaded content does not look like a valid sing-box config (missing 'inbounds').");
-    }
-
-    // Atomic Write
-    let tmp_path = template_path.with_extension("tmp");
-    fs::write(&tmp_path, &template_body)?;
-    fs::rename(&tmp_path, &template_path)?;
-    info!("✅ Template updated successfully.");
-
-    // 2. Update Env Example (if requested)
-    if let (Some(e_url), Some(e_path)) = (env_url, env_path) {
-        let full_env_url = format!("{}{}", e_url, cache_buster);
-        info!("Downloading env example from: {}", full_env_url);
-        
-        match ureq::get(&full_env_url).call() {
-            Ok(resp) => {
-                let env_body = resp.into_string()?;
-                let tmp_env = e_path.with_extension("tmp");
-                fs::write(&tmp_env, env_body)?;
-                fs::rename(&tmp_env, &e_path)?;
-                info!("📝 Env example updated.");
-            },
-            Err(e) => error!("⚠️ Failed to update env example: {}", e),
+    info!("Downloading template from: {}", args.template_url.join(", "));
+    let template_key = artifact_key(template_path);
+    let previous = meta.artifacts.get(&template_key);
+    let outcome = match fetcher.fetch(&args.template_url, previous, template_path.exists())? {
+        Fetched::NotModified => {
+            info!("✅ Template is up to date (304 Not Modified), no changes.");
+            UpdateOutcome::Unchanged
+        }
+        Fetched::Body(body, artifact) => 'template: {
+            let files = match archive::is_archive(&artifact.url) {
+                true => Some(archive::unpack(&body).with_context(|| format!("Failed to unpack bundle {}", artifact.url))?),
+                false => None,
+            };
+            // A bundle with a manifest is installed as a whole into its own directory
+            if let Some(files) = files.as_ref().filter(|f| bundle::is_managed(f)) {
+                managed = true;
+                let outcome = bundle::install(&args, ws, template_path, &env_file, files, keep_history)?;
+                meta.artifacts.insert(template_key, artifact);
+                break 'template outcome;
+            }
+            let source = open_source(&artifact.url, body, files)?;
+            verify_template(&args, &fetcher, &artifact.url, &source)?;
+            // Validation: the new template must survive the full render pipeline before it replaces the old one
+            trial_render(&args, template_path, &env_file, &source.template)
+                .context("❌ Validation failed: downloaded template does not render, keeping the current template")?;
+            bundled_env = source.env_example.map(|body| (body, artifact.url.clone()));
+
+            // Sources without validators (local files, servers without ETag) are compared by content
+            if fs::read(template_path).is_ok_and(|current| current == source.template.as_bytes()) {
+                meta.artifacts.insert(template_key, artifact);
+                info!("✅ Template content is unchanged.");
+                UpdateOutcome::Unchanged
+            } else {
+                // Snapshot what is installed now so it can be rolled back to
+                record_history(&args, ws, template_path, keep_history);
+
+                // Atomic Write
+                let tmp_path = template_path.with_extension("tmp");
+                fs::write(&tmp_path, &source.template)?;
+                fs::rename(&tmp_path, template_path)?;
+                meta.artifacts.insert(template_key, artifact);
+                info!("✅ Template updated successfully.");
+                UpdateOutcome::Updated
+            }
         }
+    };
+    // The template of the new channel is installed (or identical), so the switch can be committed
+    if let Some(ref channel) = args.channel {
+        save_channel(ws, channel)?;
     }
 
-    Ok(())
+    // 2. Update Env Example: from the bundle, or downloaded separately (if requested).
+    //    A managed bundle already linked its own .env.example in place.
+    if let Some(e_path) = &args.env_path {
+        if !managed && (bundled_env.is_some() || !args.env_url.is_empty()) {
+            let env_key = artifact_key(e_path);
+            let fetched = match bundled_env {
+                Some((body, url)) => Ok(Fetched::Body(body, ArtifactMeta { url, ..Default::default() })),
+                None => {
+                    info!("Downloading env example from: {}", args.env_url.join(", "));
+                    fetcher.fetch(&args.env_url, meta.artifacts.get(&env_key), e_path.exists())
+                }
+            };
+
+            match fetched {
+                Ok(Fetched::NotModified) => info!("📝 Env example is up to date."),
+                Ok(Fetched::Body(env_body, artifact)) if fs::read(e_path).is_ok_and(|current| current == env_body) => {
+                    meta.artifacts.insert(env_key, artifact);
+                    info!("📝 Env example is up to date.");
+                }
+                Ok(Fetched::Body(env_body, artifact)) => {
+                    // The template branch already took the snapshot if it changed
+                    if matches!(outcome, UpdateOutcome::Unchanged) {
+                        record_history(&args, ws, template_path, keep_history);
+                    }
+                    let tmp_env = e_path.with_extension("tmp");
+                    fs::write(&tmp_env, env_body)?;
+                    fs::rename(&tmp_env, e_path)?;
+                    meta.artifacts.insert(env_key, artifact);
+                    env_updated = true;
+                    info!("📝 Env example updated.");
+                },
+                Err(e) => error!("⚠️ Failed to update env example: {:#}", e),
+            }
+        }
+
+        // 3. Bring new variables from the example into the user's .env
+        if args.migrate_env
+            && e_path.exists()
+            && let Err(e) = env::migrate_env(e_path, &env_file, false)
+        {
+            error!("⚠️ Failed to migrate .env: {:#}", e);
+        }
+    }
+
+    let installed = env_updated || matches!(outcome, UpdateOutcome::Updated);
+    report.template_updated = matches!(outcome, UpdateOutcome::Updated);
+    report.env_example_updated = env_updated;
+    if !installed {
+        save_meta(&meta, &meta_file);
+        return Ok(UpdateOutcome::Unchanged);
+    }
+
+    // 4. The new files only stay once the effective config renders and passes the check (and, with --apply,
+    //    sing-box is back up with it); otherwise the previous ones are restored. Only a change needs a restart.
+    let config = args.config.clone().unwrap_or_else(|| ws.config());
+    let changed = match effective_config_changed(&args, ws, template_path, &env_file, &config) {
+        Ok(changed) => changed,
+        Err(e) => {
+            snapshot.restore()?;
+            report.template_updated = false;
+            report.env_example_updated = false;
+            return Err(e);
+        }
+    };
+    if !changed {
+        info!("✅ Effective config is identical to {:?}, no restart needed.", config);
+    } else if !args.apply {
+        info!("🔁 Effective config changed, sing-box needs a restart (sbc restart, or update --apply).");
+    } else if daemon::supervisor_pid(ws).is_some() {
+        if let Err(e) = daemon::restart_and_check(ws, ws.settings.self_update.health_check_secs) {
+            // Put the previous template back and bring the service up with it again
+            error!("❌ sing-box did not come back with the new config: {:#}", e);
+            let _ = daemon::handle_stop(ws);
+            snapshot.restore()?;
+            report.template_updated = false;
+            report.env_example_updated = false;
+            daemon::spawn_supervisor(ws)?;
+            bail!("❌ Update rolled back to the previous template");
+        }
+        report.restarted = true;
+    } else {
+        info!("Service is not running; the new config is used at the next start.");
+    }
+    save_meta(&meta, &meta_file);
+    record_history(&args, ws, template_path, keep_history);
+    Ok(if changed { UpdateOutcome::Updated } else { UpdateOutcome::Unchanged })
+}
+
+fn save_meta(meta: &UpdateMeta, meta_file: &Path) {
+    if let Err(e) = meta.save(meta_file) {
+        warn!("⚠️ Failed to save update metadata {:?}: {}", meta_file, e);
+    }
 }
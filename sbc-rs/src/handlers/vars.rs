@@ -0,0 +1,131 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use log::info;
+use sbc_rs::{dotenv, renderer, schema};
+use crate::handlers::daemon;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum VarKind {
+    /// ${VAR}: 字符串插值
+    String,
+    /// {{VAR}}: 结构化 (JSON) 替换
+    Structural,
+}
+
+#[derive(Serialize)]
+struct VarEntry {
+    name: String,
+    kind: VarKind,
+    paths: Vec<String>,
+    set: bool,
+}
+
+pub fn handle_vars(template: PathBuf, env_file: Option<PathBuf>, json: bool, env_example: Option<PathBuf>) -> Result<()> {
+    // 1. 加载环境变量 (可选 .env 文件)
+    if let Some(ref path) = env_file {
+        daemon::load_env_file(path)
+            .with_context(|| format!("加载 .env 文件失败: {:?}", path))?;
+    }
+    let env_vars: HashMap<String, String> = env::vars().collect();
+
+    // 2. 读取并解析模板
+    let template_content = fs::read_to_string(&template)
+        .with_context(|| format!("读取模板文件失败: {:?}", template))?;
+    let mut root: Value = serde_json::from_str(&renderer::strip_comments(&template_content))
+        .context("无法将模板解析为有效的 JSON。请确保输入格式正确。")?;
+    let var_schema = schema::take_schema(&mut root)?.unwrap_or_default();
+
+    // 3. 按 process_value 的规则遍历 AST
+    let mut entries: Vec<VarEntry> = Vec::new();
+    collect_vars(&root, "$", &mut entries);
+    for entry in entries.iter_mut() {
+        entry.set = match entry.kind {
+            // resolve_env_var 将空值视为未设置
+            VarKind::Structural => env_vars.get(&entry.name).is_some_and(|v| !v.trim().is_empty()),
+            VarKind::String => env_vars.contains_key(&entry.name),
+        };
+    }
+
+    // 4. 输出
+    if let Some(path) = env_example {
+        fs::write(&path, format_env_example(&entries, &var_schema))
+            .with_context(|| format!("写入 .env.example 失败: {:?}", path))?;
+        info!("📝 已生成 .env.example: {:?}", path);
+    } else if json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+    } else {
+        for entry in &entries {
+            let kind = match entry.kind {
+                VarKind::String => "string",
+                VarKind::Structural => "structural",
+            };
+            let status = if entry.set { "set" } else { "unset" };
+            println!("{:<28} {:<10} {:<5}  {}", entry.name, kind, status, entry.paths.join(", "));
+        }
+    }
+    Ok(())
+}
+
+fn record(entries: &mut Vec<VarEntry>, name: &str, kind: VarKind, path: &str) {
+    if let Some(entry) = entries.iter_mut().find(|e| e.name == name && e.kind == kind) {
+        entry.paths.push(path.to_string());
+    } else {
+        entries.push(VarEntry { name: name.to_string(), kind, paths: vec![path.to_string()], set: false });
+    }
+}
+
+fn collect_vars(v: &Value, path: &str, entries: &mut Vec<VarEntry>) {
+    match v {
+        Value::Object(map) => {
+            for (k, v) in map {
+                collect_vars(v, &format!("{}.{}", path, k), entries);
+            }
+        }
+        Value::Array(arr) => {
+            for (i, v) in arr.iter().enumerate() {
+                collect_vars(v, &format!("{}[{}]", path, i), entries);
+            }
+        }
+        Value::String(s) => {
+            // {{VAR}} 优先，命中后不再进行 ${VAR} 插值
+            if let Some(var_name) = renderer::extract_structural_placeholder(s) {
+                record(entries, var_name, VarKind::Structural, path);
+                return;
+            }
+            for var_name in renderer::scan_interpolations(s) {
+                record(entries, var_name, VarKind::String, path);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn format_env_example(entries: &[VarEntry], var_schema: &schema::VarSchema) -> String {
+    let mut out = String::from("# Sing-box 运行时环境变量模板 (由 sbc-rs vars 生成)\n# 请将此文件复制为 .env 并填入真实信息\n");
+    for entry in entries {
+        let spec = var_schema.get(&entry.name);
+        let hint = match entry.kind {
+            VarKind::String => "字符串",
+            VarKind::Structural => "JSON 对象/数组",
+        };
+        out.push('\n');
+        if let Some(description) = spec.and_then(|s| s.description.as_deref()) {
+            out.push_str(&format!("# {}\n", description));
+        }
+        let required = if spec.is_some_and(|s| s.required) { " (必填)" } else { "" };
+        out.push_str(&format!("# {}{} — {}\n", hint, required, entry.paths.join(", ")));
+        // 机密变量从不写出默认值
+        let default = spec
+            .filter(|s| !s.secret)
+            .and_then(|s| s.default_text())
+            .unwrap_or_default();
+        out.push_str(&format!("{}={}\n", entry.name, dotenv::quote(&default)));
+    }
+    out
+}
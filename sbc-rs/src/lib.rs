@@ -0,0 +1,16 @@
+// sbc-rs 核心库：模板渲染引擎及其辅助模块
+// CLI 与监护进程 (src/main.rs) 只是这些 API 的薄封装
+pub mod archive;
+pub mod bundle;
+pub mod checksum;
+pub mod convert;
+pub mod dotenv;
+pub mod lint;
+pub mod redact;
+pub mod renderer;
+pub mod ruleset;
+pub mod schema;
+pub mod script;
+pub mod settings;
+pub mod signature;
+pub mod subscription;
@@ -0,0 +1,45 @@
+// 渲染结果的基本结构检查 (不替代 sing-box check，仅拦截明显错误的配置)
+use anyhow::{Result, bail};
+use serde_json::Value;
+
+// sing-box 顶层字段及其期望类型 (true = 数组, false = 对象)
+const TOP_LEVEL: &[(&str, bool)] = &[
+    ("log", false),
+    ("dns", false),
+    ("ntp", false),
+    ("certificate", false),
+    ("endpoints", true),
+    ("inbounds", true),
+    ("outbounds", true),
+    ("providers", true),
+    ("route", false),
+    ("services", true),
+    ("experimental", false),
+];
+
+/// 检查配置结构，返回告警列表；结构性错误直接返回 Err
+pub fn lint_config(config: &Value) -> Result<Vec<String>> {
+    let Value::Object(root) = config else {
+        bail!("配置根节点必须是 JSON 对象");
+    };
+
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    for (key, value) in root {
+        match TOP_LEVEL.iter().find(|(k, _)| k == key) {
+            Some((_, true)) if !value.is_array() => errors.push(format!("\"{}\" 必须是数组", key)),
+            Some((_, false)) if !value.is_object() => errors.push(format!("\"{}\" 必须是对象", key)),
+            Some(_) => {}
+            None => warnings.push(format!("未知的顶层字段 \"{}\"", key)),
+        }
+    }
+
+    if !root.contains_key("inbounds") && !root.contains_key("outbounds") {
+        errors.push("配置中既没有 inbounds 也没有 outbounds".to_string());
+    }
+
+    if !errors.is_empty() {
+        bail!("配置结构检查未通过:\n  - {}", errors.join("\n  - "));
+    }
+    Ok(warnings)
+}
@@ -5,8 +5,11 @@ shadow_rs::shadow!(build);
 
 use anyhow::Result;
 use clap::Parser;
-use cli::{Cli, Commands};
-use handlers::{render, update, daemon};
+use cli::{ChannelCommands, Cli, Commands, EnvCommands, RulesetCommands, SubCommands};
+use handlers::{render, update, daemon, vars, history, env, settings, channel, self_update, ruleset, sub};
+use sbc_rs::redact;
+use sbc_rs::renderer::OutputFormat;
+use sbc_rs::settings::Workspace;
 
 fn main() -> Result<()> {
     // 初始化日志 (默认为 INFO)
@@ -23,23 +26,89 @@ fn main() -> Result<()> {
                 "[{} {}] {}",
                 chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
                 record.level(),
-                record.args()
+                redact::mask(&record.args().to_string())
             )
         })
         .init();
 
     let cli = Cli::parse();
 
-    match cli.command {
-        Commands::Render { template, output } => {
-            render::handle_render(template, output)
+    // 日志脱敏：默认开启，--no-redact 仅用于本地调试
+    redact::set_enabled(!cli.no_redact);
+    redact::register_env(&std::env::vars().collect());
+
+    // 工作空间与 etc/sbc.toml：所有命令共用同一解析顺序
+    let result = Workspace::resolve(cli.workspace)
+        .and_then(|ws| run_command(cli.command, &ws));
+
+    // 错误链同样经过脱敏后再输出
+    if let Err(e) = result {
+        eprintln!("Error: {}", redact::mask(&format!("{:?}", e)));
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn run_command(command: Commands, ws: &Workspace) -> Result<()> {
+    match command {
+        Commands::Render { template, output, check, compact, indent, sort_keys, strict, minify, script } => {
+            let opts = render::RenderOptions {
+                check,
+                format: OutputFormat {
+                    indent: if compact || minify { None } else { Some(indent.unwrap_or(2)) },
+                    sort_keys,
+                },
+                skip_unchanged: minify,
+                strict,
+                script,
+                local_rulesets: render::local_rulesets(ws),
+                subscription_gateway: render::subscription_gateway(ws),
+            };
+            render::handle_render(template.unwrap_or_else(|| ws.template()), output.unwrap_or_else(|| ws.config()), &opts)
+        }
+        Commands::Update(args) => {
+            match update::handle_update(args, ws) {
+                // 无变化时使用独立的退出码，便于 sbc 跳过重启
+                Ok(update::UpdateOutcome::Unchanged) => std::process::exit(update::EXIT_UNCHANGED),
+                Ok(update::UpdateOutcome::Updated) => Ok(()),
+                Err(e) => Err(e),
+            }
+        }
+        Commands::SelfUpdate(args) => {
+            match self_update::handle_self_update(args, ws) {
+                Ok(update::UpdateOutcome::Unchanged) => std::process::exit(update::EXIT_UNCHANGED),
+                Ok(update::UpdateOutcome::Updated) => Ok(()),
+                Err(e) => Err(e),
+            }
+        }
+        Commands::Run { config, template, working_dir, check, strict, script } => {
+            daemon::handle_run(ws, config, template, working_dir, check, strict, script)
+        }
+        Commands::Stop => daemon::handle_stop(ws),
+        Commands::Vars { template, env_file, json, env_example } => {
+            vars::handle_vars(template.unwrap_or_else(|| ws.template()), Some(env_file.unwrap_or_else(|| ws.env_file())), json, env_example)
+        }
+        Commands::Settings { validate: true, .. } => settings::handle_settings_validate(ws),
+        Commands::Settings { key, .. } => settings::handle_settings(ws, key),
+        Commands::Env { command: EnvCommands::Migrate { example, env_file, dry_run } } => {
+            env::handle_env_migrate(&example.unwrap_or_else(|| ws.env_example()), &env_file.unwrap_or_else(|| ws.env_file()), dry_run)
+        }
+        Commands::Channel { command: ChannelCommands::List { index, json, fetch } } => {
+            channel::handle_channel_list(ws, index, json, fetch)
+        }
+        Commands::Ruleset { command: RulesetCommands::Sync { template, env_file, jobs, json, fetch } } => {
+            ruleset::handle_ruleset_sync(ws, template, env_file, jobs, json, fetch)
+        }
+        Commands::Sub { command: SubCommands::Serve { listen, fetch } } => sub::handle_sub_serve(ws, listen, fetch),
+        Commands::Sub { command: SubCommands::Convert { input, output, user_agent, fetch } } => {
+            sub::handle_sub_convert(ws, input, output, user_agent, fetch)
         }
-        Commands::Update { template_url, template_path, env_url, env_path } => {
-            update::handle_update(template_url, template_path, env_url, env_path)
+        Commands::Sub { command: SubCommands::Check { env_file, user_agent, json, fetch } } => {
+            sub::handle_sub_check(ws, env_file, user_agent, json, fetch)
         }
-        Commands::Run { config, template, working_dir } => {
-            daemon::handle_run(Some(config), template, working_dir)
+        Commands::History { template, json } => history::handle_history(ws, template.unwrap_or_else(|| ws.template()), json),
+        Commands::Rollback { id, template, env_example, config, check } => {
+            history::handle_rollback(ws, id, template, env_example, config, check)
         }
-        Commands::Stop => daemon::handle_stop(),
     }
 }
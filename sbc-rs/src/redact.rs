@@ -0,0 +1,114 @@
+// 日志脱敏：屏蔽机密变量的值以及 URL 中的查询参数
+// service.sh 会把全部输出重定向到 sing-box.log，用户常常直接贴出日志，因此默认开启
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+const MASK: &str = "******";
+
+// 过短的值容易误伤普通日志内容，不参与按值屏蔽
+const MIN_SECRET_LEN: usize = 4;
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+fn secrets() -> &'static Mutex<Vec<String>> {
+    static SECRETS: OnceLock<Mutex<Vec<String>>> = OnceLock::new();
+    SECRETS.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+// 变量名是否看起来像机密
+pub fn is_secret_name(name: &str) -> bool {
+    let upper = name.to_ascii_uppercase();
+    upper.ends_with("_SECRET")
+        || upper.ends_with("_PASSWORD")
+        || upper.ends_with("_TOKEN")
+        || upper.starts_with("SUB_URL_")
+}
+
+// 登记一个需要屏蔽的值
+pub fn register(value: &str) {
+    let value = value.trim();
+    if value.chars().count() < MIN_SECRET_LEN {
+        return;
+    }
+    let mut list = secrets().lock().unwrap_or_else(|e| e.into_inner());
+    if !list.iter().any(|v| v == value) {
+        list.push(value.to_string());
+        // 先替换较长的值，避免其子串被先替换导致残留
+        list.sort_by_key(|v| std::cmp::Reverse(v.len()));
+    }
+}
+
+// 登记环境变量中名称像机密的值
+pub fn register_env(vars: &HashMap<String, String>) {
+    for (k, v) in vars {
+        if is_secret_name(k) {
+            register(v);
+        }
+    }
+}
+
+// 对任意文本进行脱敏
+pub fn mask(text: &str) -> String {
+    if !ENABLED.load(Ordering::SeqCst) {
+        return text.to_string();
+    }
+    let mut out = text.to_string();
+    {
+        let list = secrets().lock().unwrap_or_else(|e| e.into_inner());
+        for secret in list.iter() {
+            if out.contains(secret.as_str()) {
+                out = out.replace(secret.as_str(), MASK);
+            }
+        }
+    }
+    mask_url_queries(&out)
+}
+
+// 屏蔽 URL 查询参数的值: https://host/path?token=abc -> https://host/path?token=******
+fn mask_url_queries(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(idx) = rest.find("://") {
+        let url_end = rest[idx..]
+            .find(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '<' | '>' | ')' | ']' | ','))
+            .map(|e| idx + e)
+            .unwrap_or(rest.len());
+        // URL 从协议名开始，之前的文本 (可能含有 `?`) 原样保留
+        let url_start = rest[..idx].char_indices()
+            .rfind(|&(_, c)| !(c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.')))
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        out.push_str(&rest[..url_start]);
+        let url = &rest[url_start..url_end];
+        match url.split_once('?') {
+            Some((base, query)) => {
+                out.push_str(base);
+                out.push('?');
+                let (query, fragment) = match query.split_once('#') {
+                    Some((q, f)) => (q, Some(f)),
+                    None => (query, None),
+                };
+                let masked: Vec<String> = query
+                    .split('&')
+                    .map(|pair| match pair.split_once('=') {
+                        Some((k, v)) if !v.is_empty() => format!("{}={}", k, MASK),
+                        _ => pair.to_string(),
+                    })
+                    .collect();
+                out.push_str(&masked.join("&"));
+                if let Some(fragment) = fragment {
+                    out.push('#');
+                    out.push_str(fragment);
+                }
+            }
+            None => out.push_str(url),
+        }
+        rest = &rest[url_end..];
+    }
+    out.push_str(rest);
+    out
+}
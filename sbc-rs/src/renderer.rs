@@ -0,0 +1,407 @@
+// 模板渲染引擎：基于 AST 的 ${VAR} 插值与 {{VAR}} 结构化替换
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::env;
+use std::path::Path;
+use log::{info, warn};
+use crate::{dotenv, redact, schema};
+
+/// 变量解析器：当环境变量中不存在时依次询问
+pub type Resolver = Box<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+/// 过滤器：在占位符替换完成后按顺序变换整棵配置树
+pub type Filter = Box<dyn Fn(Value, &HashMap<String, String>) -> Result<Value> + Send + Sync>;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SubstitutionKind {
+    /// ${VAR}: 字符串插值
+    String,
+    /// {{VAR}}: 结构化 (JSON) 替换
+    Structural,
+    /// 数组中的 {{VAR}} 展开 (Magic Unwrap)
+    Unwrap,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Substitution {
+    pub name: String,
+    pub kind: SubstitutionKind,
+    pub path: String,
+}
+
+#[derive(Serialize, Default, Clone, Debug)]
+pub struct RenderReport {
+    pub substitutions: Vec<Substitution>,
+    /// 未设置 (或为空) 的变量名，按首次出现顺序
+    pub missing: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+pub struct Rendered {
+    pub value: Value,
+    pub report: RenderReport,
+}
+
+/// 输出格式：默认与 serde_json::to_string_pretty 一致 (2 空格缩进)
+#[derive(Clone, Debug)]
+pub struct OutputFormat {
+    /// None 表示紧凑单行输出
+    pub indent: Option<usize>,
+    pub sort_keys: bool,
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        Self { indent: Some(2), sort_keys: false }
+    }
+}
+
+impl OutputFormat {
+    pub fn to_string(&self, value: &Value) -> Result<String> {
+        let sorted;
+        let value = if self.sort_keys {
+            sorted = sort_keys(value.clone());
+            &sorted
+        } else {
+            value
+        };
+        let Some(indent) = self.indent else {
+            return Ok(serde_json::to_string(value)?);
+        };
+        let indent = " ".repeat(indent);
+        let mut buf = Vec::new();
+        let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+        let mut ser = serde_json::Serializer::with_formatter(&mut buf, formatter);
+        value.serialize(&mut ser)?;
+        Ok(String::from_utf8(buf)?)
+    }
+}
+
+// 递归按键名排序 (preserve_order 下 Map 默认保持插入顺序)
+fn sort_keys(value: Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<(String, Value)> = map.into_iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            Value::Object(entries.into_iter().map(|(k, v)| (k, sort_keys(v))).collect())
+        }
+        Value::Array(arr) => Value::Array(arr.into_iter().map(sort_keys).collect()),
+        v => v,
+    }
+}
+
+/// 可复用的渲染器
+///
+/// ```no_run
+/// use sbc_rs::renderer::Renderer;
+///
+/// let rendered = Renderer::new()
+///     .process_env()
+///     .var("LOG_LEVEL", "debug")
+///     .strict(true)
+///     .render_str(r#"{ "log": { "level": "${LOG_LEVEL}" } }"#)?;
+/// println!("{}", rendered.value);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Default)]
+pub struct Renderer {
+    env: HashMap<String, String>,
+    strict: bool,
+    resolvers: Vec<Resolver>,
+    filters: Vec<Filter>,
+}
+
+impl Renderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 合并当前进程的环境变量
+    pub fn process_env(self) -> Self {
+        self.vars(env::vars())
+    }
+
+    /// 合并 .env 文件中的变量 (文件不存在时忽略)
+    pub fn env_file(self, path: &Path) -> Result<Self> {
+        let vars = dotenv::read(path)
+            .with_context(|| format!("无法读取 .env 文件: {:?}", path))?;
+        Ok(self.vars(vars))
+    }
+
+    /// 合并一组变量，后加入的同名变量覆盖先前的值
+    pub fn vars<K: Into<String>, V: Into<String>>(mut self, vars: impl IntoIterator<Item = (K, V)>) -> Self {
+        for (k, v) in vars {
+            self.env.insert(k.into(), v.into());
+        }
+        self
+    }
+
+    pub fn var(self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.vars([(key.into(), value.into())])
+    }
+
+    /// 严格模式：存在未设置的变量时渲染失败，而不是告警后继续
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn resolver(mut self, resolver: impl Fn(&str) -> Option<String> + Send + Sync + 'static) -> Self {
+        self.resolvers.push(Box::new(resolver));
+        self
+    }
+
+    pub fn filter(mut self, filter: impl Fn(Value, &HashMap<String, String>) -> Result<Value> + Send + Sync + 'static) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// 渲染模板文本 (允许 // 与 /* */ 注释)
+    pub fn render_str(&self, template: &str) -> Result<Rendered> {
+        let root: Value = serde_json::from_str(&strip_comments(template))
+            .context("无法将模板解析为有效的 JSON。请确保输入格式正确。")?;
+        self.render_value(root)
+    }
+
+    /// 渲染已解析的模板
+    pub fn render_value(&self, mut root: Value) -> Result<Rendered> {
+        let mut env = self.env.clone();
+
+        // 变量声明 ("$vars")：移出输出，并在替换前校验 .env / 填充默认值
+        if let Some(var_schema) = schema::take_schema(&mut root)? {
+            schema::apply_schema(&var_schema, &mut env)?;
+        }
+
+        let mut pass = Pass { renderer: self, env: &env, report: RenderReport::default() };
+        let mut value = pass.process_value(root, "$")?;
+        let report = pass.report;
+
+        if self.strict && !report.missing.is_empty() {
+            bail!("严格模式下存在未设置的变量: {}", report.missing.join(", "));
+        }
+
+        for filter in &self.filters {
+            value = filter(value, &env)?;
+        }
+        Ok(Rendered { value, report })
+    }
+}
+
+// 单次渲染的遍历状态
+struct Pass<'a> {
+    renderer: &'a Renderer,
+    env: &'a HashMap<String, String>,
+    report: RenderReport,
+}
+
+impl Pass<'_> {
+    fn lookup(&self, var_name: &str) -> Option<String> {
+        self.env.get(var_name).cloned()
+            .or_else(|| self.renderer.resolvers.iter().find_map(|r| r(var_name)))
+    }
+
+    fn substituted(&mut self, name: &str, kind: SubstitutionKind, path: &str) {
+        self.report.substitutions.push(Substitution { name: name.to_string(), kind, path: path.to_string() });
+    }
+
+    fn missing(&mut self, name: &str, warning: String) {
+        warn!("{}", warning);
+        self.report.warnings.push(warning);
+        if !self.report.missing.iter().any(|m| m == name) {
+            self.report.missing.push(name.to_string());
+        }
+    }
+
+    fn process_value(&mut self, v: Value, path: &str) -> Result<Value> {
+        match v {
+            Value::Object(map) => {
+                let mut new_map = Map::new();
+                for (k, v) in map {
+                    let processed_v = self.process_value(v, &format!("{}.{}", path, k))?;
+                    new_map.insert(k, processed_v);
+                }
+                Ok(Value::Object(new_map))
+            }
+            Value::Array(arr) => {
+                let mut new_arr = Vec::new();
+                for (i, v) in arr.into_iter().enumerate() {
+                    let item_path = format!("{}[{}]", path, i);
+                    // 检查数组项级别的 {{VAR}} (Magic Unwrap 候选)
+                    if let Value::String(ref s) = v {
+                        if let Some(var_name) = extract_structural_placeholder(s) {
+                            if let Some(parsed_val) = self.resolve_json_var(var_name)? {
+                                // Magic Unwrap: 如果是数组则展开
+                                if let Value::Array(inner_arr) = parsed_val {
+                                    info!("发现数组占位符 {{{{{}}}}}，正在展开数组。", var_name);
+                                    self.substituted(var_name, SubstitutionKind::Unwrap, &item_path);
+                                    for inner_item in inner_arr {
+                                        new_arr.push(self.process_value(inner_item, &item_path)?);
+                                    }
+                                } else {
+                                    // 不是数组，直接添加
+                                    self.substituted(var_name, SubstitutionKind::Structural, &item_path);
+                                    new_arr.push(self.process_value(parsed_val, &item_path)?);
+                                }
+                            } else {
+                                self.missing(var_name, format!("数组中的占位符 {{{{{}}}}} 未找到或为空，跳过该项。", var_name));
+                            }
+                            continue;
+                        }
+                    }
+                    new_arr.push(self.process_value(v, &item_path)?);
+                }
+                Ok(Value::Array(new_arr))
+            }
+            Value::String(s) => {
+                // 通用字符串处理
+                // 1. 检查结构化替换 {{VAR}} (有效的 JSON 对象替换)
+                if let Some(var_name) = extract_structural_placeholder(&s) {
+                    if let Some(parsed_val) = self.resolve_json_var(var_name)? {
+                        info!("发现结构化占位符 {{{{{}}}}}，正在替换为解析后的值。", var_name);
+                        self.substituted(var_name, SubstitutionKind::Structural, path);
+                        return self.process_value(parsed_val, path);
+                    } else {
+                        self.missing(var_name, format!("值中的占位符 {{{{{}}}}} 未找到或为空，保留原样。", var_name));
+                        return Ok(Value::String(s));
+                    }
+                }
+
+                // 2. 字符串插值 ${VAR}
+                Ok(Value::String(self.interpolate_string(&s, path)))
+            }
+            _ => Ok(v),
+        }
+    }
+
+    // 查找并解析变量为 JSON
+    fn resolve_json_var(&self, var_name: &str) -> Result<Option<Value>> {
+        let Some(raw) = self.lookup(var_name) else { return Ok(None) };
+        let env_val = raw.trim();
+        if env_val.is_empty() {
+            return Ok(None);
+        }
+        let parsed: Value = serde_json::from_str(env_val)
+            .with_context(|| format!("无法将环境变量 '{}' 解析为 JSON: {}", var_name, redact::mask(env_val)))?;
+        Ok(Some(parsed))
+    }
+
+    // 简单地插值 ${VAR}
+    // 注意：这个简单的实现不处理转义，也不会递归插值变量值中的 ${...}。
+    fn interpolate_string(&mut self, s: &str, path: &str) -> String {
+        let mut out = String::with_capacity(s.len());
+        let mut rest = s;
+        while let Some(start) = rest.find("${") {
+            let Some(end_offset) = rest[start..].find('}') else { break };
+            let end = start + end_offset;
+            let var_name = &rest[start + 2..end];
+
+            // 检查是否主要是字母数字
+            if is_var_name(var_name) {
+                out.push_str(&rest[..start]);
+                if let Some(val) = self.lookup(var_name) {
+                    out.push_str(&val);
+                    self.substituted(var_name, SubstitutionKind::String, path);
+                } else {
+                    // 与 envsubst 的行为一致：未找到的变量替换为空字符串
+                    self.missing(var_name, format!("变量 ${{{}}} 未找到，替换为空字符串。", var_name));
+                }
+            } else {
+                // 不是有效的变量名，原样保留
+                out.push_str(&rest[..=end]);
+            }
+            rest = &rest[end + 1..];
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+fn is_var_name(name: &str) -> bool {
+    name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// 检查精确的 "{{VAR}}" 模式
+pub fn extract_structural_placeholder(s: &str) -> Option<&str> {
+    if s.starts_with("{{") && s.ends_with("}}") {
+        // 仅检查括号就足以作为强信号
+        Some(s[2..s.len() - 2].trim())
+    } else {
+        None
+    }
+}
+
+/// 列出字符串中所有会被插值的 ${VAR} 名称 (与插值的识别规则一致)
+pub fn scan_interpolations(s: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        let Some(end_offset) = rest[start..].find('}') else { break };
+        let end = start + end_offset;
+        let var_name = &rest[start + 2..end];
+        if is_var_name(var_name) {
+            names.push(var_name);
+        }
+        rest = &rest[end + 1..];
+    }
+    names
+}
+
+/// 移除 // 行注释与 /* */ 块注释 (字符串内部除外)
+pub fn strip_comments(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    let mut in_quote = false;
+    let mut escaped = false;
+
+    while let Some(c) = chars.next() {
+        if in_quote {
+            out.push(c);
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_quote = false;
+            }
+        } else {
+            // 检查注释开始
+            if c == '/' {
+                if let Some(&next_c) = chars.peek() {
+                    if next_c == '/' {
+                        // 行注释: 跳过直到换行符
+                        chars.next(); // 消耗第二个 /
+                        while let Some(&nc) = chars.peek() {
+                            if nc == '\n' {
+                                break;
+                            }
+                            chars.next();
+                        }
+                        continue;
+                    } else if next_c == '*' {
+                        // Block comment: skip until */
+                        chars.next(); // consume *
+                        while let Some(nc) = chars.next() {
+                            if nc == '*' {
+                                if let Some(&nnc) = chars.peek() {
+                                    if nnc == '/' {
+                                        chars.next(); // consume /
+                                        break;
+                                    }
+                                }
+                            }
+                        }
+                        continue;
+                    }
+                }
+            }
+            if c == '"' {
+                in_quote = true;
+            }
+            out.push(c);
+        }
+    }
+    out
+}
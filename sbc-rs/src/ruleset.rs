@@ -0,0 +1,153 @@
+// 远程规则集的本地缓存：`ruleset sync` 负责下载，渲染时按需改写为 type: local
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::checksum;
+use crate::settings::{RulesetSettings, StalePolicy};
+
+/// 缓存目录中的索引文件
+pub const INDEX_FILE: &str = "index.json";
+
+// 改写为本地规则集时需要移除的远程专用字段
+const REMOTE_ONLY: [&str; 3] = ["url", "download_detour", "update_interval"];
+
+/// 配置中 type 为 remote 的规则集
+pub struct RemoteRuleSet {
+    pub tag: String,
+    pub url: String,
+    pub format: Option<String>,
+}
+
+/// 收集 route.rule_set 中的远程规则集 (同一 URL 只取一次)
+pub fn remote_rule_sets(config: &Value) -> Vec<RemoteRuleSet> {
+    let mut list: Vec<RemoteRuleSet> = Vec::new();
+    let entries = config.pointer("/route/rule_set").and_then(Value::as_array).into_iter().flatten();
+    for entry in entries {
+        let field = |name: &str| entry.get(name).and_then(Value::as_str).map(str::to_string);
+        if field("type").as_deref() != Some("remote") {
+            continue;
+        }
+        let (Some(tag), Some(url)) = (field("tag"), field("url")) else {
+            continue;
+        };
+        if !list.iter().any(|r| r.url == url) {
+            list.push(RemoteRuleSet { tag, url, format: field("format") });
+        }
+    }
+    list
+}
+
+/// 缓存中的一个规则集
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CacheEntry {
+    pub tag: String,
+    /// 缓存目录中的文件名
+    pub file: String,
+    pub sha256: String,
+    pub size: u64,
+    /// 最近一次下载到新内容的时间 (RFC 3339)
+    pub fetched: String,
+    /// 最近一次确认内容为最新 (下载或 304) 的时间，过期判断以此为准
+    pub checked: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+/// 缓存索引：URL -> 缓存条目
+#[derive(Serialize, Deserialize, Default)]
+pub struct CacheIndex {
+    pub entries: BTreeMap<String, CacheEntry>,
+}
+
+impl CacheIndex {
+    /// 读取索引，不存在或损坏时视为空缓存
+    pub fn load(cache_dir: &Path) -> Self {
+        fs::read_to_string(cache_dir.join(INDEX_FILE))
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, cache_dir: &Path) -> Result<()> {
+        let path = cache_dir.join(INDEX_FILE);
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, &path).with_context(|| format!("无法写入规则集索引: {:?}", path))
+    }
+}
+
+/// 缓存文件名：可读的 tag 加 URL 摘要，扩展名沿用 URL (供 sing-box 推断格式)
+pub fn cache_file_name(rule_set: &RemoteRuleSet) -> String {
+    let tag: String = rule_set.tag.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+        .collect();
+    let path = rule_set.url.split(['?', '#']).next().unwrap_or_default();
+    let ext = match (Path::new(path).extension().and_then(|e| e.to_str()), rule_set.format.as_deref()) {
+        (Some(ext @ ("srs" | "json")), _) => ext,
+        (_, Some("source")) => "json",
+        _ => "srs",
+    };
+    format!("{}-{}.{}", tag, &checksum::sha256_hex(rule_set.url.as_bytes())[..8], ext)
+}
+
+/// 缓存条目是否超过 max_age_hours 未确认 (时间无法解析时同样视为过期)
+pub fn is_stale(entry: &CacheEntry, settings: &RulesetSettings, now: DateTime<Utc>) -> bool {
+    DateTime::parse_from_rfc3339(&entry.checked)
+        .map_or(true, |checked| now.signed_duration_since(checked) > chrono::Duration::hours(settings.max_age_hours as i64))
+}
+
+/// 渲染过滤器：将有可用缓存的远程规则集改写为本地文件。
+/// 缓存缺失或校验失败时保留远程地址；过期缓存按 stale 策略处理
+pub fn local_filter(cache_dir: PathBuf, settings: RulesetSettings) -> impl Fn(Value, &HashMap<String, String>) -> Result<Value> + Send + Sync + 'static {
+    move |mut config, _env| {
+        let index = CacheIndex::load(&cache_dir);
+        let now = Utc::now();
+        let Some(entries) = config.pointer_mut("/route/rule_set").and_then(Value::as_array_mut) else {
+            return Ok(config);
+        };
+        for entry in entries.iter_mut().filter_map(Value::as_object_mut) {
+            if entry.get("type").and_then(Value::as_str) != Some("remote") {
+                continue;
+            }
+            let Some(url) = entry.get("url").and_then(Value::as_str) else {
+                continue;
+            };
+            let tag = entry.get("tag").and_then(Value::as_str).unwrap_or_default().to_string();
+            let Some(cached) = index.entries.get(url) else {
+                warn!("⚠️ 规则集 {} 尚未缓存，使用远程地址 (可执行 sbc-rs ruleset sync)", tag);
+                continue;
+            };
+            let stale = is_stale(cached, &settings, now);
+            if stale && settings.stale == StalePolicy::Remote {
+                warn!("⚠️ 规则集 {} 的缓存已过期 ({})，改用远程地址", tag, cached.checked);
+                continue;
+            }
+            let path = cache_dir.join(&cached.file);
+            let intact = fs::read(&path).is_ok_and(|data| checksum::sha256_hex(&data) == cached.sha256);
+            if !intact {
+                warn!("⚠️ 规则集 {} 的缓存文件缺失或校验失败，使用远程地址: {:?}", tag, path);
+                continue;
+            }
+            if stale {
+                warn!("⚠️ 规则集 {} 的缓存已过期 ({})，仍使用本地副本 (可执行 sbc-rs ruleset sync 刷新)", tag, cached.checked);
+            }
+            localize(entry, &path);
+        }
+        Ok(config)
+    }
+}
+
+fn localize(entry: &mut Map<String, Value>, path: &Path) {
+    for key in REMOTE_ONLY {
+        entry.remove(key);
+    }
+    entry.insert("type".into(), "local".into());
+    entry.insert("path".into(), path.to_string_lossy().into());
+}
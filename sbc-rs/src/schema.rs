@@ -0,0 +1,161 @@
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use log::info;
+use crate::redact;
+
+// 模板顶层的变量声明块，渲染时会从输出中移除
+pub const SCHEMA_KEY: &str = "$vars";
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum VarType {
+    #[default]
+    String,
+    Url,
+    Port,
+    /// JSON 对象或数组
+    Json,
+    JsonArray,
+    JsonObject,
+    Enum,
+}
+
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct VarSpec {
+    #[serde(rename = "type", default)]
+    pub var_type: VarType,
+    #[serde(default)]
+    pub default: Option<Value>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub secret: bool,
+    #[serde(default)]
+    pub required: bool,
+    /// enum 类型的可选值
+    #[serde(default)]
+    pub values: Vec<String>,
+}
+
+impl VarSpec {
+    // 默认值的 .env 文本形式：字符串原样使用，其余按 JSON 序列化
+    pub fn default_text(&self) -> Option<String> {
+        match &self.default {
+            None | Some(Value::Null) => None,
+            Some(Value::String(s)) => Some(s.clone()),
+            Some(v) => Some(v.to_string()),
+        }
+    }
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct VarSchema {
+    pub vars: Vec<(String, VarSpec)>,
+}
+
+impl VarSchema {
+    pub fn get(&self, name: &str) -> Option<&VarSpec> {
+        self.vars.iter().find(|(n, _)| n == name).map(|(_, spec)| spec)
+    }
+}
+
+// 从模板根对象中取出并移除 "$vars" 声明块
+pub fn take_schema(root: &mut Value) -> Result<Option<VarSchema>> {
+    let Some(block) = root.as_object_mut().and_then(|map| map.shift_remove(SCHEMA_KEY)) else {
+        return Ok(None);
+    };
+    let Value::Object(map) = block else {
+        bail!("模板中的 \"{}\" 必须是一个 JSON 对象", SCHEMA_KEY);
+    };
+    let mut vars = Vec::with_capacity(map.len());
+    for (name, spec) in map {
+        let spec: VarSpec = serde_json::from_value(spec)
+            .with_context(|| format!("变量 {} 的声明无效", name))?;
+        if spec.var_type == VarType::Enum && spec.values.is_empty() {
+            bail!("变量 {} 的类型为 enum，但未声明 values", name);
+        }
+        vars.push((name, spec));
+    }
+    Ok(Some(VarSchema { vars }))
+}
+
+// 在替换前校验环境变量并填充默认值，所有问题汇总后一次性报告
+pub fn apply_schema(schema: &VarSchema, env: &mut HashMap<String, String>) -> Result<()> {
+    let mut errors = Vec::new();
+
+    for (name, spec) in &schema.vars {
+        if spec.secret
+            && let Some(v) = env.get(name)
+        {
+            redact::register(v);
+        }
+        let current = env.get(name).map(|v| v.trim()).filter(|v| !v.is_empty());
+        let value = match (current, spec.default_text()) {
+            (Some(v), _) => v.to_string(),
+            (None, Some(default)) => {
+                info!("变量 {} 未设置，使用默认值。", name);
+                env.insert(name.clone(), default.clone());
+                default
+            }
+            (None, None) => {
+                if spec.required {
+                    errors.push(format!("{} 是必填项，但未设置", name));
+                }
+                continue;
+            }
+        };
+        if let Err(msg) = validate(name, spec, &value) {
+            errors.push(msg);
+        }
+    }
+
+    if !errors.is_empty() {
+        bail!("环境变量校验失败:\n  - {}", errors.join("\n  - "));
+    }
+    Ok(())
+}
+
+fn validate(name: &str, spec: &VarSpec, value: &str) -> Result<(), String> {
+    match spec.var_type {
+        VarType::String => Ok(()),
+        VarType::Url => {
+            if is_url(value) { Ok(()) } else { Err(format!("{} 不是有效的 URL", name)) }
+        }
+        VarType::Port => match value.parse::<u16>() {
+            Ok(port) if port > 0 => Ok(()),
+            _ => Err(format!("{} 不是有效的端口 (1-65535)", name)),
+        },
+        VarType::Json => match serde_json::from_str::<Value>(value) {
+            Ok(Value::Object(_)) | Ok(Value::Array(_)) => Ok(()),
+            _ => Err(format!("{} 必须是 JSON 对象或数组", name)),
+        },
+        VarType::JsonArray => match serde_json::from_str::<Value>(value) {
+            Ok(Value::Array(_)) => Ok(()),
+            _ => Err(format!("{} 必须是 JSON 数组", name)),
+        },
+        VarType::JsonObject => match serde_json::from_str::<Value>(value) {
+            Ok(Value::Object(_)) => Ok(()),
+            _ => Err(format!("{} 必须是 JSON 对象", name)),
+        },
+        VarType::Enum => {
+            if spec.values.iter().any(|v| v == value) {
+                Ok(())
+            } else {
+                Err(format!("{} 必须是以下值之一: {}", name, spec.values.join(", ")))
+            }
+        }
+    }
+}
+
+// 简单的 URL 校验：scheme://host...
+fn is_url(value: &str) -> bool {
+    let Some((scheme, rest)) = value.split_once("://") else { return false };
+    !scheme.is_empty()
+        && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        && !rest.is_empty()
+        && !rest.starts_with('/')
+        && !value.chars().any(char::is_whitespace)
+}
@@ -0,0 +1,133 @@
+// 渲染脚本钩子 (Rhai)：对渲染后的配置树做占位符无法表达的自定义变换
+//
+// 脚本可见两个变量：
+//   config - 渲染后的配置树 (可原地修改)
+//   env    - 当前环境变量 (只读)
+// 脚本的最后一个表达式若返回对象则作为新配置，否则使用 (修改后的) config。
+//
+// 沙箱：未启用模块系统 (no_module)，Rhai 标准库本身没有文件/网络访问能力；
+// 另有运算次数、调用深度、数据大小与执行时间的限制。
+use anyhow::{Context, Result, anyhow};
+use rhai::{Dynamic, Engine, EvalAltResult, Map as RhaiMap, Position, Scope};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use log::{debug, info};
+
+/// 脚本执行时间上限
+pub const DEFAULT_TIME_BUDGET: Duration = Duration::from_secs(3);
+
+fn build_engine(script_name: String, budget: Duration) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(50_000_000);
+    engine.set_max_call_levels(64);
+    engine.set_max_expr_depths(128, 64);
+    engine.set_max_string_size(4 * 1024 * 1024);
+    engine.set_max_array_size(200_000);
+    engine.set_max_map_size(200_000);
+
+    let started = Instant::now();
+    engine.on_progress(move |_| {
+        if started.elapsed() > budget {
+            Some(Dynamic::from("timeout"))
+        } else {
+            None
+        }
+    });
+
+    let name = script_name.clone();
+    engine.on_print(move |s| info!("📜 [{}] {}", name, s));
+    engine.on_debug(move |s, _, pos| debug!("📜 [{}] {} {}", script_name, fmt_position(pos), s));
+    engine
+}
+
+fn fmt_position(pos: Position) -> String {
+    match (pos.line(), pos.position()) {
+        (Some(line), Some(col)) => format!("第 {} 行第 {} 列", line, col),
+        (Some(line), None) => format!("第 {} 行", line),
+        _ => "未知位置".to_string(),
+    }
+}
+
+fn eval_error(path: &Path, mut err: EvalAltResult, budget: Duration) -> anyhow::Error {
+    // 位置单独格式化，避免错误信息中重复出现
+    let pos = fmt_position(err.take_position());
+    match err {
+        EvalAltResult::ErrorTerminated(..) => {
+            anyhow!("渲染脚本 {:?} 执行超时 (超过 {:?})，{}", path, budget, pos)
+        }
+        other => anyhow!("渲染脚本 {:?} 执行失败 ({}): {}", path, pos, other),
+    }
+}
+
+// Rhai 的对象按键名排序，这里按原配置的键顺序还原，新增的键追加在后
+fn restore_key_order(value: Value, original: &Value) -> Value {
+    match (value, original) {
+        (Value::Object(mut map), Value::Object(orig)) => {
+            let mut ordered = serde_json::Map::new();
+            for (k, orig_v) in orig {
+                if let Some(v) = map.remove(k) {
+                    ordered.insert(k.clone(), restore_key_order(v, orig_v));
+                }
+            }
+            ordered.extend(map);
+            Value::Object(ordered)
+        }
+        (Value::Array(arr), Value::Array(orig)) => Value::Array(
+            arr.into_iter()
+                .enumerate()
+                .map(|(i, v)| match orig.get(i) {
+                    Some(orig_v) => restore_key_order(v, orig_v),
+                    None => v,
+                })
+                .collect(),
+        ),
+        (v, _) => v,
+    }
+}
+
+/// 使用脚本变换配置树
+pub fn run_script(path: &Path, config: Value, env: &HashMap<String, String>, budget: Duration) -> Result<Value> {
+    let source = fs::read_to_string(path)
+        .with_context(|| format!("读取渲染脚本失败: {:?}", path))?;
+    let script_name = path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "script".to_string());
+    let engine = build_engine(script_name, budget);
+
+    let ast = engine.compile(&source)
+        .map_err(|e| anyhow!("渲染脚本 {:?} 语法错误 ({}): {}", path, fmt_position(e.position()), e.err_type()))?;
+
+    let mut env_map = RhaiMap::new();
+    for (k, v) in env {
+        env_map.insert(k.as_str().into(), Dynamic::from(v.clone()));
+    }
+
+    let mut scope = Scope::new();
+    scope.push("config", rhai::serde::to_dynamic(&config).map_err(|e| anyhow!("{}", e))?);
+    scope.push_constant("env", env_map);
+
+    info!("📜 正在执行渲染脚本: {:?}", path);
+    let result: Dynamic = engine.eval_ast_with_scope(&mut scope, &ast)
+        .map_err(|e| eval_error(path, *e, budget))?;
+
+    let output = if result.is_map() {
+        result
+    } else {
+        scope.get_value::<Dynamic>("config")
+            .ok_or_else(|| anyhow!("渲染脚本 {:?} 移除了 config 变量", path))?
+    };
+    if !output.is_map() {
+        return Err(anyhow!("渲染脚本 {:?} 必须返回对象，实际为 {}", path, output.type_name()));
+    }
+    let value = rhai::serde::from_dynamic::<Value>(&output)
+        .map_err(|e| anyhow!("渲染脚本 {:?} 的返回值无法转换为 JSON: {}", path, e))?;
+    Ok(restore_key_order(value, &config))
+}
+
+/// 作为 Renderer 过滤器使用的脚本阶段
+pub fn script_filter(path: PathBuf) -> impl Fn(Value, &HashMap<String, String>) -> Result<Value> + Send + Sync + 'static {
+    move |config, env| run_script(&path, config, env, DEFAULT_TIME_BUDGET)
+}
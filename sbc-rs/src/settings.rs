@@ -0,0 +1,480 @@
+// 工作空间与设置文件 (etc/sbc.toml)
+// 所有命令共用同一解析顺序：--workspace > 环境变量 WORKSPACE > 默认路径
+use anyhow::{Context, Result, bail};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 默认工作空间
+pub const DEFAULT_WORKSPACE: &str = "/data/adb/sing-box-workspace";
+/// 设置文件相对工作空间的路径
+pub const SETTINGS_FILE: &str = "etc/sbc.toml";
+
+/// 工作空间内的文件位置 (相对路径基于工作空间，也可写绝对路径)
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Paths {
+    pub config: PathBuf,
+    pub template: PathBuf,
+    pub env_file: PathBuf,
+    pub env_example: PathBuf,
+    pub pubkey: PathBuf,
+    pub script: PathBuf,
+    pub pid_file: PathBuf,
+    pub log_file: PathBuf,
+    pub working_dir: PathBuf,
+    pub bin_dir: PathBuf,
+}
+
+impl Default for Paths {
+    fn default() -> Self {
+        Self {
+            config: "etc/config.json".into(),
+            template: "config.template.json".into(),
+            env_file: ".env".into(),
+            env_example: ".env.example".into(),
+            pubkey: "etc/template.pub".into(),
+            script: "etc/render.rhai".into(),
+            pid_file: "var/run/sing-box.pid".into(),
+            log_file: "var/log/sing-box.log".into(),
+            working_dir: "var/lib/sing-box".into(),
+            bin_dir: "bin".into(),
+        }
+    }
+}
+
+/// URL 中代表发布通道的占位符
+pub const CHANNEL_PLACEHOLDER: &str = "{channel}";
+
+/// `update` 的下载来源与策略
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct UpdateSettings {
+    /// 当前发布通道 (即模板仓库在 Pages 上的分支路径)
+    pub channel: String,
+    /// 通道索引 (channels.json) 地址
+    pub channel_index: String,
+    /// 配置模板地址，多个时按顺序作为镜像尝试；{channel} 替换为当前通道
+    pub template_url: Vec<String>,
+    /// .env.example 地址
+    pub env_example_url: Vec<String>,
+    /// 下载代理 (URL / auto / direct)，未设置时遵循 HTTP(S)_PROXY 等环境变量
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// 保留的模板历史版本数
+    pub keep_history: usize,
+    /// 私有模板源的认证 ([[update.auth]])
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub auth: Vec<SourceAuth>,
+    /// 未固定公钥 (paths.pubkey 不存在) 时跳过签名校验；一旦固定了公钥，签名始终强制校验
+    pub allow_unpinned: bool,
+}
+
+/// 私有模板源的认证方式。各字段填写的是 .env 中的变量名，凭证本身不写入设置文件，也不经由命令行传递
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct SourceAuth {
+    /// 适用的地址前缀，多条匹配时取最长者
+    pub prefix: String,
+    /// Bearer 令牌
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bearer: Option<String>,
+    /// Basic 认证用户名
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Basic 认证密码
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// 任意请求头 -> 变量名
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+}
+
+impl UpdateSettings {
+    /// 代入通道后的模板地址 (channel 为 None 时使用当前通道)
+    pub fn template_urls(&self, channel: Option<&str>) -> Vec<String> {
+        self.resolve(&self.template_url, channel)
+    }
+
+    /// 代入通道后的 .env.example 地址
+    pub fn env_example_urls(&self, channel: Option<&str>) -> Vec<String> {
+        self.resolve(&self.env_example_url, channel)
+    }
+
+    /// 地址中是否使用了 {channel}，否则切换通道没有意义
+    pub fn has_channels(&self) -> bool {
+        self.template_url.iter().any(|u| u.contains(CHANNEL_PLACEHOLDER))
+    }
+
+    fn resolve(&self, patterns: &[String], channel: Option<&str>) -> Vec<String> {
+        let channel = channel.unwrap_or(&self.channel);
+        patterns.iter().map(|p| p.replace(CHANNEL_PLACEHOLDER, channel)).collect()
+    }
+}
+
+/// 通道名只允许字母、数字与 `.` `_` `-`，避免拼接出意外的 URL
+pub fn validate_channel(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+    if !valid {
+        bail!("无效的通道名: {:?}", name);
+    }
+    Ok(())
+}
+
+impl Default for UpdateSettings {
+    fn default() -> Self {
+        Self {
+            channel: "mobile".into(),
+            channel_index: "https://miceworld.top/sing-box-config-templates/channels.json".into(),
+            template_url: vec!["https://miceworld.top/sing-box-config-templates/{channel}/config.template.json".into()],
+            env_example_url: vec!["https://miceworld.top/sing-box-config-templates/{channel}/.env.example".into()],
+            proxy: None,
+            keep_history: 5,
+            auth: Vec::new(),
+            allow_unpinned: false,
+        }
+    }
+}
+
+/// 监护进程参数
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DaemonSettings {
+    /// sing-box 异常退出后的最大重启次数
+    pub max_retries: u32,
+    /// 两次重启之间的等待时间 (秒)
+    pub retry_delay: u64,
+    /// 日志超过该大小 (字节) 时轮转
+    pub log_max_bytes: u64,
+}
+
+impl Default for DaemonSettings {
+    fn default() -> Self {
+        Self {
+            max_retries: 4,
+            retry_delay: 10,
+            log_max_bytes: 1024 * 1024,
+        }
+    }
+}
+
+/// `self-update` 的更新清单与回滚策略
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SelfUpdateSettings {
+    /// 更新清单 (update.json) 地址，未设置时取 module.prop 中的 updateJson
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifest_url: Option<String>,
+    /// 模块的 module.prop，用于读取当前 versionCode
+    pub module_prop: PathBuf,
+    /// 替换后 (或 update --apply 重启后) 等待多少秒确认 sing-box 仍在运行，否则回滚
+    pub health_check_secs: u64,
+}
+
+impl Default for SelfUpdateSettings {
+    fn default() -> Self {
+        Self {
+            manifest_url: None,
+            module_prop: "/data/adb/modules/sing-box-ksu-module/module.prop".into(),
+            health_check_secs: 5,
+        }
+    }
+}
+
+/// 过期规则集缓存的处理方式
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StalePolicy {
+    /// 仍使用本地副本，离线冷启动时也能加载
+    #[default]
+    Local,
+    /// 改回远程地址，由 sing-box 自行下载
+    Remote,
+}
+
+/// `ruleset sync` 的缓存与渲染时的规则集本地化
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct RulesetSettings {
+    /// 渲染时将已缓存的远程规则集改写为本地文件 (type: local)
+    pub local: bool,
+    /// 缓存目录
+    pub cache_dir: PathBuf,
+    /// 距上次成功同步超过该时长 (小时) 视为过期
+    pub max_age_hours: u64,
+    /// 过期缓存的处理方式
+    pub stale: StalePolicy,
+    /// 并行下载数
+    pub jobs: usize,
+}
+
+impl Default for RulesetSettings {
+    fn default() -> Self {
+        Self {
+            local: false,
+            cache_dir: "var/lib/rulesets".into(),
+            max_age_hours: 168,
+            stale: StalePolicy::Local,
+            jobs: 4,
+        }
+    }
+}
+
+/// 订阅重命名规则：按顺序对节点名执行正则替换 (replace 中可用 $1 等捕获组)
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RenameRule {
+    pub pattern: String,
+    pub replace: String,
+}
+
+/// 本地订阅网关：渲染时将 providers 的订阅地址改写为本机地址，由 sbc-rs 代为下载、缓存与过滤
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SubscriptionSettings {
+    /// 启用网关 (`run` 启动时一并监听，渲染时改写 providers 的 url)
+    pub gateway: bool,
+    /// 监听地址 (仅应使用本机地址)
+    pub listen: String,
+    /// 订阅缓存目录 (保存最近一次可用的上游内容)
+    pub cache_dir: PathBuf,
+    /// 仅保留名称匹配该正则的节点 (空表示不过滤)
+    pub include: String,
+    /// 丢弃名称匹配该正则的节点 (空表示不过滤)
+    pub exclude: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rename: Vec<RenameRule>,
+    /// 网关返回前将分享链接与 Clash YAML 转换为 sing-box JSON (供核心无法直接解析的订阅使用)
+    pub convert: bool,
+    /// `sub check` 在剩余流量低于此值 (GB) 时发出警告 (0 表示不检查)
+    pub warn_traffic_gb: f64,
+    /// `sub check` 在距到期不足此天数时发出警告 (0 表示不检查)
+    pub warn_days: u32,
+}
+
+impl Default for SubscriptionSettings {
+    fn default() -> Self {
+        Self {
+            gateway: false,
+            listen: "127.0.0.1:9099".into(),
+            cache_dir: "var/lib/subscriptions".into(),
+            include: String::new(),
+            exclude: "剩余流量|套餐到期|到期时间|过期时间|官网|重置".into(),
+            rename: Vec::new(),
+            convert: false,
+            warn_traffic_gb: 10.0,
+            warn_days: 7,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Settings {
+    pub paths: Paths,
+    pub update: UpdateSettings,
+    pub daemon: DaemonSettings,
+    pub self_update: SelfUpdateSettings,
+    pub ruleset: RulesetSettings,
+    pub subscription: SubscriptionSettings,
+}
+
+impl Settings {
+    /// 读取设置文件，文件不存在时使用默认值。
+    /// 解析是宽松的：未知的键被忽略，无法解析的表整体使用默认值，文件无法读取或不是有效的 TOML 时全部使用默认值，
+    /// 问题只记录为警告，一处笔误不会让 run / stop 与开机脚本失败。严格检查见 [`Settings::validate`]
+    pub fn load(path: &Path) -> Result<Self> {
+        let (settings, problems) = Self::parse(path)?;
+        for problem in &problems {
+            warn!("⚠️ 设置文件 {:?}: {}", path, problem);
+        }
+        Ok(settings)
+    }
+
+    /// 严格检查设置文件，返回发现的全部问题 (为空表示有效)
+    pub fn validate(path: &Path) -> Result<Vec<String>> {
+        Ok(Self::parse(path)?.1)
+    }
+
+    fn parse(path: &Path) -> Result<(Self, Vec<String>)> {
+        if !path.exists() {
+            return Ok((Self::default(), Vec::new()));
+        }
+        let raw: toml::Table = match fs::read_to_string(path).map_err(anyhow::Error::from).and_then(|c| Ok(c.parse()?)) {
+            Ok(raw) => raw,
+            Err(e) => return Ok((Self::default(), vec![format!("无法解析，全部使用默认值: {}", e)])),
+        };
+
+        // 逐表解析，出错的表整体回退为默认值，不影响其他表
+        let mut problems = Vec::new();
+        let mut accepted = toml::Table::new();
+        for (name, value) in raw {
+            let single = toml::Table::from_iter([(name.clone(), value.clone())]);
+            match toml::Value::Table(single).try_into::<Settings>() {
+                Ok(_) => {
+                    accepted.insert(name, value);
+                }
+                Err(e) => problems.push(format!("[{}] 无效，该表使用默认值: {}", name, e.to_string().trim().replace('\n', " "))),
+            }
+        }
+        let accepted = toml::Value::Table(accepted);
+        let settings: Settings = accepted.clone().try_into().context("设置文件格式无效")?;
+        unknown_keys(&accepted, &toml::Value::try_from(&settings)?, "", &mut problems);
+        Ok((settings, problems))
+    }
+
+    /// 将单个字符串键写入设置文件的指定表中；只改动该键所在的一行，其余内容与注释保持不变
+    pub fn store(path: &Path, table: &str, key: &str, value: &str) -> Result<()> {
+        let content = fs::read_to_string(path).unwrap_or_default();
+        let line = format!("{} = {}", key, toml::Value::String(value.to_string()));
+        let header = format!("[{}]", table);
+
+        let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+        let mut current = String::new();
+        let mut table_end = None;
+        let mut existing = None;
+        for (i, l) in lines.iter().enumerate() {
+            let trimmed = l.trim();
+            if trimmed.starts_with('[') {
+                current = trimmed.to_string();
+                continue;
+            }
+            if current == header {
+                // 新键放在表内最后一个键之后，不跟在属于下一个表的注释后面
+                if !trimmed.is_empty() && !trimmed.starts_with('#') {
+                    table_end = Some(i + 1);
+                }
+                if trimmed.split_once('=').is_some_and(|(k, _)| k.trim() == key) {
+                    existing = Some(i);
+                }
+            }
+        }
+        match (existing, table_end) {
+            (Some(i), _) => lines[i] = line,
+            (None, Some(end)) => lines.insert(end, line),
+            // 表不存在 (或为空) 时在末尾追加
+            (None, None) => {
+                if lines.iter().any(|l| l.trim() == header) {
+                    let pos = lines.iter().position(|l| l.trim() == header).unwrap_or_default();
+                    lines.insert(pos + 1, line);
+                } else {
+                    if lines.last().is_some_and(|l| !l.trim().is_empty()) {
+                        lines.push(String::new());
+                    }
+                    lines.push(header);
+                    lines.push(line);
+                }
+            }
+        }
+
+        let mut updated = lines.join("\n");
+        updated.push('\n');
+        // 写入前确认结果仍是合法的设置文件
+        toml::from_str::<Settings>(&updated)
+            .with_context(|| format!("更新设置文件失败: {:?}", path))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, updated)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+// 原文中有、解析结果中却没有的键即为未知键 (空数组或空表可能只是未序列化的默认值，不报告)
+fn unknown_keys(raw: &toml::Value, known: &toml::Value, prefix: &str, out: &mut Vec<String>) {
+    match (raw, known) {
+        (toml::Value::Table(raw), toml::Value::Table(known)) => {
+            for (key, value) in raw {
+                let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                match known.get(key) {
+                    Some(known) => unknown_keys(value, known, &path, out),
+                    None if is_empty(value) => {}
+                    None => out.push(format!("未知的设置项 {}，已忽略", path)),
+                }
+            }
+        }
+        (toml::Value::Array(raw), toml::Value::Array(known)) => {
+            for (i, (value, known)) in raw.iter().zip(known).enumerate() {
+                unknown_keys(value, known, &format!("{}[{}]", prefix, i), out);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn is_empty(value: &toml::Value) -> bool {
+    match value {
+        toml::Value::Array(items) => items.is_empty(),
+        toml::Value::Table(table) => table.is_empty(),
+        _ => false,
+    }
+}
+
+/// 已解析的工作空间及其设置
+pub struct Workspace {
+    pub root: PathBuf,
+    pub settings: Settings,
+}
+
+impl Workspace {
+    /// 按 --workspace > WORKSPACE > 默认路径 的顺序确定工作空间，并加载其中的 etc/sbc.toml
+    pub fn resolve(flag: Option<PathBuf>) -> Result<Self> {
+        let root = flag
+            .or_else(|| env::var_os("WORKSPACE").filter(|v| !v.is_empty()).map(PathBuf::from))
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_WORKSPACE));
+        let settings = Settings::load(&root.join(SETTINGS_FILE))?;
+        Ok(Self { root, settings })
+    }
+
+    /// 将设置中的相对路径解析到工作空间下
+    pub fn path(&self, rel: &Path) -> PathBuf {
+        self.root.join(rel)
+    }
+
+    pub fn config(&self) -> PathBuf { self.path(&self.settings.paths.config) }
+    pub fn template(&self) -> PathBuf { self.path(&self.settings.paths.template) }
+    pub fn env_file(&self) -> PathBuf { self.path(&self.settings.paths.env_file) }
+    pub fn env_example(&self) -> PathBuf { self.path(&self.settings.paths.env_example) }
+    pub fn pubkey(&self) -> PathBuf { self.path(&self.settings.paths.pubkey) }
+    pub fn script(&self) -> PathBuf { self.path(&self.settings.paths.script) }
+    pub fn log_file(&self) -> PathBuf { self.path(&self.settings.paths.log_file) }
+    pub fn working_dir(&self) -> PathBuf { self.path(&self.settings.paths.working_dir) }
+    pub fn bin_dir(&self) -> PathBuf { self.path(&self.settings.paths.bin_dir) }
+    pub fn ruleset_cache(&self) -> PathBuf { self.path(&self.settings.ruleset.cache_dir) }
+    pub fn subscription_cache(&self) -> PathBuf { self.path(&self.settings.subscription.cache_dir) }
+
+    /// PID 文件 (测试时可用 SBC_PID_FILE 覆盖)
+    pub fn pid_file(&self) -> PathBuf {
+        env::var_os("SBC_PID_FILE").map(PathBuf::from)
+            .unwrap_or_else(|| self.path(&self.settings.paths.pid_file))
+    }
+
+    pub fn settings_file(&self) -> PathBuf {
+        self.root.join(SETTINGS_FILE)
+    }
+
+    pub fn stop_flag(&self) -> PathBuf {
+        self.root.join("STOP")
+    }
+
+    /// 模板历史版本
+    pub fn history_dir(&self) -> PathBuf {
+        self.root.join("etc/history")
+    }
+
+    /// 已安装的模板包 (etc/bundles/<id>)
+    pub fn bundles_dir(&self) -> PathBuf {
+        self.root.join("etc/bundles")
+    }
+
+    /// 指向当前模板包的符号链接 (与 bundles_dir 同级，链接目标为相对路径)
+    pub fn bundle_link(&self) -> PathBuf {
+        self.root.join("etc/bundle")
+    }
+}
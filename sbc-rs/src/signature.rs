@@ -0,0 +1,29 @@
+// 下载内容的签名校验 (minisign / ed25519 分离签名)
+use anyhow::{Context, Result, anyhow};
+use minisign_verify::{PublicKey, Signature};
+use std::fs;
+use std::path::Path;
+
+/// 分离签名文件的后缀，与 minisign 默认一致
+pub const SIGNATURE_SUFFIX: &str = ".minisig";
+
+/// 读取固定在工作空间中的公钥，支持 minisign.pub 文件格式或单行 base64
+pub fn load_public_key(path: &Path) -> Result<PublicKey> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("无法读取公钥文件: {:?}", path))?;
+    let content = content.trim();
+    let key = if content.lines().count() > 1 {
+        PublicKey::decode(content)
+    } else {
+        PublicKey::from_base64(content)
+    };
+    key.map_err(|e| anyhow!("公钥文件 {:?} 格式无效: {}", path, e))
+}
+
+/// 校验数据与分离签名是否匹配
+pub fn verify(public_key: &PublicKey, data: &[u8], signature: &str) -> Result<()> {
+    let signature = Signature::decode(signature)
+        .map_err(|e| anyhow!("签名格式无效: {}", e))?;
+    public_key.verify(data, &signature, false)
+        .map_err(|e| anyhow!("签名校验失败: {}", e))
+}
@@ -0,0 +1,348 @@
+// 订阅内容的识别、过滤与重命名，以及渲染时将 providers 改写到本地订阅网关
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use log::warn;
+use percent_encoding::{NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::checksum;
+use crate::settings::SubscriptionSettings;
+
+/// 网关订阅编号 -> 上游订阅，由渲染时写入缓存目录
+pub const SOURCES_FILE: &str = "sources.json";
+
+/// 网关代为下载的上游订阅
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct Source {
+    /// provider 的 tag，仅用于日志
+    pub tag: String,
+    pub url: String,
+    /// 请求上游时使用的 User-Agent (沿用 provider 的 user_agent，机场据此返回对应格式)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+}
+
+/// 订阅编号：上游地址的摘要，本地地址中不出现订阅令牌
+pub fn source_id(url: &str) -> String {
+    checksum::sha256_hex(url.as_bytes())[..12].to_string()
+}
+
+pub fn load_sources(cache_dir: &Path) -> BTreeMap<String, Source> {
+    fs::read_to_string(cache_dir.join(SOURCES_FILE))
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn save_sources(cache_dir: &Path, sources: &BTreeMap<String, Source>) -> Result<()> {
+    fs::create_dir_all(cache_dir).with_context(|| format!("无法创建订阅缓存目录: {:?}", cache_dir))?;
+    let path = cache_dir.join(SOURCES_FILE);
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(sources)?)?;
+    fs::rename(&tmp, &path).with_context(|| format!("无法写入订阅映射: {:?}", path))
+}
+
+/// 网关的本地地址 (通配地址经回环访问)
+pub fn gateway_base(listen: &str) -> String {
+    let listen = listen.strip_prefix("0.0.0.0:").map_or(listen.to_string(), |port| format!("127.0.0.1:{}", port));
+    format!("http://{}/sub/", listen)
+}
+
+/// 渲染过滤器：将远程 provider 的订阅地址改写为网关地址，并记录编号与上游的对应关系 (内容未变时不重写文件)
+pub fn gateway_filter(cache_dir: PathBuf, listen: String) -> impl Fn(Value, &HashMap<String, String>) -> Result<Value> + Send + Sync + 'static {
+    move |mut config, _env| {
+        let base = gateway_base(&listen);
+        let mut sources = BTreeMap::new();
+        let providers = config.get_mut("providers").and_then(Value::as_array_mut).into_iter().flatten();
+        for provider in providers.filter_map(Value::as_object_mut) {
+            let field = |name: &str| provider.get(name).and_then(Value::as_str).map(str::to_string);
+            let Some(url) = field("url").filter(|u| u.starts_with("http://") || u.starts_with("https://")) else {
+                continue;
+            };
+            if field("type").as_deref() != Some("remote") || url.starts_with(&base) {
+                continue;
+            }
+            let id = source_id(&url);
+            sources.insert(id.clone(), Source {
+                tag: field("tag").unwrap_or_default(),
+                url,
+                user_agent: field("user_agent"),
+            });
+            provider.insert("url".into(), format!("{}{}", base, id).into());
+        }
+        if !sources.is_empty() && load_sources(&cache_dir) != sources {
+            save_sources(&cache_dir, &sources)?;
+        }
+        Ok(config)
+    }
+}
+
+/// 节点过滤与重命名规则
+pub struct NodeFilter {
+    include: Option<Regex>,
+    exclude: Option<Regex>,
+    rename: Vec<(Regex, String)>,
+}
+
+impl NodeFilter {
+    pub fn new(settings: &SubscriptionSettings) -> Result<Self> {
+        let compile = |name: &str, pattern: &str| {
+            Regex::new(pattern).with_context(|| format!("subscription.{} 不是有效的正则表达式: {}", name, pattern))
+        };
+        Ok(Self {
+            include: Some(&settings.include).filter(|p| !p.is_empty()).map(|p| compile("include", p)).transpose()?,
+            exclude: Some(&settings.exclude).filter(|p| !p.is_empty()).map(|p| compile("exclude", p)).transpose()?,
+            rename: settings.rename.iter()
+                .map(|r| Ok((compile("rename", &r.pattern)?, r.replace.clone())))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// 过滤按原始名称判断；保留的节点依次执行重命名规则。返回 None 表示丢弃
+    fn apply(&self, name: &str) -> Option<String> {
+        if self.include.as_ref().is_some_and(|re| !re.is_match(name))
+            || self.exclude.as_ref().is_some_and(|re| re.is_match(name))
+        {
+            return None;
+        }
+        Some(self.rename.iter().fold(name.to_string(), |name, (re, replace)| {
+            re.replace_all(&name, replace.as_str()).trim().to_string()
+        }))
+    }
+}
+
+// 重命名后可能出现重名，依次追加序号
+pub(crate) fn unique(name: String, seen: &mut HashSet<String>) -> String {
+    let mut candidate = name.clone();
+    let mut n = 2;
+    while !seen.insert(candidate.clone()) {
+        candidate = format!("{} {}", name, n);
+        n += 1;
+    }
+    candidate
+}
+
+/// 订阅内容格式
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    /// Clash / mihomo YAML (proxies 列表)
+    Clash,
+    /// sing-box JSON (outbounds 列表)
+    SingBox,
+    /// 分享链接，每行一个 (base64 表示整体经过 base64 编码)
+    Links { base64: bool },
+}
+
+impl Format {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Clash => "clash",
+            Format::SingBox => "sing-box",
+            Format::Links { base64: true } => "links (base64)",
+            Format::Links { base64: false } => "links",
+        }
+    }
+}
+
+/// 过滤后的订阅
+pub struct Filtered {
+    pub body: Vec<u8>,
+    pub format: Format,
+    pub kept: usize,
+    pub dropped: usize,
+}
+
+/// 宽松的 base64 解码 (标准或 URL 安全字母表，填充可有可无，忽略换行)，结果须为文本
+pub fn decode_base64_text(data: &str) -> Option<String> {
+    let compact: String = data.chars().filter(|c| !c.is_whitespace()).collect();
+    let config = GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent);
+    [alphabet::STANDARD, alphabet::URL_SAFE].iter()
+        .find_map(|a| GeneralPurpose::new(a, config).decode(&compact).ok())
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+}
+
+/// 识别订阅格式，并返回 (分享链接经 base64 解码后的) 文本
+pub fn detect(body: &[u8]) -> Result<(Format, String)> {
+    let text = String::from_utf8_lossy(body).trim_start_matches('\u{feff}').trim().to_string();
+    if text.starts_with('{') {
+        return Ok((Format::SingBox, text));
+    }
+    let is_links = |s: &str| s.lines().map(str::trim).find(|l| !l.is_empty()).is_some_and(|l| l.contains("://"));
+    if is_links(&text) {
+        return Ok((Format::Links { base64: false }, text));
+    }
+    if let Some(decoded) = decode_base64_text(&text).filter(|d| is_links(d)) {
+        return Ok((Format::Links { base64: true }, decoded));
+    }
+    if text.lines().any(|l| l.starts_with("proxies:")) {
+        return Ok((Format::Clash, text));
+    }
+    bail!("无法识别的订阅格式 (既不是 Clash YAML、sing-box JSON，也不是分享链接)")
+}
+
+/// 按规则过滤并重命名订阅中的节点，输出与输入相同的格式。没有剩余节点时返回错误
+pub fn filter(body: &[u8], rules: &NodeFilter) -> Result<Filtered> {
+    let (format, text) = detect(body)?;
+    let mut seen = HashSet::new();
+    let (body, kept, dropped) = match format {
+        Format::Clash => filter_clash(&text, rules, &mut seen)?,
+        Format::SingBox => filter_singbox(&text, rules, &mut seen)?,
+        Format::Links { base64 } => {
+            let (links, kept, dropped) = filter_links(&text, rules, &mut seen);
+            let out = match base64 {
+                true => base64::engine::general_purpose::STANDARD.encode(&links),
+                false => links,
+            };
+            (out.into_bytes(), kept, dropped)
+        }
+    };
+    if kept == 0 {
+        bail!("订阅中没有可用节点 (共 {} 个，均被过滤)", dropped);
+    }
+    Ok(Filtered { body, format, kept, dropped })
+}
+
+// 只输出 proxies：proxy-groups 与 rules 引用的名称可能已被过滤或改名
+fn filter_clash(text: &str, rules: &NodeFilter, seen: &mut HashSet<String>) -> Result<(Vec<u8>, usize, usize)> {
+    let root: serde_yaml::Value = serde_yaml::from_str(text).context("Clash 订阅不是有效的 YAML")?;
+    let proxies = root.get("proxies").and_then(serde_yaml::Value::as_sequence).context("Clash 订阅中没有 proxies 列表")?;
+    let mut kept = Vec::new();
+    for proxy in proxies {
+        // 上游内容不可信：不是映射的条目直接丢弃
+        let Some(mut node) = proxy.as_mapping().cloned() else {
+            warn!("⚠️ 跳过格式错误的 Clash 节点: {:?}", proxy);
+            continue;
+        };
+        let name = node.get("name").and_then(serde_yaml::Value::as_str).unwrap_or_default();
+        if let Some(new_name) = rules.apply(name) {
+            node.insert("name".into(), unique(new_name, seen).into());
+            kept.push(serde_yaml::Value::Mapping(node));
+        }
+    }
+    let dropped = proxies.len() - kept.len();
+    let count = kept.len();
+    let mut out = serde_yaml::Mapping::new();
+    out.insert("proxies".into(), kept.into());
+    Ok((serde_yaml::to_string(&out)?.into_bytes(), count, dropped))
+}
+
+// 节点为带 server 的出站；选择器等分组引用的 tag 可能已变化，一并丢弃
+fn filter_singbox(text: &str, rules: &NodeFilter, seen: &mut HashSet<String>) -> Result<(Vec<u8>, usize, usize)> {
+    let root: Value = serde_json::from_str(text).context("sing-box 订阅不是有效的 JSON")?;
+    let outbounds = root.get("outbounds").and_then(Value::as_array).context("sing-box 订阅中没有 outbounds 列表")?;
+    let nodes: Vec<&Map<String, Value>> = outbounds.iter()
+        .filter_map(Value::as_object)
+        .filter(|o| o.contains_key("server"))
+        .collect();
+    let mut kept = Vec::new();
+    for node in &nodes {
+        let tag = node.get("tag").and_then(Value::as_str).unwrap_or_default();
+        if let Some(new_tag) = rules.apply(tag) {
+            let mut node = (*node).clone();
+            node.insert("tag".into(), unique(new_tag, seen).into());
+            kept.push(node);
+        }
+    }
+    let (count, dropped) = (kept.len(), nodes.len() - kept.len());
+    Ok((serde_json::to_vec_pretty(&serde_json::json!({ "outbounds": kept }))?, count, dropped))
+}
+
+fn filter_links(text: &str, rules: &NodeFilter, seen: &mut HashSet<String>) -> (String, usize, usize) {
+    let (mut out, mut dropped) = (Vec::new(), 0);
+    for link in text.lines().map(str::trim).filter(|l| l.contains("://")) {
+        let name = link_name(link);
+        let Some(new_name) = rules.apply(&name) else {
+            dropped += 1;
+            continue;
+        };
+        match rename_link(link, &unique(new_name, seen)) {
+            Some(renamed) => out.push(renamed),
+            None => {
+                warn!("⚠️ 跳过格式错误的分享链接: {}", name);
+                dropped += 1;
+            }
+        }
+    }
+    let kept = out.len();
+    (out.join("\n"), kept, dropped)
+}
+
+/// 分享链接中的节点名：vmess 取 JSON 中的 ps，其余取 `#` 之后的片段
+pub fn link_name(link: &str) -> String {
+    if let Some(payload) = link.strip_prefix("vmess://") {
+        return decode_base64_text(payload)
+            .and_then(|json| serde_json::from_str::<Value>(&json).ok())
+            .and_then(|v| v.get("ps").and_then(Value::as_str).map(str::to_string))
+            .unwrap_or_default();
+    }
+    link.split_once('#')
+        .map(|(_, fragment)| percent_decode_str(fragment).decode_utf8_lossy().to_string())
+        .unwrap_or_default()
+}
+
+// vmess 的载荷须为 JSON 对象，否则视为格式错误 (返回 None)
+fn rename_link(link: &str, name: &str) -> Option<String> {
+    if let Some(payload) = link.strip_prefix("vmess://") {
+        let mut json = decode_base64_text(payload)
+            .and_then(|j| serde_json::from_str::<Map<String, Value>>(&j).ok())?;
+        json.insert("ps".into(), name.into());
+        let json = Value::Object(json).to_string();
+        return Some(format!("vmess://{}", base64::engine::general_purpose::STANDARD.encode(json)));
+    }
+    let base = link.split_once('#').map_or(link, |(base, _)| base);
+    Some(format!("{}#{}", base, utf8_percent_encode(name, NON_ALPHANUMERIC)))
+}
+
+/// 机场通过 Subscription-Userinfo 响应头下发的流量与到期信息 (字节 / Unix 时间戳)
+#[derive(Serialize, Clone, Copy, Default)]
+pub struct UserInfo {
+    pub upload: u64,
+    pub download: u64,
+    /// 0 表示不限流量
+    pub total: u64,
+    /// 缺失或为 0 表示长期有效
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expire: Option<i64>,
+}
+
+impl UserInfo {
+    /// 剩余流量 (不限流量时为 None，超额时为 0)
+    pub fn remaining(&self) -> Option<u64> {
+        (self.total > 0).then(|| self.total.saturating_sub(self.used()))
+    }
+
+    /// 已用流量 (上下行之和，数值异常时封顶而不溢出)
+    pub fn used(&self) -> u64 {
+        self.upload.saturating_add(self.download)
+    }
+}
+
+/// 解析 `upload=1; download=2; total=3; expire=4`。数值偶有写成浮点 (1.5E10) 的，一并接受；
+/// 没有任何可识别字段时返回 None
+pub fn parse_userinfo(header: &str) -> Option<UserInfo> {
+    let mut info = UserInfo::default();
+    let mut found = false;
+    for (key, value) in header.split(';').filter_map(|pair| pair.split_once('=')) {
+        let value = value.trim();
+        // 超出 u64 范围的浮点数视为无法识别，而不是截断为最大值
+        let float = || value.parse::<f64>().ok().filter(|v| v.is_finite() && *v >= 0.0 && *v < u64::MAX as f64);
+        let Some(number) = value.parse::<u64>().ok().or_else(|| float().map(|v| v as u64)) else {
+            continue;
+        };
+        match key.trim().to_ascii_lowercase().as_str() {
+            "upload" => info.upload = number,
+            "download" => info.download = number,
+            "total" => info.total = number,
+            "expire" => info.expire = i64::try_from(number).ok().filter(|ts| *ts > 0),
+            _ => continue,
+        }
+        found = true;
+    }
+    found.then_some(info)
+}
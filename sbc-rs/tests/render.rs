@@ -0,0 +1,20 @@
+use sbc_rs::renderer::Renderer;
+use serde_json::Value;
+
+#[test]
+fn renders_fixture_template() {
+    let template = include_str!("test_template.json");
+    let expected: Value = serde_json::from_str(include_str!("test_output.json")).unwrap();
+
+    let rendered = Renderer::new()
+        .var("LOG_LEVEL", "info")
+        .var("DNS_SERVERS", r#"["1.1.1.1", "9.9.9.9"]"#)
+        .var("MIXED_TYPE", "http")
+        .var("MIXED_USERS", r#"[{"user": "admin", "pass": "123"}]"#)
+        .var("STRUCT_VAL", r#"{"foo": "bar"}"#)
+        .render_str(template)
+        .unwrap();
+
+    assert_eq!(rendered.value, expected);
+    assert_eq!(rendered.report.missing, vec!["MISSING_VAR"]);
+}
@@ -19,6 +19,42 @@ if [ ! -f "$TEMPLATE_PATH" ]; then
     exit 1
 fi
 
+# Isolated workspace: commands that fall back to etc/sbc.toml must never touch a real install
+export WORKSPACE=$(mktemp -d)
+mkdir -p "$WORKSPACE/etc"
+cat << 'EOF' > "$WORKSPACE/etc/sbc.toml"
+[daemon]
+max_retries = 1
+retry_delay = 1
+EOF
+if [ "$("$SBC_BIN" settings daemon.max_retries)" = "1" ] && [ "$("$SBC_BIN" settings paths.config)" = "$WORKSPACE/etc/config.json" ]; then
+    echo "✅ Settings resolved from \$WORKSPACE/etc/sbc.toml."
+else
+    echo "❌ Settings resolution failed."
+    exit 1
+fi
+
+# Lenient settings: a typo or a bad table only warns (paths still resolve for service.sh), --validate is strict
+cp "$WORKSPACE/etc/sbc.toml" /tmp/sbc.toml.bak
+printf '[paths]\ntemplate = "t.json"\ntempalte = "typo.json"\n[self_update]\nmodule_prop = 1\n' >> "$WORKSPACE/etc/sbc.toml"
+LENIENT_TEMPLATE=$("$SBC_BIN" settings paths.template 2> /tmp/settings.lenient.log)
+set +e
+"$SBC_BIN" settings --validate > /tmp/settings.validate.log 2>&1
+VALIDATE_RC=$?
+set -e
+mv /tmp/sbc.toml.bak "$WORKSPACE/etc/sbc.toml"
+if [ "$LENIENT_TEMPLATE" = "$WORKSPACE/t.json" ] && [ "$("$SBC_BIN" settings daemon.max_retries)" = "1" ] \
+    && grep -q "未知的设置项 paths.tempalte" /tmp/settings.lenient.log && grep -q "\[self_update\] 无效" /tmp/settings.lenient.log \
+    && [ "$VALIDATE_RC" -ne 0 ] && grep -q "paths.tempalte" /tmp/settings.validate.log \
+    && "$SBC_BIN" settings --validate 2> /dev/null; then
+    echo "✅ Lenient settings validation passed."
+else
+    echo "❌ Lenient settings validation failed."
+    cat /tmp/settings.lenient.log /tmp/settings.validate.log
+    exit 1
+fi
+rm -f /tmp/settings.lenient.log /tmp/settings.validate.log
+
 echo "Generating Mock Environment..."
 # Export Mock Variables
 export CLASH_API_SECRET="test_secret"
@@ -44,14 +80,163 @@ export INBOUNDS_BOTTOM='{"type":"direct","tag":"dns-in-2","network":"udp"}'
 export EXPERIMENTAL_CLASH_API="" # Should be ignored/empty
 export EXPERIMENTAL_CACHE_FILE="" # Should be ignored/empty
 
+# 4. Test 'vars' command (placeholder inventory)
+echo "Running sbc-rs vars..."
+"$SBC_BIN" vars --template "$TEMPLATE_PATH"
+"$SBC_BIN" vars --template "$TEMPLATE_PATH" --env-example /tmp/env.example.gen
+if grep -q "^SUB_URL_1=" /tmp/env.example.gen; then
+    echo "✅ .env.example generated from template."
+else
+    echo "❌ vars --env-example validation failed."
+    exit 1
+fi
+
+# 4.1 Test 'env migrate': missing keys are appended, existing content kept byte-for-byte
+printf 'CLASH_API_SECRET="mine"\nOLD_VAR=1' > /tmp/env.migrate
+cp /tmp/env.migrate /tmp/env.migrate.orig
+"$SBC_BIN" env migrate --example /tmp/env.example.gen --env-file /tmp/env.migrate
+if head -c "$(wc -c < /tmp/env.migrate.orig)" /tmp/env.migrate | cmp -s - /tmp/env.migrate.orig \
+    && grep -q "^SUB_URL_1=" /tmp/env.migrate && [ "$(grep -c '^CLASH_API_SECRET=' /tmp/env.migrate)" -eq 1 ]; then
+    echo "✅ .env migrated from .env.example."
+else
+    echo "❌ env migrate validation failed."
+    cat /tmp/env.migrate
+    exit 1
+fi
+rm -f /tmp/env.example.gen /tmp/env.migrate /tmp/env.migrate.orig
+
 # 5. Test 'render' command
 echo "Running sbc-rs render..."
 "$SBC_BIN" render --template "$TEMPLATE_PATH" --output "$OUTPUT_PATH"
 
+# 5.1 Test 'render --check' with a fake sing-box that rejects the config
+echo "Running sbc-rs render --check (Fake sing-box)..."
+cat << 'EOF' > /tmp/sing-box-check-fail
+#!/bin/bash
+echo "FATAL[0000] decode config: mock check failure" >&2
+exit 1
+EOF
+chmod +x /tmp/sing-box-check-fail
+cp "$OUTPUT_PATH" /tmp/config.before.json
+
+if SBC_SINGBOX_BIN=/tmp/sing-box-check-fail "$SBC_BIN" render --check --template "$TEMPLATE_PATH" --output "$OUTPUT_PATH" 2> /tmp/check.log; then
+    echo "❌ render --check should have failed."
+    exit 1
+fi
+if grep -q "mock check failure" /tmp/check.log && cmp -s "$OUTPUT_PATH" /tmp/config.before.json; then
+    echo "✅ Check failure reported and existing config kept."
+else
+    echo "❌ render --check validation failed."
+    cat /tmp/check.log
+    exit 1
+fi
+rm -f /tmp/sing-box-check-fail /tmp/config.before.json /tmp/check.log
+
+# 5.2 Test the "$vars" schema: defaults fill unset variables, invalid values are all reported before rendering
+cat << 'EOF' > /tmp/template.vars.json
+{
+  "$vars": {
+    "VARS_URL": {"type": "url", "required": true, "description": "CI subscription URL"},
+    "VARS_PORT": {"type": "port", "default": 7890},
+    "VARS_LEVEL": {"type": "enum", "values": ["info", "warn"], "default": "warn"},
+    "VARS_RULES": {"type": "json-object"},
+    "VARS_SECRET": {"type": "string", "secret": true, "default": "ci-default-secret"},
+    "VARS_QUOTED": {"type": "string", "default": "'a' \"b\""},
+    "VARS_MULTI": {"type": "string", "default": "line1\nline2"}
+  },
+  "log": {"level": "${VARS_LEVEL}"},
+  "experimental": {"cache_file": {"path": "${VARS_QUOTED}", "cache_id": "${VARS_MULTI}"}},
+  "inbounds": [{"type": "mixed", "tag": "${VARS_SECRET}", "listen_port": "{{VARS_PORT}}"}],
+  "outbounds": [{"type": "direct", "tag": "${VARS_URL}"}]
+}
+EOF
+VARS_URL="http://vars.example.com/sub" "$SBC_BIN" render --template /tmp/template.vars.json --output /tmp/config.vars.json
+"$SBC_BIN" vars --template /tmp/template.vars.json --env-example /tmp/env.vars.example
+set +e
+VARS_URL="not-a-url" VARS_PORT=70000 VARS_LEVEL=debug VARS_RULES='[1]' \
+    "$SBC_BIN" render --template /tmp/template.vars.json --output /tmp/config.vars.invalid.json 2> /tmp/vars.invalid.log
+INVALID_RC=$?
+"$SBC_BIN" render --template /tmp/template.vars.json --output /tmp/config.vars.invalid.json 2> /tmp/vars.missing.log
+MISSING_RC=$?
+echo '{"log": {"level": "${VARS_UNSET_LEVEL}"}}' | "$SBC_BIN" render --strict --template - --output /tmp/config.vars.invalid.json 2> /tmp/vars.strict.log
+STRICT_RC=$?
+set -e
+if ! grep -q '\$vars' /tmp/config.vars.json && grep -q '"level": "warn"' /tmp/config.vars.json \
+    && grep -q '"listen_port": 7890' /tmp/config.vars.json && grep -q '"tag": "http://vars.example.com/sub"' /tmp/config.vars.json \
+    && grep -q "# CI subscription URL" /tmp/env.vars.example && grep -q "^VARS_PORT='7890'" /tmp/env.vars.example \
+    && grep -q "^VARS_SECRET=''" /tmp/env.vars.example \
+    && grep -qxF "VARS_QUOTED=''a' \"b\"'" /tmp/env.vars.example && grep -qxF 'VARS_MULTI="line1\nline2"' /tmp/env.vars.example \
+    && [ "$INVALID_RC" -ne 0 ] && [ ! -f /tmp/config.vars.invalid.json ] \
+    && grep -q "VARS_URL 不是有效的 URL" /tmp/vars.invalid.log && grep -q "VARS_PORT 不是有效的端口" /tmp/vars.invalid.log \
+    && grep -q "VARS_LEVEL 必须是以下值之一: info, warn" /tmp/vars.invalid.log && grep -q "VARS_RULES 必须是 JSON 对象" /tmp/vars.invalid.log \
+    && [ "$MISSING_RC" -ne 0 ] && grep -q "VARS_URL 是必填项" /tmp/vars.missing.log \
+    && [ "$STRICT_RC" -ne 0 ] && grep -q "严格模式下存在未设置的变量: VARS_UNSET_LEVEL" /tmp/vars.strict.log; then
+    echo "✅ Variable schema validation passed."
+else
+    echo "❌ Variable schema validation failed ($INVALID_RC / $MISSING_RC / $STRICT_RC)."
+    cat /tmp/config.vars.json /tmp/env.vars.example /tmp/vars.invalid.log /tmp/vars.missing.log /tmp/vars.strict.log
+    exit 1
+fi
+rm -f /tmp/template.vars.json /tmp/config.vars*.json /tmp/env.vars.example /tmp/vars.*.log
+
+# 5.3 Test render through stdin/stdout and the output formats; --minify skips rewriting unchanged output
+echo '{"outbounds": [], "log": {"level": "${CI_FMT_LEVEL}"}}' > /tmp/template.fmt.json
+export CI_FMT_LEVEL="warn"
+"$SBC_BIN" render --template - --output - < /tmp/template.fmt.json > /tmp/fmt.pretty.json 2> /dev/null
+"$SBC_BIN" render --template /tmp/template.fmt.json --output - --compact > /tmp/fmt.compact.json 2> /dev/null
+"$SBC_BIN" render --template /tmp/template.fmt.json --output - --indent 4 --sort-keys > /tmp/fmt.sorted.json 2> /dev/null
+"$SBC_BIN" render --template /tmp/template.fmt.json --output /tmp/fmt.min.json --minify 2> /dev/null
+"$SBC_BIN" render --template /tmp/template.fmt.json --output /tmp/fmt.min.json --minify 2> /tmp/fmt.min.log
+unset CI_FMT_LEVEL
+if [ "$(head -n 2 /tmp/fmt.pretty.json)" = "$(printf '{\n  "outbounds": [],')" ] \
+    && [ "$(cat /tmp/fmt.compact.json)" = '{"outbounds":[],"log":{"level":"warn"}}' ] \
+    && [ "$(head -n 3 /tmp/fmt.sorted.json)" = "$(printf '{\n    "log": {\n        "level": "warn"')" ] \
+    && [ "$(cat /tmp/fmt.min.json)" = "$(cat /tmp/fmt.compact.json)" ] && grep -q "输出内容未变化，跳过写入" /tmp/fmt.min.log; then
+    echo "✅ Render stdio and formatting validation passed."
+else
+    echo "❌ Render stdio and formatting validation failed."
+    cat /tmp/fmt.pretty.json /tmp/fmt.compact.json /tmp/fmt.sorted.json /tmp/fmt.min.json /tmp/fmt.min.log
+    exit 1
+fi
+rm -f /tmp/template.fmt.json /tmp/fmt.*
+
+# 5.4 Test the Rhai render script: it transforms the tree with env, errors point at the script line,
+# endless loops hit the time budget or the operation limit (whichever trips first) and module imports are unavailable
+echo '{"outbounds": [{"type": "direct", "tag": "a"}, {"type": "block", "tag": "b"}]}' > /tmp/template.script.json
+cat << 'EOF' > /tmp/script.ok.rhai
+for i in 0..config.outbounds.len() {
+    config.outbounds[i].tag = env.CI_SCRIPT_PREFIX + config.outbounds[i].tag;
+}
+config.outbounds = config.outbounds.filter(|ob| ob.type != "block");
+print("kept " + config.outbounds.len());
+EOF
+printf 'let x = 1;\nlet y = x.missing_fn();\n' > /tmp/script.error.rhai
+printf 'loop { }\n' > /tmp/script.timeout.rhai
+printf 'import "fs" as fs;\n' > /tmp/script.import.rhai
+CI_SCRIPT_PREFIX="ci-" "$SBC_BIN" render --template /tmp/template.script.json --output /tmp/config.script.json \
+    --script /tmp/script.ok.rhai 2> /tmp/script.ok.log
+set +e
+for name in error timeout import; do
+    "$SBC_BIN" render --template /tmp/template.script.json --output /tmp/config.script.$name.json \
+        --script /tmp/script.$name.rhai 2> /tmp/script.$name.log && { echo "❌ Script $name should have failed."; exit 1; }
+done
+set -e
+if grep -q '"tag": "ci-a"' /tmp/config.script.json && ! grep -q '"block"' /tmp/config.script.json \
+    && grep -q "\[script.ok.rhai\] kept 1" /tmp/script.ok.log \
+    && grep -q "执行失败 (第 2 行" /tmp/script.error.log && grep -Eq "执行超时|Too many operations" /tmp/script.timeout.log \
+    && grep -q "script.import.rhai\" 语法错误" /tmp/script.import.log && ! ls /tmp/config.script.*.json > /dev/null 2>&1; then
+    echo "✅ Render script validation passed."
+else
+    echo "❌ Render script validation failed."
+    cat /tmp/config.script.json /tmp/script.*.log
+    exit 1
+fi
+rm -f /tmp/template.script.json /tmp/config.script*.json /tmp/script.*
+
 # 6. Test 'update' command (Mock Server)
 echo "Running sbc-rs update (Mock Server)..."
 mkdir -p /tmp/www
-echo '{"inbounds": []}' > /tmp/www/template.json # Minimal valid config signature
+echo '{"inbounds": [], "outbounds": []}' > /tmp/www/template.json # Minimal config that renders
 echo "MOCK_ENV=1" > /tmp/www/env.example
 
 # Start background mock server
@@ -65,9 +250,650 @@ sleep 1
 "$SBC_BIN" update \
     --template-url "http://localhost:8080/template.json" \
     --template-path "$TEMPLATE_PATH.new" \
+    --allow-unsigned \
     --env-url "http://localhost:8080/env.example" \
     --env-path "$MOCK_ENV_PATH.new" || { kill $SERVER_PID; exit 1; }
 
+# Mirror fallback: a dead mirror and a 404 mirror must be skipped in favour of the working one
+"$SBC_BIN" update \
+    --template-url "http://127.0.0.1:9/template.json" \
+    --template-url "http://localhost:8080/missing.json" \
+    --template-url "http://localhost:8080/template.json" \
+    --template-path /tmp/template.mirror.json \
+    --allow-unsigned --keep-history 0 \
+    --retries 2 --retry-backoff-ms 100 2> /tmp/mirror.log || { cat /tmp/mirror.log; kill $SERVER_PID; exit 1; }
+if grep -q "Served by mirror: http://localhost:8080/template.json" /tmp/mirror.log; then
+    echo "✅ Mirror fallback validation passed."
+else
+    echo "❌ Mirror fallback validation failed."
+    cat /tmp/mirror.log
+    kill $SERVER_PID
+    exit 1
+fi
+rm -f /tmp/template.mirror.json /tmp/template.mirror.meta.json /tmp/mirror.log
+
+# Proxy auto mode: an unreachable mixed inbound must fall back to a direct connection
+echo '{"inbounds": [{"type": "mixed", "listen": "127.0.0.1", "listen_port": 9}]}' > /tmp/config.proxy.json
+"$SBC_BIN" update \
+    --template-url "http://localhost:8080/template.json" \
+    --template-path /tmp/template.proxy.json \
+    --allow-unsigned --keep-history 0 \
+    --proxy auto --proxy-config /tmp/config.proxy.json || { kill $SERVER_PID; exit 1; }
+echo "✅ Proxy auto fallback validation passed."
+rm -f /tmp/config.proxy.json /tmp/template.proxy.json /tmp/template.proxy.meta.json
+
+# Proxy auto mode through an authenticated mixed inbound whose password needs percent-encoding
+cat << 'EOF' > /tmp/proxy-server.py
+import base64, http.server, urllib.request
+EXPECTED = "Basic " + base64.b64encode(b"ci user:p@ss:w/rd#1").decode()
+class Handler(http.server.BaseHTTPRequestHandler):
+    def do_GET(self):
+        if self.headers.get("Proxy-Authorization") != EXPECTED:
+            self.send_response(407)
+            self.send_header("Proxy-Authenticate", 'Basic realm="ci"')
+            self.end_headers()
+            return
+        with urllib.request.urlopen(self.path) as upstream:
+            body = upstream.read()
+        self.send_response(200)
+        self.send_header("Content-Length", str(len(body)))
+        self.end_headers()
+        self.wfile.write(body)
+        with open("/tmp/proxy-server.log", "a") as log:
+            log.write(self.path + "\n")
+http.server.HTTPServer(("127.0.0.1", 8083), Handler).serve_forever()
+EOF
+python3 /tmp/proxy-server.py &
+PROXY_PID=$!
+sleep 1
+cat << 'EOF' > /tmp/config.proxy.json
+{"inbounds": [{"type": "mixed", "listen": "127.0.0.1", "listen_port": 8083,
+  "users": [{"username": "ci user", "password": "p@ss:w/rd#1"}]}]}
+EOF
+"$SBC_BIN" update \
+    --template-url "http://localhost:8080/template.json" \
+    --template-path /tmp/template.proxy.json \
+    --allow-unsigned --keep-history 0 \
+    --proxy auto --proxy-config /tmp/config.proxy.json 2> /tmp/proxy.log || { cat /tmp/proxy.log; kill $SERVER_PID $PROXY_PID; exit 1; }
+kill $PROXY_PID
+if grep -q "http://localhost:8080/template.json" /tmp/proxy-server.log && [ -s /tmp/template.proxy.json ] \
+    && grep -q "Using sing-box mixed inbound as proxy" /tmp/proxy.log && ! grep -q "p@ss" /tmp/proxy.log; then
+    echo "✅ Authenticated proxy validation passed."
+else
+    echo "❌ Authenticated proxy validation failed."
+    cat /tmp/proxy.log
+    kill $SERVER_PID
+    exit 1
+fi
+rm -f /tmp/proxy-server.py /tmp/proxy-server.log /tmp/proxy.log
+rm -f /tmp/config.proxy.json /tmp/template.proxy.json /tmp/template.proxy.meta.json
+
+# A second update must be answered with 304 and report "no changes" (exit 3)
+set +e
+"$SBC_BIN" update \
+    --template-url "http://localhost:8080/template.json" \
+    --template-path "$TEMPLATE_PATH.new" \
+    --allow-unsigned
+UPDATE_RC=$?
+set -e
+if [ "$UPDATE_RC" -ne 3 ]; then
+    echo "❌ Conditional update should report no changes (exit 3), got $UPDATE_RC."
+    kill $SERVER_PID
+    exit 1
+fi
+echo "✅ Conditional GET validation passed."
+
+# A template that fails to render must be rejected and leave the installed one untouched
+echo '{"inbounds": {}}' > /tmp/www/broken.json # Parses, but fails the structure lint
+cp "$TEMPLATE_PATH.new" /tmp/template.before.json
+if "$SBC_BIN" update \
+    --template-url "http://localhost:8080/broken.json" \
+    --template-path "$TEMPLATE_PATH.new" \
+    --allow-unsigned 2> /tmp/update.log; then
+    echo "❌ Update should have rejected a broken template."
+    kill $SERVER_PID
+    exit 1
+fi
+if cmp -s "$TEMPLATE_PATH.new" /tmp/template.before.json; then
+    echo "✅ Broken template rejected, existing template kept."
+else
+    echo "❌ Broken template replaced the existing one."
+    cat /tmp/update.log
+    kill $SERVER_PID
+    exit 1
+fi
+rm -f /tmp/template.before.json /tmp/update.log
+
+# Publish a second version, then roll back to the first one offline
+cp "$TEMPLATE_PATH.new" /tmp/template.v1.json
+echo '{"inbounds": [], "outbounds": [], "log": {}}' > /tmp/www/template.json
+"$SBC_BIN" update \
+    --template-url "http://localhost:8080/template.json" \
+    --template-path "$TEMPLATE_PATH.new" \
+    --allow-unsigned || { kill $SERVER_PID; exit 1; }
+# A hand-edited entry with a short hash is listed instead of crashing the listing
+mkdir -p "$WORKSPACE/etc/history/00000000-000000"
+echo '{"created": "manual", "template": "abc"}' > "$WORKSPACE/etc/history/00000000-000000/entry.json"
+"$SBC_BIN" history --template "$TEMPLATE_PATH.new" > /tmp/history.txt || { cat /tmp/history.txt; kill $SERVER_PID; exit 1; }
+grep -q "^00000000-000000 .* abc " /tmp/history.txt || { echo "❌ History listing validation failed."; cat /tmp/history.txt; kill $SERVER_PID; exit 1; }
+rm -f /tmp/history.txt
+# The re-render applies the workspace render script like run and update do
+printf 'config.log = #{ level: "ci-rollback" };\n' > "$WORKSPACE/etc/render.rhai"
+"$SBC_BIN" rollback --template "$TEMPLATE_PATH.new" --config /tmp/config.rollback.json || { kill $SERVER_PID; exit 1; }
+rm -f "$WORKSPACE/etc/render.rhai"
+if cmp -s "$TEMPLATE_PATH.new" /tmp/template.v1.json && grep -q '"level": "ci-rollback"' /tmp/config.rollback.json; then
+    echo "✅ Rollback restored the previous template and re-rendered."
+else
+    echo "❌ Rollback validation failed."
+    kill $SERVER_PID
+    exit 1
+fi
+rm -rf /tmp/template.v1.json /tmp/config.rollback.json "$WORKSPACE/etc/history"
+
+# Local sources: a plain path, then a tar.gz bundle carrying the template and .env.example
+mkdir -p /tmp/bundle/templates
+echo '{"inbounds": [], "outbounds": [{"type": "direct", "tag": "local"}]}' > /tmp/bundle/templates/config.template.json
+echo 'BUNDLED=1' > /tmp/bundle/templates/.env.example
+"$SBC_BIN" update \
+    --template-url /tmp/bundle/templates/config.template.json \
+    --template-path /tmp/template.local.json \
+    --allow-unsigned --keep-history 0 || { kill $SERVER_PID; exit 1; }
+echo '{"inbounds": [], "outbounds": [{"type": "direct", "tag": "bundle"}]}' > /tmp/bundle/templates/config.template.json
+tar czf /tmp/bundle.tar.gz -C /tmp/bundle templates
+"$SBC_BIN" update \
+    --template-url "file:///tmp/bundle.tar.gz" \
+    --template-path /tmp/template.local.json \
+    --env-path /tmp/env.local.example \
+    --allow-unsigned --keep-history 0 || { kill $SERVER_PID; exit 1; }
+if grep -q '"bundle"' /tmp/template.local.json && grep -q "BUNDLED=1" /tmp/env.local.example; then
+    echo "✅ Local path and bundle validation passed."
+else
+    echo "❌ Local path and bundle validation failed."
+    kill $SERVER_PID
+    exit 1
+fi
+rm -rf /tmp/bundle /tmp/bundle.tar.gz /tmp/template.local.json /tmp/template.local.meta.json /tmp/env.local.example
+
+# Managed bundle: bundle.json lists every file by hash and the minimum sing-box version
+mkdir -p /tmp/managed/templates/rules /tmp/managed/ws
+printf '#!/bin/sh\n[ "$1" = version ] && echo "sing-box version 1.12.3"\nexit 0\n' > /tmp/managed/sing-box
+chmod +x /tmp/managed/sing-box
+make_bundle() { # <min_sing_box> <tag>
+    echo "{\"inbounds\": [], \"outbounds\": [{\"type\": \"direct\", \"tag\": \"$2\"}]}" > /tmp/managed/templates/config.template.json
+    echo "BUNDLE_TAG=$2" > /tmp/managed/templates/.env.example
+    echo '{"version": 1, "rules": []}' > /tmp/managed/templates/rules/ads.json
+    cd /tmp/managed/templates
+    {
+        echo "{\"version\": \"$2\", \"min_sing_box\": \"$1\", \"files\": {"
+        sha256sum config.template.json .env.example rules/ads.json | awk '{printf "%s\"%s\": \"%s\"", (NR > 1 ? ", " : ""), $2, $1}'
+        echo "}}"
+    } > bundle.json
+    cd - > /dev/null
+    tar czf /tmp/managed/bundle.tar.gz -C /tmp/managed templates
+}
+make_bundle 1.12.0 first
+WORKSPACE=/tmp/managed/ws SBC_SINGBOX_BIN=/tmp/managed/sing-box "$SBC_BIN" update \
+    --template-url /tmp/managed/bundle.tar.gz \
+    --template-path /tmp/managed/ws/config.template.json \
+    --env-path /tmp/managed/ws/.env.example \
+    --allow-unsigned || { kill $SERVER_PID; exit 1; }
+make_bundle 9.0.0 second
+if WORKSPACE=/tmp/managed/ws SBC_SINGBOX_BIN=/tmp/managed/sing-box "$SBC_BIN" update \
+    --template-url /tmp/managed/bundle.tar.gz \
+    --template-path /tmp/managed/ws/config.template.json \
+    --env-path /tmp/managed/ws/.env.example \
+    --allow-unsigned 2> /tmp/managed/update.log; then
+    echo "❌ Update should have refused a bundle requiring a newer sing-box."
+    kill $SERVER_PID
+    exit 1
+fi
+if [ -L /tmp/managed/ws/config.template.json ] && grep -q '"first"' /tmp/managed/ws/config.template.json \
+    && grep -q "BUNDLE_TAG=first" /tmp/managed/ws/.env.example && [ -f /tmp/managed/ws/etc/bundle/rules/ads.json ] \
+    && grep -q "requires sing-box >= 9.0.0" /tmp/managed/update.log; then
+    echo "✅ Managed bundle validation passed."
+else
+    echo "❌ Managed bundle validation failed."
+    cat /tmp/managed/update.log
+    kill $SERVER_PID
+    exit 1
+fi
+# Bundle rollback: history remembers the active bundle, and --keep-history 0 must not prune the bundles it references
+for tag in second third; do
+    make_bundle 1.12.0 $tag
+    WORKSPACE=/tmp/managed/ws SBC_SINGBOX_BIN=/tmp/managed/sing-box "$SBC_BIN" update \
+        --template-url /tmp/managed/bundle.tar.gz \
+        --template-path /tmp/managed/ws/config.template.json \
+        --env-path /tmp/managed/ws/.env.example \
+        --keep-history $([ $tag = third ] && echo 0 || echo 5) \
+        --allow-unsigned || { kill $SERVER_PID; exit 1; }
+done
+WORKSPACE=/tmp/managed/ws "$SBC_BIN" rollback --template /tmp/managed/ws/config.template.json \
+    --env-example /tmp/managed/ws/.env.example \
+    --config /tmp/managed/config.json 2> /tmp/managed/rollback.log || { cat /tmp/managed/rollback.log; kill $SERVER_PID; exit 1; }
+if readlink /tmp/managed/ws/etc/bundle | grep -q "^bundles/second-" && [ -L /tmp/managed/ws/config.template.json ] \
+    && grep -q '"second"' /tmp/managed/ws/config.template.json && grep -q "BUNDLE_TAG=second" /tmp/managed/ws/.env.example \
+    && [ "$(ls /tmp/managed/ws/etc/bundles | wc -l)" -eq 3 ] && grep -q '"second"' /tmp/managed/config.json; then
+    echo "✅ Bundle rollback validation passed."
+else
+    echo "❌ Bundle rollback validation failed."
+    cat /tmp/managed/rollback.log
+    ls -l /tmp/managed/ws /tmp/managed/ws/etc/bundles
+    kill $SERVER_PID
+    exit 1
+fi
+rm -rf /tmp/managed
+
+# Release channels: switching installs the channel's template and only then persists the choice
+mkdir -p /tmp/www/beta
+echo '{"inbounds": [], "outbounds": [{"type": "direct", "tag": "beta"}]}' > /tmp/www/beta/config.template.json
+echo '{"channels": [{"name": "mobile", "description": "stable"}, {"name": "beta", "description": "beta"}]}' > /tmp/www/channels.json
+cat << 'EOF' >> "$WORKSPACE/etc/sbc.toml"
+[update]
+channel_index = "http://localhost:8080/channels.json"
+template_url = ["http://localhost:8080/{channel}/config.template.json"]
+env_example_url = []
+EOF
+"$SBC_BIN" channel list || { kill $SERVER_PID; exit 1; }
+"$SBC_BIN" update --channel beta \
+    --template-path /tmp/template.channel.json \
+    --allow-unsigned --keep-history 0 || { kill $SERVER_PID; exit 1; }
+if [ "$("$SBC_BIN" settings update.channel)" = "beta" ] && grep -q '"beta"' /tmp/template.channel.json; then
+    echo "✅ Channel switch validation passed."
+else
+    echo "❌ Channel switch validation failed."
+    kill $SERVER_PID
+    exit 1
+fi
+rm -f /tmp/template.channel.json /tmp/template.channel.meta.json
+
+# Private sources: the bearer token comes from .env, and a rejected token fails with a clear 403
+cat << 'EOF' > /tmp/auth-server.py
+import http.server
+class Handler(http.server.BaseHTTPRequestHandler):
+    def do_GET(self):
+        auth = self.headers.get("Authorization")
+        if auth != "Bearer ci-template-token":
+            self.send_response(401 if auth is None else 403)
+            self.end_headers()
+            return
+        body = b'{"inbounds": [], "outbounds": [{"type": "direct", "tag": "private"}]}'
+        self.send_response(200)
+        self.send_header("Content-Length", str(len(body)))
+        self.end_headers()
+        self.wfile.write(body)
+http.server.HTTPServer(("127.0.0.1", 8081), Handler).serve_forever()
+EOF
+python3 /tmp/auth-server.py &
+AUTH_PID=$!
+sleep 1
+cat << 'EOF' >> "$WORKSPACE/etc/sbc.toml"
+[[update.auth]]
+prefix = "http://127.0.0.1:8081/"
+bearer = "CI_TEMPLATE_TOKEN"
+EOF
+echo "CI_TEMPLATE_TOKEN=ci-template-token" > "$WORKSPACE/.env"
+"$SBC_BIN" update --template-url http://127.0.0.1:8081/config.template.json \
+    --template-path /tmp/template.private.json \
+    --allow-unsigned --keep-history 0 2> /tmp/auth.log || { cat /tmp/auth.log; kill $SERVER_PID $AUTH_PID; exit 1; }
+echo "CI_TEMPLATE_TOKEN=wrong-token" > "$WORKSPACE/.env"
+"$SBC_BIN" update --template-url http://127.0.0.1:8081/config.template.json \
+    --template-path /tmp/template.denied.json \
+    --allow-unsigned --keep-history 0 2> /tmp/auth.log && { kill $SERVER_PID $AUTH_PID; exit 1; }
+kill $AUTH_PID
+if grep -q '"private"' /tmp/template.private.json && grep -q "HTTP 403: the server rejected the credentials" /tmp/auth.log \
+    && ! grep -q "wrong-token" /tmp/auth.log; then
+    echo "✅ Authenticated source validation passed."
+else
+    echo "❌ Authenticated source validation failed."
+    cat /tmp/auth.log
+    kill $SERVER_PID
+    exit 1
+fi
+rm -f /tmp/auth-server.py /tmp/auth.log /tmp/template.private.json /tmp/template.private.meta.json "$WORKSPACE/.env"
+
+# Template signatures: a minisign signature (prehashed "ED") produced with the openssl CLI is accepted,
+# a tampered template is refused, and without a pinned key only update.allow_unpinned lets the update through
+mkdir -p /tmp/sig
+openssl genpkey -algorithm ed25519 -out /tmp/sig/ci.key 2> /dev/null
+{ printf 'Ed\001\002\003\004\005\006\007\010'; openssl pkey -in /tmp/sig/ci.key -pubout -outform DER | tail -c 32; } | base64 -w0 > /tmp/sig/template.pub
+minisign_sign() {
+    openssl dgst -blake2b512 -binary "$1" > /tmp/sig/hash
+    openssl pkeyutl -sign -rawin -inkey /tmp/sig/ci.key -in /tmp/sig/hash -out /tmp/sig/sig
+    printf 'timestamp:0\tfile:ci' > /tmp/sig/trusted
+    cat /tmp/sig/sig /tmp/sig/trusted > /tmp/sig/global.in
+    openssl pkeyutl -sign -rawin -inkey /tmp/sig/ci.key -in /tmp/sig/global.in -out /tmp/sig/global
+    {
+        echo "untrusted comment: ci signature"
+        { printf 'ED\001\002\003\004\005\006\007\010'; cat /tmp/sig/sig; } | base64 -w0; echo
+        echo "trusted comment: $(cat /tmp/sig/trusted)"
+        base64 -w0 /tmp/sig/global; echo
+    } > "$1.minisig"
+}
+echo '{"inbounds": [], "outbounds": [{"type": "direct", "tag": "signed"}]}' > /tmp/www/signed.json
+minisign_sign /tmp/www/signed.json
+echo '{"inbounds": [], "outbounds": [{"type": "direct", "tag": "tampered"}]}' > /tmp/www/tampered.json
+cp /tmp/www/signed.json.minisig /tmp/www/tampered.json.minisig
+"$SBC_BIN" update --template-url http://localhost:8080/signed.json --template-path /tmp/template.signed.json \
+    --pubkey /tmp/sig/template.pub --keep-history 0 2> /tmp/sig/signed.log || { cat /tmp/sig/signed.log; kill $SERVER_PID; exit 1; }
+cp /tmp/template.signed.json /tmp/template.tampered.json
+set +e
+"$SBC_BIN" update --template-url http://localhost:8080/tampered.json --template-path /tmp/template.tampered.json \
+    --pubkey /tmp/sig/template.pub --keep-history 0 2> /tmp/sig/tampered.log
+TAMPERED_RC=$?
+"$SBC_BIN" update --template-url http://localhost:8080/signed.json --template-path /tmp/template.unpinned.json \
+    --pubkey /tmp/sig/missing.pub --keep-history 0 2> /tmp/sig/unpinned.log
+UNPINNED_RC=$?
+# The shipped defaults: etc/template.pub is enforced (a template signed by another key is refused),
+# and without it allow_unpinned = false refuses the update as well
+mkdir -p /tmp/sig/ws
+cp -r "$WORKSPACE_ROOT/etc" /tmp/sig/ws/
+WORKSPACE=/tmp/sig/ws "$SBC_BIN" update --template-url http://localhost:8080/signed.json --template-path /tmp/template.shipped.json \
+    --keep-history 0 2> /tmp/sig/shipped.log
+SHIPPED_RC=$?
+rm /tmp/sig/ws/etc/template.pub
+WORKSPACE=/tmp/sig/ws "$SBC_BIN" update --template-url http://localhost:8080/signed.json --template-path /tmp/template.shipped.json \
+    --keep-history 0 2> /tmp/sig/shipped.unpinned.log
+SHIPPED_UNPINNED_RC=$?
+set -e
+cp "$WORKSPACE/etc/sbc.toml" /tmp/sbc.toml.bak
+sed -i '/^\[update\]$/a allow_unpinned = true' "$WORKSPACE/etc/sbc.toml"
+"$SBC_BIN" update --template-url http://localhost:8080/signed.json --template-path /tmp/template.optin.json \
+    --pubkey /tmp/sig/missing.pub --keep-history 0 2> /tmp/sig/optin.log || { cat /tmp/sig/optin.log; kill $SERVER_PID; exit 1; }
+mv /tmp/sbc.toml.bak "$WORKSPACE/etc/sbc.toml"
+if grep -q "Template signature verified" /tmp/sig/signed.log && grep -q '"signed"' /tmp/template.signed.json \
+    && [ "$TAMPERED_RC" -ne 0 ] && grep -q "Refusing unsigned or tampered template" /tmp/sig/tampered.log \
+    && grep -q '"signed"' /tmp/template.tampered.json \
+    && [ "$UNPINNED_RC" -ne 0 ] && grep -q "update.allow_unpinned" /tmp/sig/unpinned.log && [ ! -f /tmp/template.unpinned.json ] \
+    && grep -q "skipping template signature verification (update.allow_unpinned)" /tmp/sig/optin.log \
+    && [ "$SHIPPED_RC" -ne 0 ] && grep -q "Refusing unsigned or tampered template" /tmp/sig/shipped.log \
+    && [ "$SHIPPED_UNPINNED_RC" -ne 0 ] && grep -q "No public key pinned" /tmp/sig/shipped.unpinned.log \
+    && [ ! -f /tmp/template.shipped.json ]; then
+    echo "✅ Template signature validation passed."
+else
+    echo "❌ Template signature validation failed ($TAMPERED_RC / $UNPINNED_RC / $SHIPPED_RC / $SHIPPED_UNPINNED_RC)."
+    cat /tmp/sig/*.log
+    kill $SERVER_PID
+    exit 1
+fi
+rm -rf /tmp/sig /tmp/www/signed.json* /tmp/www/tampered.json* /tmp/template.signed* /tmp/template.tampered* /tmp/template.optin*
+
+# Log redaction: secret env values (*_TOKEN, SUB_URL_*) and URL query values are masked; a "?" before a URL is not a query
+set +e
+SUB_URL_9="http://localhost:8080/ci-sub-path" CI_REDACT_TOKEN="ci-token-value" "$SBC_BIN" update \
+    --template-url "http://localhost:8080/ci-sub-path/x.json" --template-url "http://localhost:8080/ci-token-value.json?key=ci-query-value" \
+    --template-path /tmp/template.redact.json --allow-unsigned --keep-history 0 --retries 1 > /tmp/redact.log 2>&1
+"$SBC_BIN" render --template "/tmp/why?a=b http://localhost/p?k=ci-query-value" --output /tmp/config.redact.json >> /tmp/redact.log 2>&1
+"$SBC_BIN" --no-redact update --template-url "http://localhost:8080/missing.json?key=ci-plain-value" \
+    --template-path /tmp/template.redact.json --allow-unsigned --keep-history 0 --retries 1 > /tmp/redact.plain.log 2>&1
+set -e
+if grep -q "localhost:8080/ci-token-value.json" /tmp/redact.log || grep -q "ci-sub-path" /tmp/redact.log \
+    || grep -q "ci-query-value" /tmp/redact.log || ! grep -q "key=\*\*\*\*\*\*" /tmp/redact.log \
+    || ! grep -q "/tmp/why?a=b http://localhost/p?k=\*\*\*\*\*\*" /tmp/redact.log || ! grep -q "ci-plain-value" /tmp/redact.plain.log; then
+    echo "❌ Log redaction validation failed."
+    cat /tmp/redact.log /tmp/redact.plain.log
+    kill $SERVER_PID
+    exit 1
+fi
+echo "✅ Log redaction validation passed."
+rm -f /tmp/redact.log /tmp/redact.plain.log /tmp/template.redact.json
+
+# Restart only on effective changes: reordered keys keep the rendered config, a new outbound does not
+echo '{"log": {"level": "info"}, "inbounds": [], "outbounds": [{"type": "direct", "tag": "a"}]}' > /tmp/www/effective-1.json
+"$SBC_BIN" update --template-url http://localhost:8080/effective-1.json \
+    --template-path /tmp/template.effective.json \
+    --allow-unsigned --keep-history 0 || { kill $SERVER_PID; exit 1; }
+"$SBC_BIN" render --template /tmp/template.effective.json --output /tmp/config.effective.json
+echo '{"outbounds": [{"tag": "a", "type": "direct"}], "inbounds": [], "log": {"level": "info"}}' > /tmp/www/effective-2.json
+set +e
+"$SBC_BIN" update --template-url http://localhost:8080/effective-2.json \
+    --template-path /tmp/template.effective.json --config /tmp/config.effective.json \
+    --allow-unsigned --keep-history 0 --json > /tmp/effective.unchanged.json
+UNCHANGED_RC=$?
+echo '{"outbounds": [{"tag": "b", "type": "direct"}], "inbounds": [], "log": {"level": "info"}}' > /tmp/www/effective-3.json
+"$SBC_BIN" update --template-url http://localhost:8080/effective-3.json \
+    --template-path /tmp/template.effective.json --config /tmp/config.effective.json \
+    --allow-unsigned --keep-history 0 --json > /tmp/effective.changed.json
+CHANGED_RC=$?
+set -e
+if [ "$UNCHANGED_RC" -eq 3 ] && grep -q '"status": "unchanged"' /tmp/effective.unchanged.json \
+    && [ "$CHANGED_RC" -eq 0 ] && grep -q '"status": "changed"' /tmp/effective.changed.json; then
+    echo "✅ Effective config comparison validation passed."
+else
+    echo "❌ Effective config comparison validation failed ($UNCHANGED_RC / $CHANGED_RC)."
+    cat /tmp/effective.unchanged.json /tmp/effective.changed.json
+    kill $SERVER_PID
+    exit 1
+fi
+
+# A template whose effective config fails to render is taken back: the previous template and .env.example stay
+printf 'if config.outbounds.some(|ob| ob.tag == "boom") { throw "boom outbound"; }\n' > "$WORKSPACE/etc/render.rhai"
+echo '{"outbounds": [{"tag": "boom", "type": "direct"}], "inbounds": [], "log": {"level": "info"}}' > /tmp/www/effective-4.json
+printf 'CI_RESTORE=old\n' > /tmp/env.effective.example
+printf 'CI_RESTORE=new\n' > /tmp/www/env.effective.example
+cp /tmp/template.effective.json /tmp/template.effective.before.json
+set +e
+"$SBC_BIN" update --template-url http://localhost:8080/effective-4.json \
+    --template-path /tmp/template.effective.json --config /tmp/config.effective.json \
+    --env-url http://localhost:8080/env.effective.example --env-path /tmp/env.effective.example \
+    --allow-unsigned --keep-history 0 --json > /tmp/effective.restored.json 2> /tmp/effective.restored.log
+RESTORED_RC=$?
+set -e
+rm -f "$WORKSPACE/etc/render.rhai"
+if [ "$RESTORED_RC" -ne 0 ] && grep -q '"status": "failed"' /tmp/effective.restored.json \
+    && grep -q '"template_updated": false' /tmp/effective.restored.json \
+    && cmp -s /tmp/template.effective.json /tmp/template.effective.before.json \
+    && grep -q "CI_RESTORE=old" /tmp/env.effective.example && grep -q "Restored the previous template" /tmp/effective.restored.log; then
+    echo "✅ Failed update restore validation passed."
+else
+    echo "❌ Failed update restore validation failed ($RESTORED_RC)."
+    cat /tmp/effective.restored.json /tmp/effective.restored.log
+    kill $SERVER_PID
+    exit 1
+fi
+rm -f /tmp/template.effective.json /tmp/template.effective.before.json /tmp/template.effective.meta.json /tmp/config.effective.json \
+    /tmp/effective.*.json /tmp/effective.restored.log /tmp/env.effective.example /tmp/www/env.effective.example
+
+# Rule set cache: sync downloads remote rule sets, render rewrites them to local paths (a stale copy is kept with a warning
+# under the default stale = "local"), a corrupt cache falls back to the URL
+printf 'SRS\001ci-rule-set' > /tmp/www/geosite-ci.srs
+cat << 'EOF' > /tmp/template.ruleset.json
+{"inbounds": [], "outbounds": [], "route": {"rule_set": [
+    {"type": "remote", "tag": "geosite-ci", "format": "binary", "url": "http://localhost:8080/geosite-ci.srs", "download_detour": "direct"}
+]}}
+EOF
+"$SBC_BIN" ruleset sync --template /tmp/template.ruleset.json --json > /tmp/ruleset.sync.json || { kill $SERVER_PID; exit 1; }
+cp "$WORKSPACE/etc/sbc.toml" /tmp/sbc.toml.bak
+printf '[ruleset]\nlocal = true\n' >> "$WORKSPACE/etc/sbc.toml"
+"$SBC_BIN" render --template /tmp/template.ruleset.json --output /tmp/config.ruleset.json
+CACHED=$(ls "$WORKSPACE"/var/lib/rulesets/geosite-ci-*.srs)
+echo 'max_age_hours = 0' >> "$WORKSPACE/etc/sbc.toml"
+"$SBC_BIN" render --template /tmp/template.ruleset.json --output /tmp/config.ruleset.stale.json 2> /tmp/ruleset.stale.log
+echo "corrupt" >> "$CACHED"
+"$SBC_BIN" render --template /tmp/template.ruleset.json --output /tmp/config.ruleset.remote.json 2> /tmp/ruleset.log
+mv /tmp/sbc.toml.bak "$WORKSPACE/etc/sbc.toml"
+if grep -q '"status": "downloaded"' /tmp/ruleset.sync.json \
+    && grep -q '"type": "local"' /tmp/config.ruleset.json && grep -q "\"path\": \"$CACHED\"" /tmp/config.ruleset.json \
+    && ! grep -q "download_detour" /tmp/config.ruleset.json \
+    && grep -q "\"path\": \"$CACHED\"" /tmp/config.ruleset.stale.json && grep -q "仍使用本地副本" /tmp/ruleset.stale.log \
+    && grep -q '"type": "remote"' /tmp/config.ruleset.remote.json && grep -q "校验失败" /tmp/ruleset.log; then
+    echo "✅ Rule set cache validation passed."
+else
+    echo "❌ Rule set cache validation failed."
+    cat /tmp/ruleset.sync.json /tmp/config.ruleset.json /tmp/config.ruleset.remote.json /tmp/ruleset.log /tmp/ruleset.stale.log
+    kill $SERVER_PID
+    exit 1
+fi
+rm -rf /tmp/template.ruleset.json /tmp/config.ruleset*.json /tmp/ruleset.* "$WORKSPACE/var/lib/rulesets"
+
+# Subscription gateway: providers are rewritten to the gateway, junk nodes are dropped, the cache covers a dead or slow upstream
+cat << 'EOF' > /tmp/www/sub.yaml
+proxies:
+  - {name: "剩余流量：10GB", type: ss, server: 1.1.1.1, port: 1, cipher: aes-128-gcm, password: x}
+  - {name: "香港 01", type: ss, server: hk.example.com, port: 443, cipher: aes-128-gcm, password: x}
+  - not-a-mapping
+EOF
+# Malformed upstream entries (a non-mapping proxy, a vmess payload that is a JSON string) are skipped, not fatal
+printf 'vmess://%s\nss://%s@ss.example.com:8388#%%E9%%A6%%99%%E6%%B8%%AF%%2002\n' "$(printf '"abc"' | base64 -w0)" "$(printf 'aes-128-gcm:x' | base64 -w0)" > /tmp/www/sub-links.txt
+cat << 'EOF' > /tmp/template.sub.json
+{"inbounds": [], "outbounds": [], "providers": [
+    {"tag": "ci", "type": "remote", "url": "http://localhost:8080/sub.yaml"},
+    {"tag": "ci-links", "type": "remote", "url": "http://localhost:8080/sub-links.txt"},
+    {"tag": "ci-slow", "type": "remote", "url": "http://127.0.0.1:8084/slow.txt"}
+]}
+EOF
+# Answers the first request at once and stalls every later one
+cat << 'EOF' > /tmp/slow-server.py
+import http.server, time
+class Handler(http.server.BaseHTTPRequestHandler):
+    served = 0
+    def do_GET(self):
+        if Handler.served:
+            time.sleep(20)
+        Handler.served += 1
+        body = open("/tmp/www/sub-links.txt", "rb").read()
+        self.send_response(200)
+        self.send_header("Content-Length", str(len(body)))
+        self.end_headers()
+        self.wfile.write(body)
+http.server.ThreadingHTTPServer(("127.0.0.1", 8084), Handler).serve_forever()
+EOF
+python3 /tmp/slow-server.py &
+SLOW_PID=$!
+cp "$WORKSPACE/etc/sbc.toml" /tmp/sbc.toml.bak
+cat << 'EOF' >> "$WORKSPACE/etc/sbc.toml"
+[subscription]
+gateway = true
+listen = "127.0.0.1:9099"
+[[subscription.rename]]
+pattern = "^香港"
+replace = "HK"
+EOF
+"$SBC_BIN" render --template /tmp/template.sub.json --output /tmp/config.sub.json
+"$SBC_BIN" sub serve --retries 1 > /tmp/sub-serve.log 2>&1 &
+GATEWAY_PID=$!
+sleep 1
+SUB_URL=$(grep -o 'http://127.0.0.1:9099/sub/[0-9a-f]*' /tmp/config.sub.json | head -n 1)
+LINKS_URL=$(grep -o 'http://127.0.0.1:9099/sub/[0-9a-f]*' /tmp/config.sub.json | sed -n 2p)
+SLOW_URL=$(grep -o 'http://127.0.0.1:9099/sub/[0-9a-f]*' /tmp/config.sub.json | tail -n 1)
+curl -s -D /tmp/sub.headers "$SUB_URL" > /tmp/sub.upstream.yaml
+curl -s "$LINKS_URL" > /tmp/sub.links.txt
+curl -s "$SLOW_URL" > /dev/null
+SLOW_START=$(date +%s)
+curl -s -m 10 -D /tmp/sub.slow.headers "$SLOW_URL" > /tmp/sub.slow.txt
+SLOW_SECONDS=$(( $(date +%s) - SLOW_START ))
+kill $SLOW_PID
+rm /tmp/www/sub.yaml
+curl -s -D /tmp/sub.cache.headers "$SUB_URL" > /tmp/sub.cache.yaml
+kill $GATEWAY_PID
+mv /tmp/sbc.toml.bak "$WORKSPACE/etc/sbc.toml"
+if [ -n "$SUB_URL" ] && grep -q "name: HK 01" /tmp/sub.upstream.yaml && ! grep -q "剩余流量" /tmp/sub.upstream.yaml \
+    && grep -q "X-Sbc-Source: upstream" /tmp/sub.headers \
+    && grep -q "X-Sbc-Source: cache" /tmp/sub.cache.headers && grep -q "name: HK 01" /tmp/sub.cache.yaml \
+    && ! grep -q "not-a-mapping" /tmp/sub.upstream.yaml && grep -q "#HK%2002" /tmp/sub.links.txt \
+    && ! grep -q "vmess://" /tmp/sub.links.txt && ! grep -q "panicked" /tmp/sub-serve.log \
+    && [ "$SLOW_SECONDS" -lt 6 ] && grep -q "X-Sbc-Source: cache" /tmp/sub.slow.headers && grep -q "#HK%2002" /tmp/sub.slow.txt; then
+    echo "✅ Subscription gateway validation passed."
+else
+    echo "❌ Subscription gateway validation failed."
+    cat /tmp/config.sub.json /tmp/sub.upstream.yaml /tmp/sub.cache.yaml /tmp/sub.links.txt /tmp/sub-serve.log
+    kill $SERVER_PID
+    exit 1
+fi
+rm -rf /tmp/template.sub.json /tmp/config.sub.json /tmp/sub.* /tmp/sub-serve.log /tmp/slow-server.py /tmp/www/sub-links.txt "$WORKSPACE/var/lib/subscriptions"
+
+# Subscription conversion: base64 share links and Clash YAML become sing-box outbounds, unsupported input is reported per node
+VMESS=$(printf '{"v":"2","ps":"vm","add":"vm.example.com","port":"443","id":"11111111-2222-3333-4444-555555555555","aid":"0","net":"ws","host":"cdn.example.com","path":"/ws?ed=2048","tls":"tls"}' | base64 -w0)
+cat << EOF | base64 -w0 > /tmp/www/links.b64
+ss://$(printf 'aes-128-gcm:pass' | base64 -w0)@ss.example.com:8388#ss
+vmess://$VMESS
+vless://11111111-2222-3333-4444-555555555555@vl.example.com:443?security=reality&sni=www.apple.com&pbk=PUBKEY&sid=ab&flow=xtls-rprx-vision#reality
+hysteria2://auth@hy.example.com:443?obfs=salamander&obfs-password=x#hy2
+wireguard://key@wg.example.com:51820#wg
+EOF
+cat << 'EOF' > /tmp/convert.clash.yaml
+proxies:
+  - {name: c-trojan, type: trojan, server: t.example.com, port: 443, password: p, network: grpc, grpc-opts: {grpc-service-name: svc}, smux: {enabled: true, protocol: h2mux}, foo: bar}
+  - {name: c-tuic, type: tuic, server: tu.example.com, port: 443, uuid: 11111111-2222-3333-4444-555555555555, password: p, alpn: [h3]}
+EOF
+"$SBC_BIN" sub convert http://localhost:8080/links.b64 -o /tmp/convert.links.json 2> /tmp/convert.links.log
+"$SBC_BIN" sub convert < /tmp/convert.clash.yaml > /tmp/convert.clash.json 2> /tmp/convert.clash.log
+# Without --proxy the download goes through update.proxy, here an unreachable one
+cp "$WORKSPACE/etc/sbc.toml" /tmp/sbc.toml.bak
+sed -i '/^\[update\]$/a proxy = "http://127.0.0.1:9"' "$WORKSPACE/etc/sbc.toml"
+set +e
+"$SBC_BIN" sub convert http://localhost:8080/links.b64 --retries 1 -o /tmp/convert.proxied.json 2> /tmp/convert.proxied.log
+PROXIED_RC=$?
+set -e
+"$SBC_BIN" sub convert http://localhost:8080/links.b64 --proxy direct -o /tmp/convert.direct.json 2> /tmp/convert.direct.log
+mv /tmp/sbc.toml.bak "$WORKSPACE/etc/sbc.toml"
+if grep -q '"type": "shadowsocks"' /tmp/convert.links.json && grep -q '"max_early_data": 2048' /tmp/convert.links.json \
+    && grep -q '"public_key": "PUBKEY"' /tmp/convert.links.json && grep -q '"type": "salamander"' /tmp/convert.links.json \
+    && ! grep -q "wg.example.com" /tmp/convert.links.json && grep -q "wg: 已跳过" /tmp/convert.links.log \
+    && grep -q '"service_name": "svc"' /tmp/convert.clash.json && grep -q '"protocol": "h2mux"' /tmp/convert.clash.json \
+    && grep -q '"type": "tuic"' /tmp/convert.clash.json && grep -q "c-trojan: 忽略不支持的字段: foo" /tmp/convert.clash.log \
+    && [ "$PROXIED_RC" -ne 0 ] && [ ! -f /tmp/convert.proxied.json ] && grep -q '"type": "shadowsocks"' /tmp/convert.direct.json; then
+    echo "✅ Subscription conversion validation passed."
+else
+    echo "❌ Subscription conversion validation failed."
+    cat /tmp/convert.links.json /tmp/convert.links.log /tmp/convert.clash.json /tmp/convert.clash.log /tmp/convert.proxied.log
+    kill $SERVER_PID
+    exit 1
+fi
+rm -f /tmp/www/links.b64 /tmp/convert.*
+
+# Subscription check: status, node count and Subscription-Userinfo, with warnings below the thresholds; a dead subscription fails the command
+cat << 'EOF' > /tmp/sub-check-server.py
+import http.server, time
+class Handler(http.server.BaseHTTPRequestHandler):
+    def do_GET(self):
+        if self.path.startswith("/low"):
+            userinfo = "upload=1073741824; download=9663676416; total=12884901888; expire=%d" % (time.time() + 2 * 86400 + 600)
+        elif self.path.startswith("/huge"):
+            # Out-of-range values must not overflow
+            userinfo = "upload=18446744073709551615; download=10; total=100; expire=1E400"
+        else:
+            self.send_response(404)
+            self.end_headers()
+            return
+        body = b"proxies:\n  - {name: hk, type: ss, server: hk.example.com, port: 443, cipher: aes-128-gcm, password: x}\n"
+        self.send_response(200)
+        self.send_header("Subscription-Userinfo", userinfo)
+        self.send_header("Content-Length", str(len(body)))
+        self.end_headers()
+        self.wfile.write(body)
+http.server.HTTPServer(("127.0.0.1", 8082), Handler).serve_forever()
+EOF
+python3 /tmp/sub-check-server.py &
+CHECK_PID=$!
+sleep 1
+printf 'SUB_URL_1=http://127.0.0.1:8082/low?token=ci-sub-token\nPROVIDER_NAME_1=ci-low\nSUB_URL_2=http://127.0.0.1:8082/gone\nSUB_URL_3=\nSUB_URL_4=http://127.0.0.1:8082/huge\n' > /tmp/sub-check.env
+set +e
+"$SBC_BIN" sub check --env-file /tmp/sub-check.env --retries 1 --json > /tmp/sub-check.json 2> /tmp/sub-check.log
+CHECK_RC=$?
+# Without --proxy the requests go through update.proxy, here an unreachable one
+cp "$WORKSPACE/etc/sbc.toml" /tmp/sbc.toml.bak
+sed -i '/^\[update\]$/a proxy = "http://127.0.0.1:9"' "$WORKSPACE/etc/sbc.toml"
+printf 'SUB_URL_1=http://127.0.0.1:8082/low\n' > /tmp/sub-check.proxied.env
+"$SBC_BIN" sub check --env-file /tmp/sub-check.proxied.env --retries 1 --json > /tmp/sub-check.proxied.json 2>> /tmp/sub-check.log
+PROXIED_RC=$?
+mv /tmp/sbc.toml.bak "$WORKSPACE/etc/sbc.toml"
+set -e
+kill $CHECK_PID
+if [ "$CHECK_RC" -ne 0 ] && grep -q '"status": 404' /tmp/sub-check.json && grep -q '"nodes": 1' /tmp/sub-check.json \
+    && grep -q '"remaining": 2147483648' /tmp/sub-check.json && grep -q '"days_left": 2' /tmp/sub-check.json \
+    && grep -q "ci-low: 剩余流量 2.00 GB，低于 10 GB" /tmp/sub-check.log && grep -q "ci-low: 套餐将于" /tmp/sub-check.log \
+    && grep -q '"remaining": 0' /tmp/sub-check.json && grep -q "SUB_URL_4: 流量已用尽" /tmp/sub-check.log \
+    && ! grep -q "panicked" /tmp/sub-check.log && ! grep -q "ci-sub-token" /tmp/sub-check.json /tmp/sub-check.log \
+    && [ "$PROXIED_RC" -ne 0 ] && grep -q '"error"' /tmp/sub-check.proxied.json && ! grep -q '"status"' /tmp/sub-check.proxied.json; then
+    echo "✅ Subscription check validation passed."
+else
+    echo "❌ Subscription check validation failed ($CHECK_RC / $PROXIED_RC)."
+    cat /tmp/sub-check.json /tmp/sub-check.proxied.json /tmp/sub-check.log
+    kill $SERVER_PID
+    exit 1
+fi
+rm -f /tmp/sub-check-server.py /tmp/sub-check.env /tmp/sub-check.json /tmp/sub-check.log /tmp/sub-check.proxied.*
+
 kill $SERVER_PID
 
 if grep -q "inbounds" "$TEMPLATE_PATH.new"; then
@@ -104,7 +930,7 @@ export SBC_PID_FILE="/tmp/sing-box.pid"
 DAEMON_PID=$!
 sleep 2
 
-if grep -q "Started sing-box supervisor" /tmp/daemon.log || grep -q "sing-box started with PID" /tmp/daemon.log; then
+if grep -q "sing-box 已启动" /tmp/daemon.log; then
     echo "✅ Daemon started."
 else
     echo "❌ Daemon failed to start."
@@ -127,9 +953,88 @@ else
     echo "✅ Daemon exited gracefully."
 fi
 
+# A supervisor that ignores SIGTERM is killed, so a restart never runs next to the old instance
+sh -c 'trap "" TERM; while true; do sleep 1; done' &
+STUBBORN_PID=$!
+echo $STUBBORN_PID > "$SBC_PID_FILE"
+"$SBC_BIN" stop 2> /tmp/stop.stubborn.log
+sleep 0.5
+if ! kill -0 $STUBBORN_PID 2>/dev/null && [ ! -f "$SBC_PID_FILE" ] && grep -q "SIGKILL" /tmp/stop.stubborn.log; then
+    echo "✅ Stubborn supervisor was killed."
+else
+    echo "❌ Stubborn supervisor validation failed."
+    cat /tmp/stop.stubborn.log
+    kill -9 $STUBBORN_PID
+    exit 1
+fi
+rm -f /tmp/stop.stubborn.log
+
 # Clean up
 rm /tmp/sing-box
 
+# 8. Test 'self-update' (binaries from a signed update.json manifest, service not running)
+echo "Testing self-update..."
+mkdir -p /tmp/sig
+openssl genpkey -algorithm ed25519 -out /tmp/sig/ci.key 2> /dev/null
+{ printf 'Ed\001\002\003\004\005\006\007\010'; openssl pkey -in /tmp/sig/ci.key -pubout -outform DER | tail -c 32; } | base64 -w0 > "$WORKSPACE/etc/template.pub"
+mkdir -p "$WORKSPACE/bin" /tmp/www-self
+printf '#!/bin/sh\n# v1\nexit 0\n' > "$WORKSPACE/bin/sing-box"
+printf '#!/bin/sh\n# v2\nexit 0\n' > /tmp/www-self/sing-box
+chmod +x "$WORKSPACE/bin/sing-box"
+printf 'id=sing-box-ksu-module\nversion=v1\nversionCode=100\n' > "$WORKSPACE/module.prop"
+cat << EOF >> "$WORKSPACE/etc/sbc.toml"
+[self_update]
+module_prop = "$WORKSPACE/module.prop"
+manifest_url = "http://localhost:8080/update.json?t=200"
+EOF
+write_manifest() {
+    echo "{\"version\": \"v2\", \"versionCode\": 200, \"binaries\": {\"sing-box\": {\"url\": \"http://localhost:8080/sing-box\", \"sha256\": \"$1\"}}}" > /tmp/www-self/update.json
+    minisign_sign /tmp/www-self/update.json
+}
+cd /tmp/www-self
+python3 -m http.server 8080 &
+SERVER_PID=$!
+cd -
+sleep 1
+
+# A checksum mismatch must leave the installed binary alone
+write_manifest "0000000000000000000000000000000000000000000000000000000000000000"
+if "$SBC_BIN" self-update 2> /tmp/self-update.log || ! grep -q "# v1" "$WORKSPACE/bin/sing-box"; then
+    echo "❌ self-update accepted a binary with a wrong checksum."
+    cat /tmp/self-update.log
+    kill $SERVER_PID
+    exit 1
+fi
+# So must a manifest that does not match its signature, even with correct checksums
+cp /tmp/www-self/update.json.minisig /tmp/sig/stale.minisig
+write_manifest "$(sha256sum /tmp/www-self/sing-box | cut -d' ' -f1)"
+mv /tmp/www-self/update.json.minisig /tmp/sig/update.json.minisig
+cp /tmp/sig/stale.minisig /tmp/www-self/update.json.minisig
+if "$SBC_BIN" self-update 2> /tmp/self-update.log || ! grep -q "Refusing unsigned or tampered update manifest" /tmp/self-update.log \
+    || ! grep -q "# v1" "$WORKSPACE/bin/sing-box"; then
+    echo "❌ self-update accepted a tampered manifest."
+    cat /tmp/self-update.log
+    kill $SERVER_PID
+    exit 1
+fi
+mv /tmp/sig/update.json.minisig /tmp/www-self/update.json.minisig
+"$SBC_BIN" self-update --check | grep -q "sing-box" || { kill $SERVER_PID; exit 1; }
+"$SBC_BIN" self-update || { kill $SERVER_PID; exit 1; }
+set +e
+"$SBC_BIN" self-update
+SELF_RC=$?
+set -e
+kill $SERVER_PID
+if grep -q "# v2" "$WORKSPACE/bin/sing-box" && grep -q "versionCode=200" "$WORKSPACE/module.prop" && [ "$SELF_RC" -eq 3 ] \
+    && [ ! -e "$WORKSPACE/bin/sing-box.old" ]; then
+    echo "✅ self-update validation passed."
+else
+    echo "❌ self-update validation failed (second run exit $SELF_RC)."
+    ls "$WORKSPACE/bin"
+    exit 1
+fi
+rm -rf /tmp/www-self /tmp/self-update.log /tmp/sig "$WORKSPACE/etc/template.pub"
+
 echo "Validating Output with sing-box check..."
 if command -v sing-box &> /dev/null; then
     # sing-box check needs the rulesets referenced in config to exist? 
@@ -144,3 +1049,4 @@ fi
 
 echo "=== CI Validation PASSED ==="
 rm -f "$OUTPUT_PATH"
+rm -rf "$WORKSPACE"